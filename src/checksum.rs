@@ -0,0 +1,97 @@
+use crc::crc32;
+
+use parser::message::MAGIC_POSITION;
+use storage::segment::ENTRY_HEADER_SIZE;
+
+// legacy messages (magic 0 and 1) start with a CRC-32 (IEEE) of the rest of
+// the message. Record batches (magic 2) hold a CRC-32C of everything after
+// it, after the partition leader epoch and the magic byte
+const MESSAGE_CRC_POSITION: usize = ENTRY_HEADER_SIZE;
+const BATCH_CRC_POSITION: usize   = MAGIC_POSITION + 1;
+
+pub fn message_crc(message: &[u8]) -> u32 {
+  crc32::checksum_ieee(message)
+}
+
+pub fn record_batch_crc(batch: &[u8]) -> u32 {
+  crc32::checksum_castagnoli(batch)
+}
+
+fn read_u32(input: &[u8], position: usize) -> Option<u32> {
+  input.get(position..position + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// checks the entry (offset, size, then a message or a record batch) at the
+// start of `input`, and returns the bytes after it. None if the entry is
+// truncated or its checksum does not match
+pub fn check_entry(input: &[u8]) -> Option<&[u8]> {
+  let size = read_u32(input, 8).filter(|s| *s <= i32::MAX as u32)? as usize;
+  let entry = input.get(..ENTRY_HEADER_SIZE + size)?;
+
+  let valid = if entry.get(MAGIC_POSITION) == Some(&2) {
+    let body = entry.get(BATCH_CRC_POSITION + 4..)?;
+    read_u32(entry, BATCH_CRC_POSITION)? == record_batch_crc(body)
+  } else {
+    let body = entry.get(MESSAGE_CRC_POSITION + 4..)?;
+    read_u32(entry, MESSAGE_CRC_POSITION)? == message_crc(body)
+  };
+
+  if valid {
+    Some(&input[entry.len()..])
+  } else {
+    None
+  }
+}
+
+// checks every entry of a message set
+pub fn check_entries(mut input: &[u8]) -> bool {
+  while !input.is_empty() {
+    match check_entry(input) {
+      Some(rest) => input = rest,
+      None       => return false,
+    }
+  }
+  true
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parser::message::{Message,OMsMessage,RecordBatch};
+  use responses::fetch::{ser_message_set,ser_record_batch};
+
+  #[test]
+  fn check_entries_test() {
+    let mut input: Vec<u8> = vec![];
+    let message = Message { magic_byte: 1, attributes: 0, timestamp: Some(1000), key: Some(&b"k"[..]), value: Some(&b"v"[..]) };
+    ser_message_set(&vec![OMsMessage { offset: 0, message }], &mut input);
+    let messages_len = input.len();
+    ser_record_batch(&RecordBatch {
+      base_offset: 1,
+      partition_leader_epoch: 0,
+      attributes: 0,
+      last_offset_delta: 0,
+      first_timestamp: 0,
+      max_timestamp: 0,
+      producer_id: -1,
+      producer_epoch: -1,
+      base_sequence: -1,
+      record_count: 1,
+      records: &[0x0e, 0x00, 0x00, 0x00, 0x01, 0x02, 0x61, 0x00][..]
+    }, &mut input);
+
+    assert!(check_entries(&input));
+    assert_eq!(check_entry(&input), Some(&input[messages_len..]));
+    assert!(check_entries(&[]));
+
+    // every byte after the CRC is covered
+    for position in &[ENTRY_HEADER_SIZE + 4, messages_len - 1, messages_len + BATCH_CRC_POSITION + 4, input.len() - 1] {
+      let mut corrupted = input.clone();
+      corrupted[*position] ^= 1;
+      assert!(!check_entries(&corrupted), "position {}", position);
+    }
+
+    assert!(!check_entries(&input[..input.len() - 1]));
+    assert!(!check_entries(&input[..8]));
+  }
+}
@@ -0,0 +1,153 @@
+use std::io::{self,Read,Write};
+
+use flate2;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use snap;
+use lz4_flex::frame::{FrameDecoder,FrameEncoder,FrameInfo,BlockMode};
+use ruzstd::decoding::StreamingDecoder;
+use ruzstd::encoding::{compress_to_vec,CompressionLevel};
+
+// codecs of the compression bits in message and record batch attributes
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Compression {
+  None,
+  Gzip,
+  Snappy,
+  Lz4,
+  Zstd,
+}
+
+// java clients frame snappy blocks in the format of snappy-java
+const SNAPPY_JAVA_MAGIC: &[u8] = &[0x82, b'S', b'N', b'A', b'P', b'P', b'Y', 0];
+const SNAPPY_JAVA_HEADER_SIZE: usize = 16;
+const SNAPPY_JAVA_BLOCK_SIZE: usize = 32 * 1024;
+
+impl Compression {
+  pub fn from_id(id: i16) -> Option<Compression> {
+    match id {
+      0 => Some(Compression::None),
+      1 => Some(Compression::Gzip),
+      2 => Some(Compression::Snappy),
+      3 => Some(Compression::Lz4),
+      4 => Some(Compression::Zstd),
+      _ => None,
+    }
+  }
+
+  pub fn id(self) -> i16 {
+    match self {
+      Compression::None   => 0,
+      Compression::Gzip   => 1,
+      Compression::Snappy => 2,
+      Compression::Lz4    => 3,
+      Compression::Zstd   => 4,
+    }
+  }
+
+  // name used by the compression.type setting
+  pub fn from_name(name: &str) -> Option<Compression> {
+    match name {
+      "uncompressed" => Some(Compression::None),
+      "gzip"         => Some(Compression::Gzip),
+      "snappy"       => Some(Compression::Snappy),
+      "lz4"          => Some(Compression::Lz4),
+      "zstd"         => Some(Compression::Zstd),
+      _              => None,
+    }
+  }
+
+  pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+    match self {
+      Compression::None   => Ok(data.to_vec()),
+      Compression::Gzip   => {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data)?;
+        encoder.finish()
+      },
+      Compression::Snappy => {
+        let mut output = SNAPPY_JAVA_MAGIC.to_vec();
+        // version and minimum compatible version
+        output.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1]);
+        let mut encoder = snap::raw::Encoder::new();
+        for block in data.chunks(SNAPPY_JAVA_BLOCK_SIZE) {
+          let compressed = encoder.compress_vec(block).map_err(io::Error::other)?;
+          output.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+          output.extend(compressed);
+        }
+        Ok(output)
+      },
+      Compression::Lz4    => {
+        let info = FrameInfo::new().block_mode(BlockMode::Independent);
+        let mut encoder = FrameEncoder::with_frame_info(info, Vec::new());
+        encoder.write_all(data)?;
+        encoder.finish().map_err(io::Error::other)
+      },
+      Compression::Zstd   => Ok(compress_to_vec(data, CompressionLevel::Fastest)),
+    }
+  }
+
+  pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    match self {
+      Compression::None   => output.extend_from_slice(data),
+      Compression::Gzip   => {
+        GzDecoder::new(data).read_to_end(&mut output)?;
+      },
+      Compression::Snappy => {
+        let mut decoder = snap::raw::Decoder::new();
+        if !data.starts_with(SNAPPY_JAVA_MAGIC) {
+          return decoder.decompress_vec(data).map_err(io::Error::other);
+        }
+
+        let mut blocks = data.get(SNAPPY_JAVA_HEADER_SIZE..).unwrap_or(&[]);
+        while !blocks.is_empty() {
+          let truncated = || io::Error::new(io::ErrorKind::InvalidData, "truncated snappy block");
+          let size = blocks.get(..4).ok_or_else(truncated)?;
+          let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+          let block = blocks.get(4..4 + size).ok_or_else(truncated)?;
+          output.extend(decoder.decompress_vec(block).map_err(io::Error::other)?);
+          blocks = &blocks[4 + size..];
+        }
+      },
+      Compression::Lz4    => {
+        FrameDecoder::new(data).read_to_end(&mut output)?;
+      },
+      Compression::Zstd   => {
+        let mut input = data;
+        let mut decoder = StreamingDecoder::new(&mut input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        decoder.read_to_end(&mut output)?;
+      },
+    }
+    Ok(output)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trip_test() {
+    let data: Vec<u8> = (0..100000).map(|i| (i % 251) as u8).collect();
+    for id in 0..5 {
+      let codec = Compression::from_id(id).unwrap();
+      assert_eq!(codec.id(), id);
+      let compressed = codec.compress(&data).unwrap();
+      assert_eq!(codec.decompress(&compressed).unwrap(), data, "{:?}", codec);
+    }
+    assert_eq!(Compression::from_id(5), None);
+  }
+
+  #[test]
+  fn snappy_test() {
+    // blocks are framed like snappy-java does, and raw blocks are accepted
+    let compressed = Compression::Snappy.compress(b"hello").unwrap();
+    assert!(compressed.starts_with(SNAPPY_JAVA_MAGIC));
+    let raw = snap::raw::Encoder::new().compress_vec(b"hello").unwrap();
+    assert_eq!(Compression::Snappy.decompress(&raw).unwrap(), b"hello");
+
+    assert!(Compression::Snappy.decompress(&compressed[..compressed.len() - 1]).is_err());
+    assert!(Compression::Gzip.decompress(b"not gzip").is_err());
+  }
+}
@@ -0,0 +1,382 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self,Read};
+use std::net::SocketAddr;
+use std::path::{Path,PathBuf};
+
+use compression::Compression;
+use network::frame::DEFAULT_MAX_REQUEST_SIZE;
+use proust::{DEFAULT_NUM_PARTITIONS,TimestampType};
+use storage::log::DEFAULT_SEGMENT_SIZE;
+use topics::Node;
+
+pub const USAGE: &str = "Usage: proust [options]
+
+Options:
+  -c, --config FILE          read the broker settings from a properties file
+  -o, --override KEY=VALUE   override a setting of the configuration file
+  -h, --help                 print this message
+
+Settings:
+  listeners                  comma separated addresses to listen on, like PLAINTEXT://127.0.0.1:9092
+  advertised.host.name       host announced to clients, defaults to the first listener address
+  advertised.port            port announced to clients, defaults to the first listener port
+  broker.id                  id of this broker
+  log.dirs                   directory storing the partition logs, only one is supported
+  log.segment.bytes          size after which a new log segment is started
+  log.retention.hours        age after which log segments are deleted, -1 to keep them
+  log.message.timestamp.type CreateTime or LogAppendTime, to replace producer timestamps
+  compression.type           producer, uncompressed, gzip, snappy, lz4 or zstd
+  check.crcs                 false to skip the CRC check of produced messages
+  socket.request.max.bytes   size over which requests are refused and the connection closed
+  num.network.threads        threads reading requests and writing responses
+  num.io.threads             threads handling requests
+  num.partitions             partitions of automatically created topics
+  auto.create.topics.enable  create unknown topics on first use";
+
+#[derive(Debug)]
+pub enum ConfigError {
+  Io(PathBuf, io::Error),
+  Usage(String),
+  Syntax(String, String),
+  UnknownKey(String, String),
+  InvalidValue(String, String, String),
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      ConfigError::Io(ref path, ref e)                      => write!(f, "could not read {}: {}", path.display(), e),
+      ConfigError::Usage(ref message)                       => write!(f, "{}", message),
+      ConfigError::Syntax(ref origin, ref line)             => write!(f, "{}: expected KEY=VALUE, got {:?}", origin, line),
+      ConfigError::UnknownKey(ref origin, ref key)          => write!(f, "{}: unknown setting {:?}", origin, key),
+      ConfigError::InvalidValue(ref origin, ref key, ref reason) => write!(f, "{}: invalid value for {}: {}", origin, key, reason),
+    }
+  }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Config {
+  // not empty, the first one is announced to clients
+  pub listeners:          Vec<SocketAddr>,
+  pub advertised_host:    Option<String>,
+  pub advertised_port:    Option<i32>,
+  pub broker_id:          i32,
+  pub log_dir:            PathBuf,
+  pub segment_bytes:      usize,
+  // -1 keeps segments forever
+  pub retention_hours:    i64,
+  pub timestamp_type:     TimestampType,
+  // None keeps the codec chosen by the producer
+  pub compression:        Option<Compression>,
+  pub check_crcs:         bool,
+  pub max_request_size:   usize,
+  pub network_threads:    usize,
+  pub io_threads:         usize,
+  pub num_partitions:     i32,
+  pub auto_create_topics: bool,
+}
+
+impl Default for Config {
+  fn default() -> Config {
+    Config {
+      listeners:          vec!["127.0.0.1:9092".parse().expect("valid default listener")],
+      advertised_host:    None,
+      advertised_port:    None,
+      broker_id:          0,
+      log_dir:            PathBuf::from("data"),
+      segment_bytes:      DEFAULT_SEGMENT_SIZE,
+      retention_hours:    168,
+      timestamp_type:     TimestampType::CreateTime,
+      compression:        None,
+      check_crcs:         true,
+      max_request_size:   DEFAULT_MAX_REQUEST_SIZE,
+      network_threads:    3,
+      io_threads:         8,
+      num_partitions:     DEFAULT_NUM_PARTITIONS,
+      auto_create_topics: true,
+    }
+  }
+}
+
+impl Config {
+
+  // settings from the configuration file given on the command line, if any,
+  // then from the overrides, in order
+  pub fn from_args(args: &[String]) -> Result<Config, ConfigError> {
+    let mut file: Option<&str> = None;
+    let mut overrides: Vec<&str> = Vec::new();
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+      match &arg[..] {
+        "-c" | "--config" => {
+          file = Some(it.next().ok_or_else(|| ConfigError::Usage(format!("{} expects a file name", arg)))?);
+        },
+        "-o" | "--override" => {
+          overrides.push(it.next().ok_or_else(|| ConfigError::Usage(format!("{} expects KEY=VALUE", arg)))?);
+        },
+        _ => return Err(ConfigError::Usage(format!("unexpected argument {:?}\n\n{}", arg, USAGE))),
+      }
+    }
+
+    let mut config = match file {
+      Some(path) => Config::from_file(Path::new(path))?,
+      None       => Config::default(),
+    };
+
+    for o in overrides {
+      let (key, value) = split_setting(o).ok_or_else(|| ConfigError::Syntax("--override".to_string(), o.to_string()))?;
+      config.set("--override", key, value)?;
+    }
+
+    Ok(config)
+  }
+
+  pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+    let mut content = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut content))
+      .map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+
+    Config::parse(&path.display().to_string(), &content)
+  }
+
+  // parses properties in the format of Kafka's server.properties:
+  // one KEY=VALUE per line, with '#' or '!' starting comments
+  pub fn parse(name: &str, content: &str) -> Result<Config, ConfigError> {
+    let mut config = Config::default();
+
+    for (i, line) in content.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+        continue;
+      }
+
+      let origin = format!("{}:{}", name, i + 1);
+      let (key, value) = split_setting(line).ok_or_else(|| ConfigError::Syntax(origin.clone(), line.to_string()))?;
+      config.set(&origin, key, value)?;
+    }
+
+    Ok(config)
+  }
+
+  fn set(&mut self, origin: &str, key: &str, value: &str) -> Result<(), ConfigError> {
+    let invalid = |reason: &str| ConfigError::InvalidValue(origin.to_string(), key.to_string(), reason.to_string());
+
+    match key {
+      "listeners" => {
+        let listeners = value.split(',').map(|l| parse_listener(l.trim())).collect::<Option<Vec<_>>>()
+          .ok_or_else(|| invalid("expected addresses like PLAINTEXT://127.0.0.1:9092,PLAINTEXT://:9093"))?;
+        if listeners.iter().enumerate().any(|(i, l)| listeners[..i].contains(l)) {
+          return Err(invalid("the same address is listed twice"));
+        }
+        self.listeners = listeners;
+      },
+      "advertised.host.name" => {
+        if value.is_empty() {
+          return Err(invalid("the host name cannot be empty"));
+        }
+        self.advertised_host = Some(value.to_string());
+      },
+      "advertised.port" => {
+        let port: u16 = value.parse().map_err(|_| invalid("expected a port number"))?;
+        self.advertised_port = Some(port as i32);
+      },
+      "broker.id" => {
+        self.broker_id = value.parse().ok().filter(|&id| id >= 0).ok_or_else(|| invalid("expected a positive integer"))?;
+      },
+      "log.dirs" | "log.dir" => {
+        if value.is_empty() {
+          return Err(invalid("the directory cannot be empty"));
+        }
+        if value.contains(',') {
+          return Err(invalid("only one log directory is supported"));
+        }
+        self.log_dir = PathBuf::from(value);
+      },
+      "log.segment.bytes" => {
+        // message positions in segments are stored as int32
+        self.segment_bytes = value.parse().ok().filter(|&b: &usize| b > 0 && b <= i32::MAX as usize)
+          .ok_or_else(|| invalid("expected a size between 1 and 2147483647"))?;
+      },
+      "log.retention.hours" => {
+        self.retention_hours = value.parse().ok().filter(|&h: &i64| h > 0 || h == -1)
+          .ok_or_else(|| invalid("expected a positive number of hours, or -1"))?;
+      },
+      "log.message.timestamp.type" => {
+        self.timestamp_type = match value {
+          "CreateTime"    => TimestampType::CreateTime,
+          "LogAppendTime" => TimestampType::LogAppendTime,
+          _               => return Err(invalid("expected CreateTime or LogAppendTime")),
+        };
+      },
+      "compression.type" => {
+        self.compression = match value {
+          "producer" => None,
+          _          => Some(Compression::from_name(value)
+            .ok_or_else(|| invalid("expected producer, uncompressed, gzip, snappy, lz4 or zstd"))?),
+        };
+      },
+      "check.crcs" => {
+        self.check_crcs = value.parse().map_err(|_| invalid("expected true or false"))?;
+      },
+      "socket.request.max.bytes" => {
+        // request sizes are int32
+        self.max_request_size = value.parse().ok().filter(|&b: &usize| b > 0 && b <= i32::MAX as usize)
+          .ok_or_else(|| invalid("expected a size between 1 and 2147483647"))?;
+      },
+      "num.network.threads" => {
+        self.network_threads = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| invalid("expected a positive integer"))?;
+      },
+      "num.io.threads" => {
+        self.io_threads = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| invalid("expected a positive integer"))?;
+      },
+      "num.partitions" => {
+        self.num_partitions = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| invalid("expected a positive integer"))?;
+      },
+      "auto.create.topics.enable" => {
+        self.auto_create_topics = value.parse().map_err(|_| invalid("expected true or false"))?;
+      },
+      _ => return Err(ConfigError::UnknownKey(origin.to_string(), key.to_string())),
+    }
+
+    Ok(())
+  }
+
+  // how the broker is announced in metadata responses
+  pub fn node(&self) -> Node {
+    let listener = self.listeners[0];
+    let host = match self.advertised_host {
+      Some(ref host) => host.clone(),
+      None if listener.ip().is_unspecified() => "localhost".to_string(),
+      None => listener.ip().to_string(),
+    };
+
+    Node {
+      id:   self.broker_id,
+      host,
+      port: self.advertised_port.unwrap_or(listener.port() as i32),
+    }
+  }
+
+  // None if segments are never deleted
+  pub fn retention_ms(&self) -> Option<i64> {
+    if self.retention_hours < 0 {
+      None
+    } else {
+      Some(self.retention_hours * 3600 * 1000)
+    }
+  }
+}
+
+fn split_setting(s: &str) -> Option<(&str, &str)> {
+  let separator = s.find('=')?;
+  let key = s[..separator].trim();
+  if key.is_empty() {
+    return None;
+  }
+
+  Some((key, s[separator+1..].trim()))
+}
+
+// accepts "PLAINTEXT://host:port" or "host:port", with an empty host
+// listening on every interface
+fn parse_listener(value: &str) -> Option<SocketAddr> {
+  let address = match value.find("://") {
+    Some(i) if &value[..i] == "PLAINTEXT" => &value[i+3..],
+    Some(_)                               => return None,
+    None                                  => value,
+  };
+
+  if address.starts_with(':') {
+    format!("0.0.0.0{}", address).parse().ok()
+  } else {
+    address.parse().ok()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn args(a: &[&str]) -> Vec<String> {
+    a.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn parse_test() {
+    let config = Config::parse("server.properties", "
+      # broker settings
+      broker.id=3
+      listeners=PLAINTEXT://:9093, 127.0.0.1:9094
+      log.dirs = /var/lib/proust
+      log.segment.bytes=1048576
+      log.retention.hours=-1
+      log.message.timestamp.type=LogAppendTime
+      compression.type=lz4
+      check.crcs=false
+      socket.request.max.bytes=1048576
+      num.network.threads=2
+      num.io.threads=4
+      num.partitions=4
+      auto.create.topics.enable=false
+    ").unwrap();
+
+    assert_eq!(config, Config {
+      listeners:          vec!["0.0.0.0:9093".parse().unwrap(), "127.0.0.1:9094".parse().unwrap()],
+      advertised_host:    None,
+      advertised_port:    None,
+      broker_id:          3,
+      log_dir:            PathBuf::from("/var/lib/proust"),
+      segment_bytes:      1048576,
+      retention_hours:    -1,
+      timestamp_type:     TimestampType::LogAppendTime,
+      compression:        Some(Compression::Lz4),
+      check_crcs:         false,
+      max_request_size:   1048576,
+      network_threads:    2,
+      io_threads:         4,
+      num_partitions:     4,
+      auto_create_topics: false,
+    });
+    assert_eq!(config.node(), Node { id: 3, host: "localhost".to_string(), port: 9093 });
+    assert_eq!(config.retention_ms(), None);
+  }
+
+  #[test]
+  fn parse_errors_test() {
+    let err = Config::parse("server.properties", "broker.id=1\nnum.partitions=0").unwrap_err();
+    assert_eq!(err.to_string(), "server.properties:2: invalid value for num.partitions: expected a positive integer");
+
+    let err = Config::parse("server.properties", "zookeeper.connect=localhost:2181").unwrap_err();
+    assert_eq!(err.to_string(), "server.properties:1: unknown setting \"zookeeper.connect\"");
+
+    let err = Config::parse("server.properties", "listeners").unwrap_err();
+    assert_eq!(err.to_string(), "server.properties:1: expected KEY=VALUE, got \"listeners\"");
+
+    assert!(Config::parse("p", "listeners=SSL://:9093").is_err());
+    assert!(Config::parse("p", "listeners=PLAINTEXT://:9092,SSL://:9093").is_err());
+    assert!(Config::parse("p", "listeners=PLAINTEXT://:9092,").is_err());
+    assert!(Config::parse("p", "listeners=:9092,0.0.0.0:9092").is_err());
+    let err = Config::parse("p", "log.dirs=/data1,/data2").unwrap_err();
+    assert_eq!(err.to_string(), "p:1: invalid value for log.dirs: only one log directory is supported");
+    assert!(Config::parse("p", "log.segment.bytes=4294967296").is_err());
+    assert!(Config::parse("p", "auto.create.topics.enable=yes").is_err());
+    assert!(Config::parse("p", "log.message.timestamp.type=AppendTime").is_err());
+    assert!(Config::parse("p", "compression.type=brotli").is_err());
+    assert!(Config::parse("p", "socket.request.max.bytes=0").is_err());
+    assert!(Config::parse("p", "num.io.threads=0").is_err());
+  }
+
+  #[test]
+  fn from_args_test() {
+    assert_eq!(Config::from_args(&[]).unwrap(), Config::default());
+
+    let config = Config::from_args(&args(&["-o", "advertised.host.name=kafka1", "--override", "advertised.port=19092"])).unwrap();
+    assert_eq!(config.node(), Node { id: 0, host: "kafka1".to_string(), port: 19092 });
+
+    assert!(Config::from_args(&args(&["--config"])).is_err());
+    assert!(Config::from_args(&args(&["--config", "/nonexistent/server.properties"])).is_err());
+    assert!(Config::from_args(&args(&["--port", "9092"])).is_err());
+  }
+}
@@ -0,0 +1,145 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use parser::errors::InputError;
+use responses::error_code::ErrorCode;
+
+// errors met while serving a request, with what is known of the request
+// at that point
+#[derive(Debug)]
+pub struct BrokerError {
+  pub kind:    ErrorKind,
+  pub context: ErrorContext,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+  // the request could not be parsed
+  Parse(InputError),
+  // the request is valid, but cannot be served
+  Protocol(ErrorCode),
+  // reading or writing the logs failed
+  Storage(io::Error),
+}
+
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct ErrorContext {
+  pub api_key:        Option<i16>,
+  pub api_version:    Option<i16>,
+  pub correlation_id: Option<i32>,
+  pub topic:          Option<String>,
+  pub partition:      Option<i32>,
+}
+
+impl BrokerError {
+  pub fn new(kind: ErrorKind) -> BrokerError {
+    BrokerError { kind, context: ErrorContext::default() }
+  }
+
+  pub fn parse(error: InputError) -> BrokerError {
+    BrokerError::new(ErrorKind::Parse(error))
+  }
+
+  pub fn protocol(code: ErrorCode) -> BrokerError {
+    BrokerError::new(ErrorKind::Protocol(code))
+  }
+
+  pub fn storage(error: io::Error) -> BrokerError {
+    BrokerError::new(ErrorKind::Storage(error))
+  }
+
+  pub fn with_request(mut self, api_key: i16, api_version: i16, correlation_id: i32) -> BrokerError {
+    self.context.api_key        = Some(api_key);
+    self.context.api_version    = Some(api_version);
+    self.context.correlation_id = Some(correlation_id);
+    self
+  }
+
+  pub fn with_partition(mut self, topic: &str, partition: i32) -> BrokerError {
+    self.context.topic     = Some(topic.to_string());
+    self.context.partition = Some(partition);
+    self
+  }
+
+  // error code sent to the client
+  pub fn error_code(&self) -> ErrorCode {
+    match self.kind {
+      ErrorKind::Parse(InputError::UnsupportedVersion)    => ErrorCode::UnsupportedVersion,
+      ErrorKind::Parse(InputError::InvalidMessage)        |
+      ErrorKind::Parse(InputError::InvalidMessageSize)    |
+      ErrorKind::Parse(InputError::InvalidMessageSetSize) => ErrorCode::CorruptMessage,
+      ErrorKind::Parse(_)                                 => ErrorCode::InvalidRequest,
+      ErrorKind::Protocol(code)                           => code,
+      ErrorKind::Storage(_)                               => ErrorCode::KafkaStorageError,
+    }
+  }
+}
+
+impl From<io::Error> for BrokerError {
+  fn from(error: io::Error) -> BrokerError {
+    BrokerError::storage(error)
+  }
+}
+
+impl From<ErrorCode> for BrokerError {
+  fn from(code: ErrorCode) -> BrokerError {
+    BrokerError::protocol(code)
+  }
+}
+
+impl fmt::Display for BrokerError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.kind {
+      ErrorKind::Parse(ref e)       => write!(f, "could not parse request: {:?}", e)?,
+      ErrorKind::Protocol(ref code) => write!(f, "{:?}", code)?,
+      ErrorKind::Storage(ref e)     => write!(f, "storage error: {}", e)?,
+    }
+
+    let ErrorContext { api_key, api_version, correlation_id, ref topic, partition } = self.context;
+    if let (Some(key), Some(version)) = (api_key, api_version) {
+      write!(f, " (api key {} v{}", key, version)?;
+      if let Some(id) = correlation_id {
+        write!(f, ", correlation id {}", id)?;
+      }
+      write!(f, ")")?;
+    }
+    if let Some(ref topic) = *topic {
+      write!(f, " on {}", topic)?;
+      if let Some(p) = partition {
+        write!(f, "-{}", p)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl error::Error for BrokerError {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self.kind {
+      ErrorKind::Storage(ref e) => Some(e),
+      _                         => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn error_code_test() {
+    assert_eq!(BrokerError::parse(InputError::UnsupportedVersion).error_code(), ErrorCode::UnsupportedVersion);
+    assert_eq!(BrokerError::parse(InputError::InvalidMessageSize).error_code(), ErrorCode::CorruptMessage);
+    assert_eq!(BrokerError::parse(InputError::ParserError).error_code(), ErrorCode::InvalidRequest);
+    assert_eq!(BrokerError::from(ErrorCode::InvalidTopic).error_code(), ErrorCode::InvalidTopic);
+    assert_eq!(BrokerError::from(io::Error::other("disk full")).error_code(), ErrorCode::KafkaStorageError);
+  }
+
+  #[test]
+  fn display_test() {
+    let e = BrokerError::storage(io::Error::other("disk full")).with_request(0, 3, 7).with_partition("topic1", 2);
+    assert_eq!(e.to_string(), "storage error: disk full (api key 0 v3, correlation id 7) on topic1-2");
+    assert_eq!(BrokerError::protocol(ErrorCode::CorruptMessage).to_string(), "CorruptMessage");
+  }
+}
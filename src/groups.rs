@@ -0,0 +1,495 @@
+use std::collections::{BTreeMap,HashMap};
+
+use parser::join_group::JoinGroupRequest;
+use responses::error_code::ErrorCode;
+
+// session timeouts accepted from members, in milliseconds
+pub const MIN_SESSION_TIMEOUT: i32 = 6000;
+pub const MAX_SESSION_TIMEOUT: i32 = 300000;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum GroupState {
+  // no members, only committed offsets
+  Empty,
+  // waiting for every member to send a JoinGroup
+  PreparingRebalance,
+  // waiting for the leader to send the assignment in a SyncGroup
+  CompletingRebalance,
+  Stable,
+}
+
+#[derive(Debug)]
+pub struct Member {
+  pub id:            String,
+  session_timeout:   i64,
+  rebalance_timeout: i64,
+  // supported protocols with their metadata, by order of preference
+  protocols:         Vec<(String, Vec<u8>)>,
+  last_heartbeat:    i64,
+  // members with a pending JoinGroup or SyncGroup do not expire
+  awaiting_join:     bool,
+  awaiting_sync:     bool,
+  assignment:        Vec<u8>,
+}
+
+impl Member {
+  fn supports(&self, protocol: &str) -> bool {
+    self.protocols.iter().any(|(name, _)| name == protocol)
+  }
+
+  pub fn metadata(&self, protocol: &str) -> &[u8] {
+    self.protocols.iter().find(|(name, _)| name == protocol).map(|(_, m)| &m[..]).unwrap_or(&[])
+  }
+}
+
+#[derive(Debug)]
+pub struct Group {
+  pub state:          GroupState,
+  pub generation_id:  i32,
+  pub protocol_type:  String,
+  pub protocol:       String,
+  pub leader_id:      String,
+  pub members:        BTreeMap<String, Member>,
+  rebalance_deadline: i64,
+}
+
+impl Group {
+  fn new() -> Group {
+    Group {
+      state:              GroupState::Empty,
+      generation_id:      0,
+      protocol_type:      String::new(),
+      protocol:           String::new(),
+      leader_id:          String::new(),
+      members:            BTreeMap::new(),
+      rebalance_deadline: 0,
+    }
+  }
+
+  // members have until the largest rebalance timeout to join again
+  fn prepare_rebalance(&mut self, now: i64) {
+    let timeout = self.members.values().map(|m| m.rebalance_timeout).max().unwrap_or(0);
+    self.state = GroupState::PreparingRebalance;
+    self.rebalance_deadline = now + timeout;
+    for member in self.members.values_mut() {
+      member.awaiting_sync = false;
+    }
+  }
+
+  // starts a new generation once every member joined, or removes the ones
+  // that did not when the rebalance times out
+  fn try_complete_join(&mut self, now: i64) {
+    if self.state != GroupState::PreparingRebalance {
+      return;
+    }
+    if now < self.rebalance_deadline && self.members.values().any(|m| !m.awaiting_join) {
+      return;
+    }
+
+    self.members.retain(|_, m| m.awaiting_join);
+    self.generation_id += 1;
+
+    if self.members.is_empty() {
+      self.state = GroupState::Empty;
+      self.protocol_type.clear();
+      self.protocol.clear();
+      self.leader_id.clear();
+      return;
+    }
+
+    if !self.members.contains_key(&self.leader_id) {
+      self.leader_id = self.members.keys().next().expect("the group has members").clone();
+    }
+
+    // the first protocol of the leader supported by every member
+    self.protocol = {
+      let leader = &self.members[&self.leader_id];
+      leader.protocols.iter()
+        .map(|(name, _)| name)
+        .find(|name| self.members.values().all(|m| m.supports(name)))
+        .cloned()
+        .unwrap_or_default()
+    };
+
+    self.state = GroupState::CompletingRebalance;
+    for member in self.members.values_mut() {
+      member.awaiting_join = false;
+      member.last_heartbeat = now;
+      member.assignment.clear();
+    }
+  }
+
+  // expired members are removed and trigger a rebalance
+  fn expire_members(&mut self, now: i64) {
+    let before = self.members.len();
+    self.members.retain(|id, m| {
+      let alive = m.awaiting_join || m.awaiting_sync || now < m.last_heartbeat + m.session_timeout;
+      if !alive {
+        info!("member {} timed out", id);
+      }
+      alive
+    });
+
+    if self.members.len() != before && self.state != GroupState::PreparingRebalance {
+      self.prepare_rebalance(now);
+    }
+  }
+
+  // next time the group needs to be looked at
+  fn deadline(&self) -> Option<i64> {
+    let expirations = self.members.values()
+      .filter(|m| !m.awaiting_join && !m.awaiting_sync)
+      .map(|m| m.last_heartbeat + m.session_timeout);
+
+    if self.state == GroupState::PreparingRebalance {
+      expirations.chain(Some(self.rebalance_deadline)).min()
+    } else {
+      expirations.min()
+    }
+  }
+}
+
+// membership of consumer groups. Requests that need the other members,
+// JoinGroup and SyncGroup, register here and their responses are built
+// from `join_result` and `sync_result` once the group moved on
+pub struct GroupCoordinator {
+  groups:         HashMap<String, Group>,
+  member_counter: u64,
+}
+
+impl GroupCoordinator {
+  pub fn new() -> GroupCoordinator {
+    GroupCoordinator {
+      groups:         HashMap::new(),
+      member_counter: 0,
+    }
+  }
+
+  // adds or updates a member and returns its id, generated for new members
+  pub fn join(&mut self, req: &JoinGroupRequest, client_id: &str, now: i64) -> Result<String, ErrorCode> {
+    let JoinGroupRequest { group_id, member_id, session_timeout, rebalance_timeout, protocol_type, ref group_protocols } = *req;
+
+    if group_id.is_empty() {
+      return Err(ErrorCode::InvalidGroupId);
+    }
+    if !(MIN_SESSION_TIMEOUT..=MAX_SESSION_TIMEOUT).contains(&session_timeout) {
+      return Err(ErrorCode::InvalidSessionTimeout);
+    }
+    if protocol_type.is_empty() || group_protocols.is_empty() {
+      return Err(ErrorCode::InconsistentGroupProtocol);
+    }
+    if !member_id.is_empty() && !self.groups.contains_key(group_id) {
+      return Err(ErrorCode::UnknownMemberId);
+    }
+
+    let group = self.groups.entry(group_id.to_string()).or_insert_with(Group::new);
+    if !group.members.is_empty() {
+      let others_support = |name: &str| group.members.values().filter(|m| m.id != member_id).all(|m| m.supports(name));
+      if group.protocol_type != protocol_type || !group_protocols.iter().any(|p| others_support(p.protocol_name)) {
+        return Err(ErrorCode::InconsistentGroupProtocol);
+      }
+    }
+    if !member_id.is_empty() && !group.members.contains_key(member_id) {
+      return Err(ErrorCode::UnknownMemberId);
+    }
+
+    let id = if member_id.is_empty() {
+      self.member_counter += 1;
+      format!("{}-{}", client_id, self.member_counter)
+    } else {
+      member_id.to_string()
+    };
+
+    group.protocol_type = protocol_type.to_string();
+    group.members.insert(id.clone(), Member {
+      id:                id.clone(),
+      session_timeout:   session_timeout as i64,
+      rebalance_timeout: rebalance_timeout.max(0) as i64,
+      protocols:         group_protocols.iter().map(|p| (p.protocol_name.to_string(), p.protocol_metadata.to_vec())).collect(),
+      last_heartbeat:    now,
+      awaiting_join:     true,
+      awaiting_sync:     false,
+      assignment:        Vec::new(),
+    });
+
+    if group.state != GroupState::PreparingRebalance {
+      group.prepare_rebalance(now);
+    }
+    group.try_complete_join(now);
+
+    Ok(id)
+  }
+
+  // None while the member waits for the other members to join
+  pub fn join_result(&self, group_id: &str, member_id: &str) -> Option<Result<(&Group, &Member), ErrorCode>> {
+    let group = match self.groups.get(group_id) {
+      Some(g) => g,
+      None    => return Some(Err(ErrorCode::UnknownMemberId)),
+    };
+
+    match group.members.get(member_id) {
+      None                        => Some(Err(ErrorCode::UnknownMemberId)),
+      Some(m) if m.awaiting_join  => None,
+      Some(m)                     => Some(Ok((group, m))),
+    }
+  }
+
+  pub fn rebalance_deadline(&self, group_id: &str) -> Option<i64> {
+    self.groups.get(group_id).map(|g| g.rebalance_deadline)
+  }
+
+  pub fn session_timeout(&self, group_id: &str, member_id: &str) -> Option<i64> {
+    self.groups.get(group_id).and_then(|g| g.members.get(member_id)).map(|m| m.session_timeout)
+  }
+
+  // the leader's SyncGroup carries the assignment of every member
+  pub fn sync(&mut self, group_id: &str, generation_id: i32, member_id: &str, assignments: &[(&str, &[u8])], now: i64) -> Result<(), ErrorCode> {
+    let group = self.groups.get_mut(group_id).ok_or(ErrorCode::UnknownMemberId)?;
+    if !group.members.contains_key(member_id) {
+      return Err(ErrorCode::UnknownMemberId);
+    }
+    if generation_id != group.generation_id {
+      return Err(ErrorCode::IllegalGeneration);
+    }
+
+    match group.state {
+      GroupState::Empty               => Err(ErrorCode::UnknownMemberId),
+      GroupState::PreparingRebalance  => Err(ErrorCode::RebalanceInProgress),
+      GroupState::Stable              => Ok(()),
+      GroupState::CompletingRebalance => {
+        if member_id == group.leader_id {
+          for &(id, assignment) in assignments {
+            if let Some(m) = group.members.get_mut(id) {
+              m.assignment = assignment.to_vec();
+            }
+          }
+          for m in group.members.values_mut() {
+            m.awaiting_sync = false;
+            m.last_heartbeat = now;
+          }
+          group.state = GroupState::Stable;
+        } else if let Some(m) = group.members.get_mut(member_id) {
+          m.awaiting_sync = true;
+          m.last_heartbeat = now;
+        }
+        Ok(())
+      },
+    }
+  }
+
+  // None while the member waits for the leader's assignment
+  pub fn sync_result(&self, group_id: &str, generation_id: i32, member_id: &str) -> Option<Result<&[u8], ErrorCode>> {
+    let group = match self.groups.get(group_id) {
+      Some(g) => g,
+      None    => return Some(Err(ErrorCode::UnknownMemberId)),
+    };
+    let member = match group.members.get(member_id) {
+      Some(m) => m,
+      None    => return Some(Err(ErrorCode::UnknownMemberId)),
+    };
+
+    if generation_id != group.generation_id {
+      return Some(Err(ErrorCode::RebalanceInProgress));
+    }
+
+    match group.state {
+      GroupState::Stable              => Some(Ok(&member.assignment[..])),
+      GroupState::CompletingRebalance => None,
+      GroupState::PreparingRebalance  => Some(Err(ErrorCode::RebalanceInProgress)),
+      GroupState::Empty               => Some(Err(ErrorCode::UnknownMemberId)),
+    }
+  }
+
+  // during a rebalance, heartbeats tell members to join again
+  pub fn heartbeat(&mut self, group_id: &str, generation_id: i32, member_id: &str, now: i64) -> ErrorCode {
+    let group = match self.groups.get_mut(group_id) {
+      Some(g) => g,
+      None    => return ErrorCode::UnknownMemberId,
+    };
+    let state = group.state;
+    let current_generation = group.generation_id;
+    let member = match group.members.get_mut(member_id) {
+      Some(m) => m,
+      None    => return ErrorCode::UnknownMemberId,
+    };
+
+    match state {
+      GroupState::Empty => ErrorCode::UnknownMemberId,
+      GroupState::PreparingRebalance | GroupState::CompletingRebalance => {
+        member.last_heartbeat = now;
+        ErrorCode::RebalanceInProgress
+      },
+      GroupState::Stable if generation_id != current_generation => ErrorCode::IllegalGeneration,
+      GroupState::Stable => {
+        member.last_heartbeat = now;
+        ErrorCode::NoError
+      },
+    }
+  }
+
+  pub fn leave(&mut self, group_id: &str, member_id: &str, now: i64) -> ErrorCode {
+    let group = match self.groups.get_mut(group_id) {
+      Some(g) => g,
+      None    => return ErrorCode::UnknownMemberId,
+    };
+    if group.members.remove(member_id).is_none() {
+      return ErrorCode::UnknownMemberId;
+    }
+
+    if group.state != GroupState::PreparingRebalance {
+      group.prepare_rebalance(now);
+    }
+    group.try_complete_join(now);
+    ErrorCode::NoError
+  }
+
+  // commits from group members must match the current generation, while
+  // consumers managing their own partitions commit with generation -1
+  pub fn validate_commit(&self, group_id: &str, generation_id: i32, member_id: &str) -> Result<(), ErrorCode> {
+    let group = match self.groups.get(group_id) {
+      Some(g) if g.state != GroupState::Empty => g,
+      _ if generation_id < 0                  => return Ok(()),
+      _                                       => return Err(ErrorCode::IllegalGeneration),
+    };
+
+    if !group.members.contains_key(member_id) {
+      return Err(ErrorCode::UnknownMemberId);
+    }
+    if generation_id != group.generation_id {
+      return Err(ErrorCode::IllegalGeneration);
+    }
+    if group.state != GroupState::Stable {
+      return Err(ErrorCode::RebalanceInProgress);
+    }
+    Ok(())
+  }
+
+  // expires members and completes rebalances that timed out.
+  // Returns when it should be called again
+  pub fn tick(&mut self, now: i64) -> Option<i64> {
+    for group in self.groups.values_mut() {
+      group.expire_members(now);
+      group.try_complete_join(now);
+    }
+
+    self.groups.values().filter_map(|g| g.deadline()).min()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parser::join_group::GroupProtocol;
+
+  fn join_request<'a>(group_id: &'a str, member_id: &'a str, session_timeout: i32, protocol_type: &'a str,
+                      protocols: &[&'a str]) -> JoinGroupRequest<'a> {
+    JoinGroupRequest {
+      group_id,
+      session_timeout,
+      rebalance_timeout: 20000,
+      member_id,
+      protocol_type,
+      group_protocols: protocols.iter().enumerate().map(|(i, &protocol_name)| GroupProtocol {
+        protocol_name,
+        protocol_metadata: &[1, 2, 3][i..i+1]
+      }).collect()
+    }
+  }
+
+  fn join(c: &mut GroupCoordinator, member_id: &str, now: i64) -> String {
+    c.join(&join_request("group1", member_id, 10000, "consumer", &["range", "roundrobin"]), "client", now).unwrap()
+  }
+
+  #[test]
+  fn single_member_test() {
+    let mut c = GroupCoordinator::new();
+    let m1 = join(&mut c, "", 0);
+
+    {
+      let (group, member) = c.join_result("group1", &m1).unwrap().unwrap();
+      assert_eq!(group.state, GroupState::CompletingRebalance);
+      assert_eq!(group.generation_id, 1);
+      assert_eq!(group.protocol, "range");
+      assert_eq!(group.leader_id, m1);
+      assert_eq!(member.metadata("range"), &[1]);
+      assert_eq!(member.metadata("roundrobin"), &[2]);
+    }
+
+    assert_eq!(c.heartbeat("group1", 1, &m1, 10), ErrorCode::RebalanceInProgress);
+    assert_eq!(c.sync("group1", 0, &m1, &[], 10), Err(ErrorCode::IllegalGeneration));
+    assert_eq!(c.sync("group1", 1, &m1, &[(&m1, &[4, 2])], 10), Ok(()));
+    assert_eq!(c.sync_result("group1", 1, &m1), Some(Ok(&[4, 2][..])));
+    assert_eq!(c.groups.get("group1").unwrap().state, GroupState::Stable);
+    assert_eq!(c.heartbeat("group1", 1, &m1, 20), ErrorCode::NoError);
+    assert_eq!(c.heartbeat("group1", 1, "unknown", 20), ErrorCode::UnknownMemberId);
+    assert_eq!(c.validate_commit("group1", 1, &m1), Ok(()));
+    assert_eq!(c.validate_commit("group1", 0, &m1), Err(ErrorCode::IllegalGeneration));
+
+    assert_eq!(c.leave("group1", &m1, 30), ErrorCode::NoError);
+    assert_eq!(c.groups.get("group1").unwrap().state, GroupState::Empty);
+    assert_eq!(c.validate_commit("group1", -1, ""), Ok(()));
+  }
+
+  #[test]
+  fn rebalance_test() {
+    let mut c = GroupCoordinator::new();
+    let m1 = join(&mut c, "", 0);
+    c.sync("group1", 1, &m1, &[], 0).unwrap();
+
+    // a new member waits for the existing one to join again
+    let m2 = join(&mut c, "", 100);
+    assert!(c.join_result("group1", &m2).is_none());
+    assert_eq!(c.groups.get("group1").unwrap().state, GroupState::PreparingRebalance);
+    assert_eq!(c.heartbeat("group1", 1, &m1, 200), ErrorCode::RebalanceInProgress);
+
+    join(&mut c, &m1, 300);
+    let group = c.groups.get("group1").unwrap();
+    assert_eq!(group.state, GroupState::CompletingRebalance);
+    assert_eq!(group.generation_id, 2);
+    assert_eq!(group.leader_id, m1);
+    assert!(c.join_result("group1", &m2).unwrap().is_ok());
+
+    // the follower waits for the leader's assignment
+    c.sync("group1", 2, &m2, &[], 400).unwrap();
+    assert_eq!(c.sync_result("group1", 2, &m2), None);
+    c.sync("group1", 2, &m1, &[(&m1, &[1]), (&m2, &[2])], 500).unwrap();
+    assert_eq!(c.sync_result("group1", 2, &m2), Some(Ok(&[2][..])));
+  }
+
+  #[test]
+  fn timeouts_test() {
+    let mut c = GroupCoordinator::new();
+    let m1 = join(&mut c, "", 0);
+    c.sync("group1", 1, &m1, &[], 0).unwrap();
+    let m2 = join(&mut c, "", 1000);
+
+    // m1 does not join again before the rebalance timeout
+    assert_eq!(c.tick(1000), Some(10000));
+    c.tick(21000);
+    let group = c.groups.get("group1").unwrap();
+    assert_eq!(group.state, GroupState::CompletingRebalance);
+    assert_eq!(group.members.keys().collect::<Vec<_>>(), vec![&m2]);
+    assert_eq!(group.leader_id, m2);
+
+    // m2 stops sending heartbeats
+    c.sync("group1", 2, &m2, &[], 21000).unwrap();
+    c.tick(30000);
+    assert_eq!(c.groups.get("group1").unwrap().members.len(), 1);
+    c.tick(31000);
+    assert_eq!(c.groups.get("group1").unwrap().state, GroupState::Empty);
+  }
+
+  #[test]
+  fn join_errors_test() {
+    let mut c = GroupCoordinator::new();
+    let protocols = ["range", "roundrobin"];
+    assert_eq!(c.join(&join_request("", "", 10000, "consumer", &protocols), "client", 0), Err(ErrorCode::InvalidGroupId));
+    assert_eq!(c.join(&join_request("group1", "", 1000, "consumer", &protocols), "client", 0), Err(ErrorCode::InvalidSessionTimeout));
+    assert_eq!(c.join(&join_request("group1", "m", 10000, "consumer", &protocols), "client", 0), Err(ErrorCode::UnknownMemberId));
+    assert_eq!(c.join(&join_request("group1", "", 10000, "consumer", &[]), "client", 0), Err(ErrorCode::InconsistentGroupProtocol));
+
+    join(&mut c, "", 0);
+    assert_eq!(c.join(&join_request("group1", "", 10000, "connect", &protocols), "client", 0), Err(ErrorCode::InconsistentGroupProtocol));
+    assert_eq!(c.join(&join_request("group1", "", 10000, "consumer", &["sticky"]), "client", 0), Err(ErrorCode::InconsistentGroupProtocol));
+  }
+}
@@ -9,6 +9,10 @@ extern crate env_logger;
 #[macro_use]
 extern crate nom;
 extern crate crc;
+extern crate flate2;
+extern crate snap;
+extern crate lz4_flex;
+extern crate ruzstd;
 
 mod parser;
 mod storage;
@@ -16,15 +20,63 @@ mod network;
 mod responses;
 mod util;
 mod proust;
+mod topics;
+mod config;
+mod offset_store;
+mod groups;
+mod compression;
+mod checksum;
+mod error;
+
+use std::env;
+use std::process;
+
+use config::Config;
 
 fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  if args.iter().any(|a| a == "-h" || a == "--help") {
+    println!("{}", config::USAGE);
+    return;
+  }
+
+  let config = match Config::from_args(&args) {
+    Ok(config) => config,
+    Err(e)     => {
+      eprintln!("configuration error: {}", e);
+      process::exit(1);
+    }
+  };
+
   println!("Le peintre original procède à la façon des oculistes.");
 
   env_logger::init().expect("Can't init env_logger");
 
-  storage::storage_test();
+  let mut broker = match proust::Broker::new(config.node(), &config.log_dir, config.segment_bytes) {
+    Ok(broker) => broker,
+    Err(e)     => {
+      eprintln!("could not open the log directory {}: {}", config.log_dir.display(), e);
+      process::exit(1);
+    }
+  };
+  broker.auto_create_topics = config.auto_create_topics;
+  broker.num_partitions = config.num_partitions;
+  broker.retention_ms = config.retention_ms();
+  broker.message_timestamp_type = config.timestamp_type;
+  broker.compression = config.compression;
+  broker.check_crcs = config.check_crcs;
 
-  let jg = network::kafka::start_listener("127.0.0.1:9092".to_string()).expect("start kafka");
+  let acceptors = match network::kafka::start_listener(&config.listeners, broker, config.max_request_size, config.network_threads, config.io_threads) {
+    Ok(acceptors) => acceptors,
+    Err(e)        => {
+      eprintln!("{}", e);
+      process::exit(1);
+    }
+  };
 
-  jg.join();
-}
\ No newline at end of file
+  for acceptor in acceptors {
+    if acceptor.join().is_err() {
+      error!("a listener thread panicked");
+    }
+  }
+}
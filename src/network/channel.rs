@@ -0,0 +1,86 @@
+use mio::{Poll, PollOpt, Ready, Registration, SetReadiness, Token};
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender, SendError};
+
+// mpsc channel whose messages wake up the Poll of the receiving thread
+pub fn channel<T>() -> (Notifier<T>, Notified<T>) {
+  let (registration, readiness) = Registration::new2();
+  let (sender, receiver) = mpsc::channel();
+
+  (Notifier { sender, readiness: readiness.clone() }, Notified { receiver, registration, readiness })
+}
+
+pub struct Notifier<T> {
+  sender:    Sender<T>,
+  readiness: SetReadiness,
+}
+
+// not derived, the messages do not need to be Clone
+impl<T> Clone for Notifier<T> {
+  fn clone(&self) -> Notifier<T> {
+    Notifier { sender: self.sender.clone(), readiness: self.readiness.clone() }
+  }
+}
+
+impl<T> Notifier<T> {
+  pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+    self.sender.send(message)?;
+    // fails only if the receiving Poll is gone
+    let _ = self.readiness.set_readiness(Ready::readable());
+    Ok(())
+  }
+}
+
+pub struct Notified<T> {
+  receiver:     Receiver<T>,
+  registration: Registration,
+  readiness:    SetReadiness,
+}
+
+impl<T> Notified<T> {
+  pub fn register(&self, poll: &Poll, token: Token) -> io::Result<()> {
+    poll.register(&self.registration, token, Ready::readable(), PollOpt::edge())
+  }
+
+  // the messages sent until now. The readiness is cleared before reading
+  // them, so that messages sent in the meantime wake the Poll again
+  pub fn drain(&self) -> Vec<T> {
+    let _ = self.readiness.set_readiness(Ready::empty());
+    self.receiver.try_iter().collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use mio::Events;
+  use std::thread;
+  use std::time::Duration;
+
+  #[test]
+  fn channel_test() {
+    let poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(16);
+    let (notifier, notified) = channel();
+    notified.register(&poll, Token(3)).unwrap();
+
+    let sender = notifier.clone();
+    thread::spawn(move || {
+      sender.send(1).unwrap();
+      sender.send(2).unwrap();
+    }).join().unwrap();
+
+    poll.poll(&mut events, Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(events.iter().map(|e| e.token()).collect::<Vec<_>>(), vec![Token(3)]);
+    assert_eq!(notified.drain(), vec![1, 2]);
+
+    // drained, nothing wakes the Poll until the next message
+    poll.poll(&mut events, Some(Duration::from_millis(10))).unwrap();
+    assert!(events.is_empty());
+
+    notifier.send(3).unwrap();
+    poll.poll(&mut events, Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(notified.drain(), vec![3]);
+  }
+}
@@ -0,0 +1,168 @@
+use bytes::BytesMut;
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, ErrorKind};
+
+// socket.request.max.bytes in Kafka
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 100 * 1024 * 1024;
+
+const SIZE_LENGTH: usize = 4;
+const READ_CHUNK: usize = 16 * 1024;
+
+#[derive(Debug,PartialEq)]
+pub enum FrameError {
+  NegativeSize(i32),
+  TooLarge(usize),
+}
+
+impl fmt::Display for FrameError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      FrameError::NegativeSize(size) => write!(f, "invalid request size {}", size),
+      FrameError::TooLarge(size)     => write!(f, "request of {} bytes is over the maximum request size", size),
+    }
+  }
+}
+
+// accumulates the bytes read from a connection, and splits them in
+// requests on their int32 size prefix
+pub struct FrameDecoder {
+  buffer:   BytesMut,
+  max_size: usize,
+}
+
+impl FrameDecoder {
+  pub fn new(max_size: usize) -> FrameDecoder {
+    FrameDecoder { buffer: BytesMut::new(), max_size }
+  }
+
+  // reads until the socket would block, or until the buffer holds a
+  // complete request or an invalid size, so that it never grows over the
+  // largest request. Returns true if the peer closed the connection
+  pub fn fill<R: Read>(&mut self, reader: &mut R) -> io::Result<bool> {
+    let mut chunk = [0; READ_CHUNK];
+    while !self.has_frame() {
+      let len = cmp::min(READ_CHUNK, SIZE_LENGTH + self.max_size - self.buffer.len());
+      match reader.read(&mut chunk[..len]) {
+        Ok(0)  => return Ok(true),
+        Ok(n)  => self.buffer.extend_from_slice(&chunk[..n]),
+        Err(e) => match e.kind() {
+          ErrorKind::WouldBlock  => return Ok(false),
+          ErrorKind::Interrupted => continue,
+          _                      => return Err(e),
+        }
+      }
+    }
+    Ok(false)
+  }
+
+  // true if next_frame would return a request or an error
+  pub fn has_frame(&self) -> bool {
+    match self.frame_size() {
+      Ok(Some(size)) => self.buffer.len() >= SIZE_LENGTH + size,
+      Ok(None)       => false,
+      Err(_)         => true,
+    }
+  }
+
+  // the size of the first request, checked as soon as its prefix is read
+  fn frame_size(&self) -> Result<Option<usize>, FrameError> {
+    if self.buffer.len() < SIZE_LENGTH {
+      return Ok(None);
+    }
+
+    let size = i32::from_be_bytes([self.buffer[0], self.buffer[1], self.buffer[2], self.buffer[3]]);
+    if size < 0 {
+      return Err(FrameError::NegativeSize(size));
+    }
+    let size = size as usize;
+    if size > self.max_size {
+      return Err(FrameError::TooLarge(size));
+    }
+    Ok(Some(size))
+  }
+
+  // the next complete request, without its size prefix
+  pub fn next_frame(&mut self) -> Result<Option<BytesMut>, FrameError> {
+    let size = match self.frame_size()? {
+      Some(size) if self.buffer.len() >= SIZE_LENGTH + size => size,
+      _                                                     => return Ok(None),
+    };
+
+    self.buffer.split_to(SIZE_LENGTH);
+    Ok(Some(self.buffer.split_to(size)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // hands out the chunks one read at a time, then would block
+  struct Chunks(Vec<&'static [u8]>);
+
+  impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      if self.0.is_empty() {
+        return Err(io::Error::new(ErrorKind::WouldBlock, "no data"));
+      }
+      let chunk = self.0.remove(0);
+      buf[..chunk.len()].copy_from_slice(chunk);
+      Ok(chunk.len())
+    }
+  }
+
+  #[test]
+  fn next_frame_test() {
+    let mut decoder = FrameDecoder::new(16);
+
+    // the size prefix is split across reads
+    assert!(!decoder.fill(&mut Chunks(vec![&[0x00, 0x00], &[0x00]])).unwrap());
+    assert_eq!(decoder.next_frame(), Ok(None));
+    assert!(!decoder.fill(&mut Chunks(vec![&[0x03, 0x01, 0x02]])).unwrap());
+    assert_eq!(decoder.next_frame(), Ok(None));
+
+    // pipelined requests in a single read
+    decoder.fill(&mut Chunks(vec![&[0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x04]])).unwrap();
+    assert_eq!(decoder.next_frame().unwrap().unwrap(), &[0x01, 0x02, 0x03][..]);
+    assert_eq!(decoder.next_frame().unwrap().unwrap(), &[][..]);
+    assert_eq!(decoder.next_frame(), Ok(None));
+    decoder.fill(&mut Chunks(vec![&[0x05]])).unwrap();
+    assert_eq!(decoder.next_frame().unwrap().unwrap(), &[0x04, 0x05][..]);
+    assert_eq!(decoder.next_frame(), Ok(None));
+
+    assert!(decoder.fill(&mut &[][..]).unwrap());
+  }
+
+  #[test]
+  fn invalid_size_test() {
+    let mut decoder = FrameDecoder::new(16);
+    decoder.fill(&mut Chunks(vec![&[0x00, 0x00, 0x00, 0x11]])).unwrap();
+    assert_eq!(decoder.next_frame(), Err(FrameError::TooLarge(17)));
+
+    let mut decoder = FrameDecoder::new(16);
+    decoder.fill(&mut Chunks(vec![&[0xff, 0xff, 0xff, 0xff]])).unwrap();
+    assert_eq!(decoder.next_frame(), Err(FrameError::NegativeSize(-1)));
+  }
+
+  #[test]
+  fn fill_bound_test() {
+    // reads stop at the first complete request
+    let mut decoder = FrameDecoder::new(4);
+    let mut socket = &[0x00, 0x00, 0x00, 0x04, 0x01, 0x02, 0x03, 0x04, 0x00, 0x00, 0x00, 0x01, 0x05][..];
+    assert!(!decoder.fill(&mut socket).unwrap());
+    assert_eq!(socket.len(), 5);
+    assert!(decoder.has_frame());
+    assert_eq!(decoder.next_frame().unwrap().unwrap(), &[0x01, 0x02, 0x03, 0x04][..]);
+    assert!(!decoder.has_frame());
+    assert!(!decoder.fill(&mut socket).unwrap());
+    assert_eq!(decoder.next_frame().unwrap().unwrap(), &[0x05][..]);
+
+    // and at a size prefix over the maximum
+    let mut decoder = FrameDecoder::new(4);
+    let mut chunks = Chunks(vec![&[0x00, 0x00], &[0x00, 0x05], &[0x01, 0x02, 0x03, 0x04, 0x05]]);
+    assert!(!decoder.fill(&mut chunks).unwrap());
+    assert_eq!(chunks.0.len(), 1);
+    assert_eq!(decoder.next_frame(), Err(FrameError::TooLarge(5)));
+  }
+}
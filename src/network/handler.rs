@@ -1,55 +1,40 @@
 use mio::*;
-use mio::net::{TcpListener, TcpStream};
+use mio::net::TcpStream;
 use mio::unix::UnixReady;
-use bytes::{BytesMut, BufMut};
-use nom::be_u32;
-use nom::IResult::*;
 use std::collections::HashMap;
-use std::io::{Read, Write, ErrorKind};
-use std::net::SocketAddr;
-use std::error::Error;
-use responses::metadata::*;
-use responses::response::*;
+use std::io::ErrorKind;
+use std::net;
+use network::frame::FrameDecoder;
+use network::outbound::OutboundQueue;
+use network::channel::{Notifier,Notified};
+use storage::SharedBytes;
 
-const SERVER: Token = Token(0);
+// new connections and responses are sent to the network thread through
+// a channel registered with this token
+const CHANNEL: Token = Token(0);
 
 pub struct Session {
-  pub socket: TcpStream,
-  pub state:  ClientState,
-  pub token:  usize,
-  pub buffer: Option<BytesMut>
-}
-
-#[derive(Debug,Clone)]
-pub enum ClientState {
-  Normal,
-  Await(usize)
+  pub socket:   TcpStream,
+  pub token:    usize,
+  pub frames:   FrameDecoder,
+  pub outbound: OutboundQueue
 }
 
 pub trait Client {
-
-  fn new(stream: TcpStream, index: usize) -> Self;
-  fn handle_message(&mut self, buffer: &mut [u8]) -> ClientErr;
+  // state shared by every client of a server
+  type State;
+  // result of a request, sent back to the network thread
+  type Response: Send;
+
+  fn new(stream: TcpStream, index: usize, max_request_size: usize) -> Self;
+  fn handle_message(&mut self, buffer: &mut [u8], state: &mut Self::State) -> ClientErr;
+  fn handle_response(&mut self, response: Self::Response, state: &mut Self::State) -> ClientErr;
   fn session(&mut self) -> &mut Session;
 
-  #[inline]
-  fn state(&mut self) -> ClientState {
-    self.session().state.clone()
-  }
-
-  #[inline]
-  fn set_state(&mut self, st: ClientState) {
-    self.session().state = st;
-  }
-
-  #[inline]
-  fn buffer(&mut self) -> Option<BytesMut> {
-    self.session().buffer.take()
-  }
-
-  #[inline]
-  fn set_buffer(&mut self, buf: &[u8]) {
-    self.session().buffer = Some(BytesMut::from(buf));
+  // while a request is handled, the next ones are left in the decoder
+  // so that responses are sent in order
+  fn awaiting_response(&mut self) -> bool {
+    false
   }
 
   #[inline]
@@ -57,101 +42,34 @@ pub trait Client {
     &mut self.session().socket
   }
 
-  fn read_size(&mut self) -> ClientResult {
-    let mut size_buf: [u8; 4] = [0; 4];
-
-    match self.socket().read(&mut size_buf) {
-      Ok(size) => {
-        if size != 4 {
-          Err(ClientErr::Continue)
-        }
-        else {
-          match be_u32(&size_buf) {
-            Done(buf, size) => Ok(size as usize),
-            _ => Err(ClientErr::Continue),
-          }
-        }
-      },
-      Err(e) => {
-        match e.kind() {
-          ErrorKind::BrokenPipe => {
-            error!("broken pipe, removing client");
-            Err(ClientErr::ShouldClose)
-          },
-          _ => {
-            error!("error writing: {:?} | {:?} | {:?} | {:?}", e, e.description(), e.cause(), e.kind());
-            Err(ClientErr::Continue)
-          }
-        }
-      }
+  // queues the parts of the message after the responses not written yet,
+  // and writes as much as the socket accepts
+  fn write(&mut self, msg: Vec<SharedBytes>) -> ClientResult {
+    for buffer in msg {
+      self.session().outbound.push(buffer);
     }
+    self.flush()
   }
 
-  fn read_to_buf(&mut self, buffer: &mut BytesMut, size: usize) -> ClientResult {
-    let mut bytes_read: usize = 0;
-    loop {
-      match self.socket().read(unsafe { buffer.bytes_mut() }) {
-        Ok(just_read) => {
-          if just_read == 0 {
-            println!("breaking because just_read == {}", just_read);
-            break;
-          }
-          else {
-            bytes_read += just_read;
-            unsafe { buffer.advance_mut(just_read) };
-          }
-        },
-        Err(e) => {
-          match e.kind() {
-            ErrorKind::BrokenPipe => {
-              println!("broken pipe, removing client");
-              return Err(ClientErr::ShouldClose)
-            },
-            ErrorKind::WouldBlock => {
-              break;
-            },
-            _ => {
-              println!("error writing: {:?} | {:?} | {:?} | {:?}", e, e.description(), e.cause(), e.kind());
-              return Err(ClientErr::Continue)
-            }
-          }
-        }
+  // called again on writable events while the queue is not empty
+  fn flush(&mut self) -> ClientResult {
+    let session = self.session();
+    session.outbound.flush(&mut session.socket).map_err(|e| {
+      match e.kind() {
+        ErrorKind::BrokenPipe => error!("broken pipe, removing client"),
+        _                     => error!("error writing: {}", e),
       }
-    }
-
-    Ok(bytes_read)
+      ClientErr::ShouldClose
+    })
   }
 
-  fn write(&mut self, msg: &[u8]) -> ClientResult {
-    match self.socket().write_all(msg) {
-      Ok(_)  => Ok(msg.len()),
-      Err(e) => {
-        match e.kind() {
-          ErrorKind::Interrupted  => {
-            error!("Interrupted during write message, removing client");
-            Err(ClientErr::Continue)
-          },
-          ErrorKind::BrokenPipe => {
-            error!("broken pipe, removing client");
-            Err(ClientErr::ShouldClose)
-          },
-          _ => {
-            error!("error writing: {:?} | {:?} | {:?} | {:?}", e, e.description(), e.cause(), e.kind());
-            Err(ClientErr::Continue)
-          }
-        }
-      }
-    }
+  // requests are left unread while too many responses wait to be written
+  #[inline]
+  fn reads_paused(&mut self) -> bool {
+    self.session().outbound.is_full()
   }
 }
 
-#[derive(Debug)]
-pub enum Message {
-  Stop,
-  Data(Vec<u8>),
-  Close(usize)
-}
-
 #[derive(Debug)]
 pub enum ClientErr {
   Continue,
@@ -160,39 +78,64 @@ pub enum ClientErr {
 
 pub type ClientResult = Result<usize, ClientErr>;
 
+pub enum ChannelEvent<R> {
+  Connection(TcpStream),
+  // response to the request of a client, by token
+  Response(usize, R)
+}
+
+// accepts connections, and hands them to the network threads in turn
+pub fn accept<R>(listener: net::TcpListener, servers: Vec<Notifier<ChannelEvent<R>>>) {
+  for (i, stream) in listener.incoming().enumerate() {
+    match stream.and_then(TcpStream::from_stream) {
+      Ok(stream) => {
+        if servers[i % servers.len()].send(ChannelEvent::Connection(stream)).is_err() {
+          error!("network thread {} stopped", i % servers.len());
+        }
+      },
+      Err(e)     => error!("Invalid connection: {}", e),
+    }
+  }
+}
+
+// event loop of a network thread, reading requests from its clients and
+// writing their responses
 pub struct Server<C: Client> {
-  pub tcp_listener: TcpListener,
+  // tokens are not reused, a response may arrive after its client is gone
   pub token_index:  usize,
   pub clients:      HashMap<usize, C>,
   pub poll:         Poll,
-  pub available_tokens: Vec<usize>
+  pub state:        C::State,
+  events:           Notified<ChannelEvent<C::Response>>,
+  max_request_size: usize
 }
 
 
 impl<C: Client> Server<C> {
 
-  pub fn new(addr: SocketAddr, poll: Poll) -> Self {
+  pub fn new(poll: Poll, state: C::State, events: Notified<ChannelEvent<C::Response>>, max_request_size: usize) -> Self {
     Server {
-      tcp_listener: TcpListener::bind(&addr.into()).unwrap(),
       token_index: 1,
       clients: HashMap::new(),
       poll,
-      available_tokens: Vec::new()
+      state,
+      events,
+      max_request_size
     }
   }
 
   pub fn run(&mut self) {
     let mut events = Events::with_capacity(1024);
 
-    self.poll.register(&self.tcp_listener, SERVER, Ready::readable(), PollOpt::edge()).unwrap();
+    self.events.register(&self.poll, CHANNEL).unwrap();
 
-    'main: loop {
+    loop {
       self.poll.poll(&mut events, None).unwrap();
 
       for event in events.iter() {
         match event.token() {
-          SERVER => {
-            self.accept();
+          CHANNEL => {
+            self.channel_events();
           },
           Token(t) => {
             let kind = event.readiness();
@@ -214,86 +157,99 @@ impl<C: Client> Server<C> {
     }
   }
 
-  fn accept(&mut self) {
-    if let Ok((stream, addr)) = self.tcp_listener.accept() {
-      let index = self.next_token();
-      info!("got client n°{:?}", index);
-      let token = Token(index);
+  fn channel_events(&mut self) {
+    for event in self.events.drain() {
+      match event {
+        ChannelEvent::Connection(stream)     => self.add_client(stream),
+        ChannelEvent::Response(tk, response) => self.client_response(tk, response),
+      }
+    }
+  }
 
-      self.poll.register(&stream, token, Ready::all(), PollOpt::edge());
+  fn add_client(&mut self, stream: TcpStream) {
+    let index = self.token_index;
+    self.token_index += 1;
+    info!("got client n°{:?}", index);
 
-      self.clients.insert(index, Client::new(stream, index));
-    }
-    else {
-      error!("Invalid connection");
+    if let Err(e) = self.poll.register(&stream, Token(index), Ready::readable() | Ready::writable() | UnixReady::hup(), PollOpt::edge()) {
+      error!("could not register client n°{:?}: {}", index, e);
+      return;
     }
+
+    self.clients.insert(index, Client::new(stream, index, self.max_request_size));
   }
 
-  fn next_token(&mut self) -> usize {
-    match self.available_tokens.pop() {
-      None => {
-        let index = self.token_index;
-        self.token_index += 1;
-        index
-      },
-      Some(index) => {
-        index
+  fn client_response(&mut self, tk: usize, response: C::Response) {
+    let mut error = false;
+
+    if let Some(client) = self.clients.get_mut(&tk) {
+      if let ClientErr::ShouldClose = client.handle_response(response, &mut self.state) {
+        error = true;
       }
     }
+
+    if error {
+      self.close(tk);
+    } else {
+      // requests that arrived in the meantime are waiting in the decoder
+      // or in the socket
+      self.client_read(tk);
+    }
   }
 
+  // reads what the socket holds, then handles every complete request,
+  // in order. Requests following one that is not answered yet stay in the
+  // decoder or in the socket. While reads are paused, the next ones stay
+  // in the socket
   fn client_read(&mut self, tk: usize) {
     let mut error = false;
 
     if let Some(client) = self.clients.get_mut(&tk) {
-      match client.state() {
-        ClientState::Normal => {
-          match client.read_size() {
-            Ok(size) => {
-              let mut buffer = BytesMut::with_capacity(size);
-              let capacity = buffer.remaining_mut();  // actual buffer capacity may be higher
-              if let Err(ClientErr::ShouldClose) = client.read_to_buf(&mut buffer, size) {
-                error = true;
-              }
+      if client.reads_paused() {
+        return;
+      }
+      // the next request waits for the response to the current one
+      if client.awaiting_response() && client.session().frames.has_frame() {
+        return;
+      }
 
-              if capacity - buffer.remaining_mut() < size {
-                client.set_state(ClientState::Await(size - (capacity - buffer.remaining_mut())));
-                client.set_buffer(&buffer);
-              }
-              else {
-                if let ClientErr::ShouldClose = client.handle_message(&mut buffer) {
-                  error = true;
-                }
-              }
-            },
-            Err(ClientErr::ShouldClose) => {
-              println!("should close");
-              error = true;
-            },
-            a => {
-              println!("other error: {:?}", a);
+      loop {
+        let (closed, stopped) = {
+          let session = client.session();
+          match session.frames.fill(&mut session.socket) {
+            Ok(closed) => (closed, session.frames.has_frame()),
+            Err(e)     => {
+              error!("error reading from client {}: {}", tk, e);
+              (true, false)
             }
           }
-        },
-        ClientState::Await(sz) => {
-          println!("awaits {} bytes", sz);
-          let mut buffer = client.buffer().unwrap();
-          let capacity = buffer.remaining_mut();
-
-          if let Err(ClientErr::ShouldClose) = client.read_to_buf(&mut buffer, sz) {
-            error = true;
-          }
-          if capacity - buffer.remaining_mut() < sz {
-            client.set_state(ClientState::Await(sz - (capacity - buffer.remaining_mut())));
-            client.set_buffer(&buffer);
-          }
-          else {
-            if let ClientErr::ShouldClose = client.handle_message(&mut buffer) {
+        };
+
+        while !error && !client.awaiting_response() && !client.reads_paused() {
+          match client.session().frames.next_frame() {
+            Ok(Some(mut frame)) => {
+              if let ClientErr::ShouldClose = client.handle_message(&mut frame, &mut self.state) {
+                error = true;
+              }
+            },
+            Ok(None) => break,
+            Err(e)   => {
+              error!("closing client {}: {}", tk, e);
               error = true;
             }
           }
-        },
-      };
+        }
+
+        if closed {
+          error = true;
+        }
+
+        // the decoder stops reading at a complete request, and the socket
+        // will not be readable again for the data it still holds
+        if error || !stopped || client.awaiting_response() || client.reads_paused() {
+          break;
+        }
+      }
     }
 
     // Here to fix "multiple mutable borrows occurs" if we call self.close() directly in
@@ -304,24 +260,35 @@ impl<C: Client> Server<C> {
   }
 
   fn client_write(&mut self, token: usize) {
+    let mut error = false;
+    let mut resumed = false;
+
     if let Some(client) = self.clients.get_mut(&token) {
-      match client.state() {
-        ClientState::Normal => {
-          //TODO: Look if we have something to write.
-        },
-        ClientState::Await(_) => {
-          // Do nothing, we are looking to read
-        },
+      if client.session().outbound.is_empty() {
+        return;
       }
+
+      let paused = client.reads_paused();
+      if let Err(ClientErr::ShouldClose) = client.flush() {
+        error = true;
+      }
+      resumed = paused && !client.reads_paused();
+    }
+
+    if error {
+      self.close(token);
+    } else if resumed {
+      // no readable event comes for the requests that stayed in the socket
+      self.client_read(token);
     }
   }
 
   fn close(&mut self, token: usize) {
-    self.clients.remove(&token);
-    self.available_tokens.push(token);
-
-    if let Some(client) = self.clients.get_mut(&token) {
-      self.poll.deregister(client.socket());
+    // the socket is closed when the client is dropped
+    if let Some(mut client) = self.clients.remove(&token) {
+      if let Err(e) = self.poll.deregister(client.socket()) {
+        error!("could not deregister client n°{}: {}", token, e);
+      }
     }
   }
 }
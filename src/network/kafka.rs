@@ -1,31 +1,245 @@
 use mio::net::TcpStream;
 use mio::*;
-use bytes::{BytesMut, BufMut};
-use nom::IResult;
 use nom::HexDisplay;
 
+use std::cmp;
 use std::error::Error;
+use std::mem;
+use std::net::{self,SocketAddr};
+use std::sync::{Arc,Condvar,Mutex};
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc::{self,Receiver,Sender};
 use std::thread;
+use std::time::{Duration,Instant};
 
 use network::handler::*;
 use network::handler::Client as ClientTrait;
-use parser::request::request_message;
-use responses::response::ser_response_message;
-use proust::handle_request;
+use network::channel::{Notifier,channel};
+use network::frame::FrameDecoder;
+use network::outbound::OutboundQueue;
+use parser::request::parse_request;
+use responses::response::{ResponseMessage,ser_response_message};
+use proust::{Broker,DelayedOperation,RequestResult,handle_request,complete_delayed,error_response};
+use error::BrokerError;
+use storage::SharedBytes;
+use util::now_ms;
+
+// request read by a network thread, for the request handlers
+struct Request {
+  frame: Vec<u8>,
+  token: usize,
+  reply: Notifier<ChannelEvent<Response>>
+}
+
+impl Request {
+  fn respond(&self, response: Response) {
+    if self.reply.send(ChannelEvent::Response(self.token, response)).is_err() {
+      error!("network thread stopped, dropping the response to client n°{}", self.token);
+    }
+  }
+}
+
+enum Response {
+  // the serialized response, with the fetched messages as separate parts
+  Data(Vec<SharedBytes>),
+  // the request is done, without a response to send
+  Skip,
+  Close
+}
+
+// request that could not be answered right away, kept until its
+// operation completes or its deadline passes
+struct Delayed {
+  request:   Request,
+  deadline:  Instant,
+  operation: DelayedOperation
+}
+
+// shared by the request handlers and the maintenance thread. The broker
+// has its own locks, `delayed` is only locked to add or take requests
+struct Handlers {
+  broker:        Broker,
+  delayed:       Mutex<Vec<Delayed>>,
+  // wakes up the maintenance thread when a request is delayed, to wait
+  // for its deadline
+  delayed_added: Condvar,
+  // set when delayed requests may complete. One thread at a time checks
+  // them, while `checking` is held
+  check_delayed: AtomicBool,
+  checking:      Mutex<()>
+}
+
+type SharedHandlers = Arc<Handlers>;
+
+fn serialize(res: ResponseMessage) -> Response {
+  let correlation_id = res.correlation_id;
+  let mut v: Vec<u8> = Vec::new();
+  let message_sets = ser_response_message(res, &mut v);
+
+  // the message sets are written between the parts of the response, from
+  // the segments they were read from
+  let v = SharedBytes::from(v);
+  let mut parts = Vec::with_capacity(2 * message_sets.len() + 1);
+  let mut start = 0;
+  for (position, message_set) in message_sets {
+    parts.push(v.slice(start, position));
+    parts.push(message_set);
+    start = position;
+  }
+  parts.push(v.slice(start, v.len()));
+
+  trace!("response to request {}: {} bytes", correlation_id, parts.iter().map(|p| p.len()).sum::<usize>());
+  Response::Data(parts)
+}
+
+// answers a failed request with its error code. Without a correlation
+// id the client cannot match a response, so the connection is closed
+fn error_result(e: &BrokerError) -> Response {
+  match error_response(e) {
+    Some(res) => serialize(res),
+    None      => {
+      error!("closing the connection: {}", e);
+      Response::Close
+    }
+  }
+}
+
+fn handle(handlers: &Handlers, request: Request) {
+  // parsing does not need the broker
+  let req = match parse_request(&request.frame[..]) {
+    Ok(req) => req,
+    Err(e)  => {
+      error!("{}\n{}", e, request.frame[..].to_hex(8));
+      return request.respond(error_result(&e));
+    }
+  };
+
+  trace!("request {} v{} from {:?}: {} bytes", req.correlation_id, req.api_version, req.client_id, request.frame.len());
+  // the broker locks are released once the request is handled, before
+  // serializing the response
+  match handle_request(&handlers.broker, req) {
+    RequestResult::Response(res) => request.respond(serialize(res)),
+    RequestResult::NoResponse => request.respond(Response::Skip),
+    RequestResult::Delayed(operation, timeout) => {
+      handlers.delayed.lock().unwrap().push(Delayed {
+        request,
+        deadline: Instant::now() + Duration::from_millis(cmp::max(timeout, 0) as u64),
+        operation
+      });
+      handlers.delayed_added.notify_one();
+    },
+  }
+
+  // this request may complete others
+  complete_delayed_requests(handlers);
+}
+
+// if another thread is checking the delayed requests, it checks them
+// again once done instead
+fn complete_delayed_requests(handlers: &Handlers) {
+  handlers.check_delayed.store(true, Ordering::SeqCst);
+
+  loop {
+    let checking = match handlers.checking.try_lock() {
+      Ok(guard) => guard,
+      Err(_)    => return,
+    };
+
+    while handlers.check_delayed.swap(false, Ordering::SeqCst) {
+      check_delayed_requests(handlers);
+    }
+
+    // the flag can be set after the last check, by a thread that could
+    // not take the lock
+    drop(checking);
+    if !handlers.check_delayed.load(Ordering::SeqCst) {
+      return;
+    }
+  }
+}
+
+fn check_delayed_requests(handlers: &Handlers) {
+  let now = Instant::now();
+  let mut pending = Vec::new();
+
+  // requests delayed meanwhile wait for the next check
+  for mut delayed in mem::take(&mut *handlers.delayed.lock().unwrap()) {
+    let expired = now >= delayed.deadline;
+    if !expired && !delayed.operation.may_complete(&handlers.broker) {
+      pending.push(delayed);
+      continue;
+    }
+
+    let response = match parse_request(&delayed.request.frame[..]) {
+      Ok(req) => complete_delayed(&handlers.broker, &mut delayed.operation, req, expired).map(serialize),
+      Err(e)  => Some(error_result(&e))
+    };
+
+    match response {
+      Some(response) => delayed.request.respond(response),
+      None           => pending.push(delayed),
+    }
+  }
+
+  if !pending.is_empty() {
+    handlers.delayed.lock().unwrap().extend(pending);
+    // the maintenance thread may have missed their deadlines
+    handlers.delayed_added.notify_one();
+  }
+}
+
+fn request_handler(handlers: SharedHandlers, requests: Arc<Mutex<Receiver<Request>>>) {
+  loop {
+    // the receiver is unlocked before handling the request
+    let next = requests.lock().unwrap().recv();
+    match next {
+      Ok(request) => handle(&handlers, request),
+      Err(_)      => return,
+    }
+  }
+}
+
+// runs the broker's periodic work, and answers delayed requests when
+// their deadline passes. Neither holds a lock the request handlers wait on
+fn maintenance(handlers: SharedHandlers) {
+  loop {
+    let now = now_ms();
+    let next = handlers.broker.maintenance(now);
+    complete_delayed_requests(&handlers);
+
+    let mut timeout = Duration::from_millis(cmp::max(next - now, 0) as u64);
+    let delayed = handlers.delayed.lock().unwrap();
+    if let Some(deadline) = delayed.iter().map(|d| d.deadline).min() {
+      timeout = cmp::min(timeout, deadline.saturating_duration_since(Instant::now()));
+    }
+    let _ = handlers.delayed_added.wait_timeout(delayed, timeout).unwrap();
+  }
+}
+
+// state of a network thread
+struct Network {
+  requests: Sender<Request>,
+  replies:  Notifier<ChannelEvent<Response>>
+}
 
 struct Client {
-  session: Session
+  session:   Session,
+  in_flight: bool
 }
 
 impl ClientTrait for Client {
-  fn new(stream: TcpStream, index: usize) -> Client {
+  type State = Network;
+  type Response = Response;
+
+  fn new(stream: TcpStream, index: usize, max_request_size: usize) -> Client {
     Client{
       session: Session {
         socket: stream,
-        state: ClientState::Normal,
         token: index,
-        buffer: None
-      }
+        frames: FrameDecoder::new(max_request_size),
+        outbound: OutboundQueue::new()
+      },
+      in_flight: false
     }
   }
 
@@ -33,35 +247,77 @@ impl ClientTrait for Client {
     &mut self.session
   }
 
-  fn handle_message(&mut self, buffer: &mut [u8]) -> ClientErr {
-    let parsed_request_message = request_message(&buffer[..]);
-    if let IResult::Done(_, req) = parsed_request_message {
-      println!("Got request: {:#?}", req);
-      let response = handle_request(req);
-      if let Ok(res) = response {
-        println!("Writing response: {:#?}", res);
-        let mut v: Vec<u8> = Vec::new();
-        ser_response_message(res, &mut v);
-        self.write(&v[..]);
-      } else {
-        println!("Got request handling error {:?}", response);
-      }
-    } else {
-      println!("Got request parsing error {:?}\n{}", parsed_request_message, (&buffer[..]).to_hex(8));
-    }
+  fn handle_message(&mut self, buffer: &mut [u8], network: &mut Network) -> ClientErr {
+    let request = Request {
+      frame: buffer.to_vec(),
+      token: self.session.token,
+      reply: network.replies.clone()
+    };
 
+    if network.requests.send(request).is_err() {
+      error!("request handlers stopped");
+      return ClientErr::ShouldClose;
+    }
+    self.in_flight = true;
     ClientErr::Continue
   }
+
+  fn handle_response(&mut self, response: Response, _network: &mut Network) -> ClientErr {
+    self.in_flight = false;
+    match response {
+      Response::Data(v) => match self.write(v) {
+        Ok(_)  => ClientErr::Continue,
+        Err(e) => e,
+      },
+      Response::Skip    => ClientErr::Continue,
+      Response::Close   => ClientErr::ShouldClose,
+    }
+  }
+
+  fn awaiting_response(&mut self) -> bool {
+    self.in_flight
+  }
 }
 
-pub fn start_listener(address: String) -> Result<thread::JoinHandle<()>, Box<Error>> {
-  let poll = Poll::new()?;
+// an acceptor thread per address hands connections to the network
+// threads, which read requests and send them to a pool of request handlers
+pub fn start_listener(addresses: &[SocketAddr], broker: Broker, max_request_size: usize, network_threads: usize, io_threads: usize) -> Result<Vec<thread::JoinHandle<()>>, Box<dyn Error>> {
+  let mut listeners = Vec::new();
+  for address in addresses {
+    let listener = net::TcpListener::bind(address).map_err(|e| format!("could not listen on {}: {}", address, e))?;
+    listeners.push(listener);
+  }
 
-  let jg = thread::spawn(move || {
-    let mut server = Server::<Client>::new(address.parse().unwrap(), poll);
-    server.run();
+  let handlers: SharedHandlers = Arc::new(Handlers {
+    broker,
+    delayed:       Mutex::new(Vec::new()),
+    delayed_added: Condvar::new(),
+    check_delayed: AtomicBool::new(false),
+    checking:      Mutex::new(())
   });
+  let (requests, receiver) = mpsc::channel();
+  let receiver = Arc::new(Mutex::new(receiver));
 
-  Ok(jg)
-}
+  for _ in 0..io_threads {
+    let handlers = handlers.clone();
+    let receiver = receiver.clone();
+    thread::spawn(move || request_handler(handlers, receiver));
+  }
+  thread::spawn(move || maintenance(handlers));
 
+  let mut servers = Vec::new();
+  for _ in 0..network_threads {
+    let (notifier, notified) = channel();
+    let network = Network { requests: requests.clone(), replies: notifier.clone() };
+    let mut server = Server::<Client>::new(Poll::new()?, network, notified, max_request_size);
+    thread::spawn(move || server.run());
+    servers.push(notifier);
+  }
+
+  let acceptors = listeners.into_iter().map(|listener| {
+    let servers = servers.clone();
+    thread::spawn(move || accept(listener, servers))
+  }).collect();
+
+  Ok(acceptors)
+}
@@ -1,2 +1,5 @@
 pub mod kafka;
 pub mod handler;
+pub mod frame;
+pub mod outbound;
+pub mod channel;
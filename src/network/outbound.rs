@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+use std::io::{self, Write, ErrorKind};
+use storage::SharedBytes;
+
+// over this many bytes waiting to be written, the connection's requests
+// are not read anymore until the client catches up
+pub const MAX_QUEUED_BYTES: usize = 1024 * 1024;
+
+// responses waiting for the socket to be writable, sent in the order
+// they were queued. Fetched messages are queued as their own buffers, and
+// written from the segments they were read from
+pub struct OutboundQueue {
+  buffers:  VecDeque<SharedBytes>,
+  // bytes of the first buffer already written
+  position: usize,
+  // bytes left to write
+  len:      usize,
+}
+
+impl OutboundQueue {
+  pub fn new() -> OutboundQueue {
+    OutboundQueue { buffers: VecDeque::new(), position: 0, len: 0 }
+  }
+
+  pub fn push(&mut self, buffer: SharedBytes) {
+    if !buffer.is_empty() {
+      self.len += buffer.len();
+      self.buffers.push_back(buffer);
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn is_full(&self) -> bool {
+    self.len > MAX_QUEUED_BYTES
+  }
+
+  // writes until the queue is empty or the socket would block, and
+  // returns the number of bytes written
+  pub fn flush<W: Write>(&mut self, writer: &mut W) -> io::Result<usize> {
+    let mut written = 0;
+
+    while let Some(front_len) = self.buffers.front().map(|b| b.len()) {
+      match writer.write(&self.buffers[0][self.position..]) {
+        Ok(0)  => return Err(io::Error::new(ErrorKind::WriteZero, "the socket accepts no more data")),
+        Ok(n)  => {
+          written       += n;
+          self.len      -= n;
+          self.position += n;
+          if self.position == front_len {
+            self.buffers.pop_front();
+            self.position = 0;
+          }
+        },
+        Err(e) => match e.kind() {
+          ErrorKind::WouldBlock  => break,
+          ErrorKind::Interrupted => continue,
+          _                      => return Err(e),
+        }
+      }
+    }
+
+    Ok(written)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // accepts up to `capacity` bytes, then would block
+  struct Socket {
+    data:     Vec<u8>,
+    capacity: usize,
+  }
+
+  impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      let n = ::std::cmp::min(buf.len(), self.capacity - self.data.len());
+      if n == 0 {
+        return Err(io::Error::new(ErrorKind::WouldBlock, "full"));
+      }
+      self.data.extend_from_slice(&buf[..n]);
+      Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn flush_test() {
+    let mut queue = OutboundQueue::new();
+    let mut socket = Socket { data: vec![], capacity: 3 };
+
+    queue.push(vec![1, 2].into());
+    queue.push(vec![].into());
+    queue.push(vec![3, 4, 5].into());
+
+    // the second response is written partially
+    assert_eq!(queue.flush(&mut socket).unwrap(), 3);
+    assert!(!queue.is_empty());
+    assert_eq!(queue.flush(&mut socket).unwrap(), 0);
+
+    socket.capacity = 10;
+    queue.push(vec![6].into());
+    assert_eq!(queue.flush(&mut socket).unwrap(), 3);
+    assert!(queue.is_empty());
+    assert_eq!(socket.data, vec![1, 2, 3, 4, 5, 6]);
+  }
+
+  #[test]
+  fn is_full_test() {
+    let mut queue = OutboundQueue::new();
+    queue.push(vec![0; MAX_QUEUED_BYTES].into());
+    assert!(!queue.is_full());
+    queue.push(vec![0].into());
+    assert!(queue.is_full());
+  }
+}
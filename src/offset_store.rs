@@ -0,0 +1,311 @@
+use std::io;
+use std::collections::HashMap;
+
+use nom::IResult::*;
+
+use parser::primitive::{be_i16,be_i32,be_i64,kafka_string};
+use parser::errors::InputError;
+use parser::message::{Message,o_ms_message};
+use checksum::check_entry;
+use responses::primitive::{ser_i16,ser_i32,ser_i64,ser_kafka_string};
+use responses::fetch::ser_message;
+use storage::log::PartitionLog;
+
+// internal topic where committed offsets are written, with the same
+// key and value formats as Kafka so the topic can be read by its tools
+pub const OFFSETS_TOPIC: &str = "__consumer_offsets";
+pub const OFFSETS_PARTITION: i32 = 0;
+
+// how long committed offsets are kept when the commit does not say
+pub const DEFAULT_OFFSET_RETENTION_MS: i64 = 24 * 60 * 60 * 1000;
+// largest metadata string accepted with a commit
+pub const MAX_METADATA_SIZE: usize = 4096;
+
+const KEY_VERSION: i16 = 1;
+const VALUE_VERSION: i16 = 1;
+pub const READ_CHUNK_SIZE: usize = 1024 * 1024;
+// bytes written to the offsets topic after which it is compacted
+const COMPACTION_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct CommittedOffset {
+  pub offset:           i64,
+  pub metadata:         String,
+  pub commit_timestamp: i64,
+  pub expire_timestamp: i64,
+}
+
+// latest offset committed by each group for each partition, rebuilt from
+// the offsets topic at startup. The topic is replayed a chunk at a time, so
+// that the broker answers other requests while a large topic loads
+pub struct OffsetStore {
+  offsets:       HashMap<(String, String, i32), CommittedOffset>,
+  // next offset to replay, None once loaded
+  load_position: Option<i64>,
+  // bytes loaded or committed since the last compaction
+  written:       usize,
+}
+
+impl OffsetStore {
+
+  pub fn new(log: &PartitionLog) -> OffsetStore {
+    OffsetStore { offsets: HashMap::new(), load_position: Some(log.log_start_offset()), written: 0 }
+  }
+
+  pub fn loading(&self) -> bool {
+    self.load_position.is_some()
+  }
+
+  // next offset of the offsets topic to replay, None once loaded
+  pub fn load_position(&self) -> Option<i64> {
+    self.load_position
+  }
+
+  // applies a chunk parsed from the load position
+  pub fn load_chunk(&mut self, chunk: LoadedChunk) {
+    // stale chunk, read before another one was applied
+    if Some(chunk.offset) != self.load_position {
+      return;
+    }
+
+    self.written += chunk.bytes;
+    for (key, committed) in chunk.offsets {
+      self.offsets.insert(key, committed);
+    }
+
+    if chunk.next.is_none() {
+      info!("loaded {} committed offsets", self.offsets.len());
+    }
+    self.load_position = chunk.next;
+  }
+
+  // the commits of a request are written to disk, with a single sync,
+  // before being visible in fetches and before the client is told they
+  // succeeded
+  pub fn commit(&mut self, log: &mut PartitionLog, group: &str, commits: Vec<(&str, i32, CommittedOffset)>) -> io::Result<()> {
+    for &(topic, partition, ref committed) in commits.iter() {
+      let message = commit_message(group, topic, partition, committed);
+      log.append(&message)?;
+      self.written += message.len();
+    }
+    log.sync()?;
+
+    for (topic, partition, committed) in commits {
+      self.offsets.insert((group.to_string(), topic.to_string(), partition), committed);
+    }
+    Ok(())
+  }
+
+  pub fn needs_compaction(&self) -> bool {
+    !self.loading() && self.written > COMPACTION_BYTES
+  }
+
+  // keeps only the latest commit of each partition in the offsets topic:
+  // they are written again in a new segment, and the older segments are
+  // deleted. Expired offsets are not written again, so they are gone once
+  // their segment is deleted. Returns the number of deleted segments
+  pub fn compact(&mut self, log: &mut PartitionLog, now: i64) -> io::Result<usize> {
+    if self.loading() {
+      return Ok(0);
+    }
+
+    self.remove_expired(now);
+    if log.segments().last().map(|s| !s.is_empty()) == Some(true) {
+      log.roll()?;
+    }
+
+    let start = log.log_end_offset();
+    self.written = 0;
+    for (&(ref group, ref topic, partition), committed) in self.offsets.iter() {
+      let message = commit_message(group, topic, partition, committed);
+      log.append(&message)?;
+      self.written += message.len();
+    }
+    log.sync()?;
+
+    let deleted = log.delete_segments_before_offset(start)?;
+    info!("compacted the offsets log to {} committed offsets, deleted {} segments", self.offsets.len(), deleted);
+    Ok(deleted)
+  }
+
+  // expired offsets are treated as if they were never committed
+  pub fn get(&self, group: &str, topic: &str, partition: i32, now: i64) -> Option<&CommittedOffset> {
+    self.offsets.get(&(group.to_string(), topic.to_string(), partition))
+      .filter(|c| c.expire_timestamp > now)
+  }
+
+  pub fn remove_expired(&mut self, now: i64) {
+    self.offsets.retain(|_, c| c.expire_timestamp > now);
+  }
+}
+
+// commits replayed from a chunk of the offsets topic. Chunks are parsed
+// without locking the store, then applied with `OffsetStore::load_chunk`
+pub struct LoadedChunk {
+  offset:  i64,
+  offsets: Vec<((String, String, i32), CommittedOffset)>,
+  // offset following the chunk, None at the end of the topic or if it
+  // could not be parsed
+  next:    Option<i64>,
+  bytes:   usize,
+}
+
+impl LoadedChunk {
+  // `chunk` was read at `offset`, an empty or missing chunk ends the loading
+  pub fn parse(chunk: Option<&[u8]>, offset: i64) -> LoadedChunk {
+    let mut loaded = LoadedChunk { offset, offsets: Vec::new(), next: None, bytes: 0 };
+    let mut bytes = match chunk {
+      Some(b) if !b.is_empty() => b,
+      _                        => return loaded,
+    };
+
+    let mut next = offset;
+    while let Done(rest, m) = o_ms_message(bytes) {
+      let valid = check_entry(bytes).is_some();
+      match (m.message.key.and_then(parse_key), m.message.value.and_then(parse_value)) {
+        _ if !valid => warn!("ignoring corrupted offset commit at offset {}", m.offset),
+        (Some(key), Some(committed)) => loaded.offsets.push((key, committed)),
+        _ => warn!("ignoring invalid offset commit at offset {}", m.offset),
+      }
+
+      next = m.offset + 1;
+      loaded.bytes += bytes.len() - rest.len();
+      bytes = rest;
+    }
+
+    if bytes.is_empty() {
+      loaded.next = Some(next);
+    } else {
+      error!("could not parse the offsets log at offset {}, stopped loading", next);
+    }
+    loaded
+  }
+}
+
+fn commit_message(group: &str, topic: &str, partition: i32, committed: &CommittedOffset) -> Vec<u8> {
+  let mut key: Vec<u8> = Vec::new();
+  ser_i16(KEY_VERSION, &mut key);
+  ser_kafka_string(group, &mut key);
+  ser_kafka_string(topic, &mut key);
+  ser_i32(partition, &mut key);
+
+  let mut value: Vec<u8> = Vec::new();
+  ser_i16(VALUE_VERSION, &mut value);
+  ser_i64(committed.offset, &mut value);
+  ser_kafka_string(&committed.metadata, &mut value);
+  ser_i64(committed.commit_timestamp, &mut value);
+  ser_i64(committed.expire_timestamp, &mut value);
+
+  let mut message: Vec<u8> = Vec::new();
+  ser_message(&Message { magic_byte: 0, attributes: 0, timestamp: None, key: Some(&key), value: Some(&value) }, &mut message);
+  message
+}
+
+fn parse_key(key: &[u8]) -> Option<(String, String, i32)> {
+  let res = do_parse!(key,
+    fix_error!(InputError, tag!(&[0, KEY_VERSION as u8][..])) >>
+    group: kafka_string >>
+    topic: kafka_string >>
+    partition: be_i32 >>
+    ((group.to_string(), topic.to_string(), partition))
+  );
+
+  match res {
+    Done(_, k) => Some(k),
+    _          => None,
+  }
+}
+
+fn parse_value(value: &[u8]) -> Option<CommittedOffset> {
+  let res = do_parse!(value,
+    version: be_i16 >>
+    offset: be_i64 >>
+    metadata: kafka_string >>
+    commit_timestamp: be_i64 >>
+    expire_timestamp: be_i64 >>
+    ((version, CommittedOffset { offset, metadata: metadata.to_string(), commit_timestamp, expire_timestamp }))
+  );
+
+  match res {
+    Done(_, (VALUE_VERSION, committed)) => Some(committed),
+    _                                   => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use util::test_dir;
+
+  fn load(log: &PartitionLog) -> OffsetStore {
+    let mut store = OffsetStore::new(log);
+    while let Some(offset) = store.load_position() {
+      let chunk = log.read(offset, READ_CHUNK_SIZE);
+      store.load_chunk(LoadedChunk::parse(chunk.as_ref().map(|c| &c[..]), offset));
+    }
+    store
+  }
+
+  fn committed(offset: i64, expire_timestamp: i64) -> CommittedOffset {
+    CommittedOffset { offset, metadata: "meta".to_string(), commit_timestamp: 1000, expire_timestamp }
+  }
+
+  #[test]
+  fn commit_and_load_test() {
+    let dir = test_dir("offset-store");
+    {
+      let mut log = PartitionLog::open(&dir, OFFSETS_TOPIC, OFFSETS_PARTITION, 1024).unwrap();
+      let mut store = load(&log);
+      assert!(!store.loading());
+      assert_eq!(store.get("group1", "topic1", 0, 0), None);
+
+      store.commit(&mut log, "group1", vec![("topic1", 0, committed(10, 5000))]).unwrap();
+      // the partitions of a request are committed together
+      store.commit(&mut log, "group1", vec![("topic1", 0, committed(20, 5000)), ("topic2", 3, committed(7, 5000))]).unwrap();
+      store.commit(&mut log, "group2", vec![("topic1", 1, committed(3, 2000))]).unwrap();
+      assert_eq!(store.get("group1", "topic1", 0, 0), Some(&committed(20, 5000)));
+    }
+
+    let log = PartitionLog::open(&dir, OFFSETS_TOPIC, OFFSETS_PARTITION, 1024).unwrap();
+    let mut store = load(&log);
+    assert_eq!(store.get("group1", "topic1", 0, 0), Some(&committed(20, 5000)));
+    assert_eq!(store.get("group1", "topic2", 3, 0), Some(&committed(7, 5000)));
+    assert_eq!(store.get("group2", "topic1", 1, 0), Some(&committed(3, 2000)));
+    assert_eq!(store.get("group2", "topic1", 1, 3000), None);
+
+    store.remove_expired(3000);
+    assert_eq!(store.offsets.len(), 2);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn compact_test() {
+    let dir = test_dir("offset-compaction");
+    {
+      let mut log = PartitionLog::open(&dir, OFFSETS_TOPIC, OFFSETS_PARTITION, 1024).unwrap();
+      let mut store = load(&log);
+      for i in 0..100 {
+        store.commit(&mut log, "group1", vec![("topic1", 0, committed(i, 5000))]).unwrap();
+        store.commit(&mut log, "group2", vec![("topic1", 0, committed(i, 2000))]).unwrap();
+      }
+      assert!(log.segments().len() > 2);
+
+      // the commits of group2 are expired
+      assert!(store.compact(&mut log, 3000).unwrap() > 0);
+      assert_eq!(log.log_start_offset(), 200);
+      assert_eq!(log.log_end_offset(), 201);
+      assert_eq!(store.get("group1", "topic1", 0, 3000), Some(&committed(99, 5000)));
+
+      store.commit(&mut log, "group1", vec![("topic1", 0, committed(100, 5000))]).unwrap();
+    }
+
+    let log = PartitionLog::open(&dir, OFFSETS_TOPIC, OFFSETS_PARTITION, 1024).unwrap();
+    let store = load(&log);
+    assert_eq!(store.offsets.len(), 1);
+    assert_eq!(store.get("group1", "topic1", 0, 3000), Some(&committed(100, 5000)));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
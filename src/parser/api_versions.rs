@@ -0,0 +1,58 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+
+/*
+ApiVersionsRequest => (empty for versions 0 to 2)
+*/
+
+pub const API_VERSIONS_KEY: i16 = 18;
+
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub struct ApiVersion {
+  pub api_key: i16,
+  pub min_version: i16,
+  pub max_version: i16
+}
+
+// versions of each API handled by `parse_request_payload` and the response
+// serializers, as advertised in ApiVersions responses. Requests with other
+// versions are rejected before reaching the parsers
+pub const API_VERSIONS: &[ApiVersion] = &[
+  ApiVersion { api_key: 0,  min_version: 0, max_version: 3 }, // Produce
+  ApiVersion { api_key: 1,  min_version: 0, max_version: 4 }, // Fetch
+  ApiVersion { api_key: 2,  min_version: 0, max_version: 1 }, // Offsets
+  ApiVersion { api_key: 3,  min_version: 0, max_version: 0 }, // Metadata
+  ApiVersion { api_key: 8,  min_version: 0, max_version: 2 }, // OffsetCommit
+  ApiVersion { api_key: 9,  min_version: 0, max_version: 1 }, // OffsetFetch
+  ApiVersion { api_key: 10, min_version: 0, max_version: 2 }, // FindCoordinator
+  ApiVersion { api_key: 11, min_version: 0, max_version: 1 }, // JoinGroup
+  ApiVersion { api_key: 12, min_version: 0, max_version: 0 }, // Heartbeat
+  ApiVersion { api_key: 13, min_version: 0, max_version: 0 }, // LeaveGroup
+  ApiVersion { api_key: 14, min_version: 0, max_version: 0 }, // SyncGroup
+  ApiVersion { api_key: API_VERSIONS_KEY, min_version: 0, max_version: 2 },
+];
+
+pub fn version_range(api_key: i16) -> Option<&'static ApiVersion> {
+  API_VERSIONS.iter().find(|v| v.api_key == api_key)
+}
+
+pub fn supported_version(api_key: i16, api_version: i16) -> bool {
+  match version_range(api_key) {
+    Some(v) => v.min_version <= api_version && api_version <= v.max_version,
+    None    => false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn supported_version_test() {
+    assert!(supported_version(8, 2));
+    assert!(!supported_version(8, 3));
+    assert!(!supported_version(8, -1));
+    assert!(!supported_version(4, 0));
+    assert!(supported_version(API_VERSIONS_KEY, 0));
+  }
+}
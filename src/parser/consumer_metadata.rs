@@ -6,11 +6,55 @@ use parser::primitive::*;
 use nom::{HexDisplay,Needed,IResult,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
+use nom::ErrorKind::Custom;
 
-pub type ConsumerMetadataRequest<'a> = KafkaString<'a>;
+use parser::errors::*;
 
-pub fn consumer_metadata_request<'a>(input:&'a [u8]) -> IResult<&'a [u8], ConsumerMetadataRequest<'a>> {
-  kafka_string(input)
+/*
+GroupCoordinatorRequest (version 0), renamed FindCoordinatorRequest since version 1
+  v0 => GroupId
+  v1 => CoordinatorKey CoordinatorType
+    GroupId => string
+    CoordinatorKey => string
+    CoordinatorType => int8 (0 for groups, 1 for transactions)
+*/
+
+pub const GROUP_COORDINATOR: i8 = 0;
+pub const TRANSACTION_COORDINATOR: i8 = 1;
+
+#[derive(PartialEq,Debug)]
+pub struct ConsumerMetadataRequest<'a> {
+  // the group id for group coordinators
+  pub coordinator_key: KafkaString<'a>,
+  pub coordinator_type: i8
+}
+
+pub fn consumer_metadata_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], ConsumerMetadataRequest<'a>, InputError> {
+  match api_version {
+    0     => consumer_metadata_request_v0(input),
+    1 | 2 => consumer_metadata_request_v1(input),
+    _     => InputError::ParserError.fail(),
+  }
+}
+
+pub fn consumer_metadata_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], ConsumerMetadataRequest<'a>, InputError> {
+  map!(input, kafka_string, |coordinator_key| {
+    ConsumerMetadataRequest { coordinator_key, coordinator_type: GROUP_COORDINATOR }
+  })
+}
+
+pub fn consumer_metadata_request_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], ConsumerMetadataRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    coordinator_key: kafka_string >>
+    coordinator_type: be_i8 >>
+    (
+      ConsumerMetadataRequest {
+        coordinator_key,
+        coordinator_type,
+      }
+    )
+  )
 }
 
 #[cfg(test)]
@@ -24,9 +68,22 @@ mod tests {
       let input = &[
         0x00, 0x00  //  ""
       ];
-      let result = consumer_metadata_request(input);
-      let expected = "";
+      let result = consumer_metadata_request(input, 0);
+      let expected = ConsumerMetadataRequest { coordinator_key: "", coordinator_type: GROUP_COORDINATOR };
 
       assert_eq!(result, Done(&[][..], expected))
   }
+
+  #[test]
+  fn find_coordinator_request_tests() {
+      let input = &[
+        0x00, 0x02, 0x67, 0x31, // coordinator_key = "g1"
+        0x01                    // coordinator_type = 1
+      ];
+      let result = consumer_metadata_request(input, 1);
+      let expected = ConsumerMetadataRequest { coordinator_key: "g1", coordinator_type: TRANSACTION_COORDINATOR };
+
+      assert_eq!(result, Done(&[][..], expected));
+      assert_eq!(consumer_metadata_request(input, 3), InputError::ParserError.fail());
+  }
 }
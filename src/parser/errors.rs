@@ -1,36 +1,29 @@
+use nom::{ErrorKind,IResult};
+
+// why a request could not be parsed. It is the error type of the request
+// parsers, which fail with it as a custom nom error kind
+#[derive(Debug,Clone,Copy,PartialEq)]
 pub enum InputError {
   ParserError,
   NotImplemented,
   InvalidRequestSize,
   InvalidMessageSize,
   InvalidMessageSetSize,
-  InvalidMessage
+  InvalidMessage,
+  UnsupportedVersion
 }
 
 impl InputError {
   #[inline]
-  pub fn to_int(&self) -> u32 {
-    match *self {
-      InputError::ParserError           => 1,
-      InputError::NotImplemented        => 2,
-      InputError::InvalidRequestSize    => 3,
-      InputError::InvalidMessageSetSize => 4,
-      InputError::InvalidMessageSize    => 5,
-      InputError::InvalidMessage        => 6
-    }
+  pub fn fail<I, O>(self) -> IResult<I, O, InputError> {
+    IResult::Error(ErrorKind::Custom(self))
   }
-}
 
-#[inline]
-pub fn from_int(code: u32) -> Option<InputError> {
-  match code {
-    1 => Option::Some(InputError::ParserError),
-    2 => Option::Some(InputError::NotImplemented),
-    3 => Option::Some(InputError::InvalidRequestSize),
-    4 => Option::Some(InputError::InvalidMessageSetSize),
-    5 => Option::Some(InputError::InvalidMessageSize),
-    6 => Option::Some(InputError::InvalidMessage),
-    _ => Option::None
+  // errors of nom's own parsers and combinators are reported as ParserError
+  pub fn from_error_kind(kind: &ErrorKind<InputError>) -> InputError {
+    match *kind {
+      ErrorKind::Custom(e) => e,
+      _                    => InputError::ParserError
+    }
   }
 }
-
@@ -2,31 +2,92 @@
 #![allow(unused_imports)]
 
 use parser::primitive::*;
+use parser::errors::*;
 
-use nom::{HexDisplay,Needed,IResult,FileProducer, be_i16, be_i32, be_i64};
+use nom::{HexDisplay,Needed,IResult,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
+use nom::ErrorKind::Custom;
 
 #[derive(PartialEq,Debug)]
 pub struct FetchRequest<'a> {
-  replica_id: i32,
-  max_wait_time: i32,
-  min_bytes: i32,
-  topics: Vec<TopicFetch<'a>>
+  pub replica_id: i32,
+  pub max_wait_time: i32,
+  pub min_bytes: i32,
+  // limit over all partitions, since version 3
+  pub max_bytes: i32,
+  // since version 4, 0 to read uncommitted messages
+  pub isolation_level: i8,
+  pub topics: Vec<TopicFetch<'a>>
 }
 
-pub fn fetch_request<'a>(input:&'a [u8]) -> IResult<&'a [u8], FetchRequest<'a>> {
+pub fn fetch_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], FetchRequest<'a>, InputError> {
+  match api_version {
+    // versions 1 and 2 only change the response
+    0..=2 => fetch_request_v0(input),
+    3 => fetch_request_v3(input),
+    4 => fetch_request_v4(input),
+    _ => InputError::ParserError.fail(),
+  }
+}
+
+pub fn fetch_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], FetchRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    replica_id: be_i32 >>
+    max_wait_time: be_i32 >>
+    min_bytes: be_i32 >>
+    topics: apply!(kafka_array, topic_fetch) >>
+    (
+      FetchRequest {
+        replica_id,
+        max_wait_time,
+        min_bytes,
+        max_bytes: i32::MAX,
+        isolation_level: 0,
+        topics,
+      }
+    )
+  )
+}
+
+pub fn fetch_request_v3<'a>(input:&'a [u8]) -> IResult<&'a [u8], FetchRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    replica_id: be_i32 >>
+    max_wait_time: be_i32 >>
+    min_bytes: be_i32 >>
+    max_bytes: be_i32 >>
+    topics: apply!(kafka_array, topic_fetch) >>
+    (
+      FetchRequest {
+        replica_id,
+        max_wait_time,
+        min_bytes,
+        max_bytes,
+        isolation_level: 0,
+        topics,
+      }
+    )
+  )
+}
+
+pub fn fetch_request_v4<'a>(input:&'a [u8]) -> IResult<&'a [u8], FetchRequest<'a>, InputError> {
   do_parse!(
     input,
     replica_id: be_i32 >>
     max_wait_time: be_i32 >>
     min_bytes: be_i32 >>
+    max_bytes: be_i32 >>
+    isolation_level: be_i8 >>
     topics: apply!(kafka_array, topic_fetch) >>
     (
       FetchRequest {
         replica_id,
         max_wait_time,
         min_bytes,
+        max_bytes,
+        isolation_level,
         topics,
       }
     )
@@ -35,11 +96,11 @@ pub fn fetch_request<'a>(input:&'a [u8]) -> IResult<&'a [u8], FetchRequest<'a>>
 
 #[derive(PartialEq,Debug)]
 pub struct TopicFetch<'a> {
-  topic_name: KafkaString<'a>,
-  partitions: Vec<PartitionFetch>
+  pub topic_name: KafkaString<'a>,
+  pub partitions: Vec<PartitionFetch>
 }
 
-pub fn topic_fetch<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicFetch<'a>> {
+pub fn topic_fetch<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicFetch<'a>, InputError> {
   do_parse!(
     input,
     topic_name: kafka_string >>
@@ -55,12 +116,12 @@ pub fn topic_fetch<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicFetch<'a>> {
 
 #[derive(PartialEq,Debug)]
 pub struct PartitionFetch {
-  partition: i32,
-  fetch_offset: i64,
-  max_bytes: i32
+  pub partition: i32,
+  pub fetch_offset: i64,
+  pub max_bytes: i32
 }
 
-pub fn partition_fetch<'a>(input:&'a [u8]) -> IResult<&'a [u8], PartitionFetch> {
+pub fn partition_fetch(input:&[u8]) -> IResult<&[u8], PartitionFetch, InputError> {
   do_parse!(
     input,
     partition: be_i32 >>
@@ -95,11 +156,13 @@ mod tests {
                 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //  fetch_offset = 0
                 0x00, 0x00, 0x00, 0x00                          //  max_bytes = 0
       ];
-      let result = fetch_request(input);
+      let result = fetch_request(input, 0);
       let expected = FetchRequest {
         replica_id: 0,
         max_wait_time: 0,
         min_bytes: 0,
+        max_bytes: i32::MAX,
+        isolation_level: 0,
         topics: vec![
           TopicFetch {
             topic_name: "",
@@ -116,4 +179,36 @@ mod tests {
 
       assert_eq!(result, Done(&[][..], expected))
   }
+
+  #[test]
+  fn fetch_request_v4_tests() {
+      let input = &[
+        0xff, 0xff, 0xff, 0xff, // replica_id = -1
+        0x00, 0x00, 0x01, 0xf4, // max_wait_time = 500
+        0x00, 0x00, 0x00, 0x01, // min_bytes = 1
+        0x00, 0x00, 0x10, 0x00, // max_bytes = 4096
+        0x01,                   // isolation_level = 1
+        0x00, 0x00, 0x00, 0x00  // topics array length
+      ];
+
+      assert_eq!(fetch_request(input, 4), Done(&[][..], FetchRequest {
+        replica_id: -1,
+        max_wait_time: 500,
+        min_bytes: 1,
+        max_bytes: 4096,
+        isolation_level: 1,
+        topics: vec![]
+      }));
+
+      // without the isolation level
+      let input_v3 = [&input[..16], &input[17..]].concat();
+      assert_eq!(fetch_request(&input_v3, 3), Done(&[][..], FetchRequest {
+        replica_id: -1,
+        max_wait_time: 500,
+        min_bytes: 1,
+        max_bytes: 4096,
+        isolation_level: 0,
+        topics: vec![]
+      }));
+  }
 }
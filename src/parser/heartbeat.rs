@@ -0,0 +1,72 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+
+use parser::primitive::*;
+use parser::errors::*;
+
+use nom::{HexDisplay,Needed,IResult,FileProducer};
+use nom::{Consumer,ConsumerState};
+use nom::IResult::*;
+use nom::ErrorKind::Custom;
+
+/*
+HeartbeatRequest => GroupId GenerationId MemberId
+  GroupId => string
+  GenerationId => int32
+  MemberId => string
+*/
+
+#[derive(PartialEq,Debug)]
+pub struct HeartbeatRequest<'a> {
+  pub group_id: KafkaString<'a>,
+  pub generation_id: i32,
+  pub member_id: KafkaString<'a>
+}
+
+pub fn heartbeat_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], HeartbeatRequest<'a>, InputError> {
+  match api_version {
+    0 => heartbeat_request_v0(input),
+    _ => InputError::ParserError.fail(),
+  }
+}
+
+pub fn heartbeat_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], HeartbeatRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    group_id: kafka_string >>
+    generation_id: be_i32 >>
+    member_id: kafka_string >>
+    (
+      HeartbeatRequest {
+        group_id,
+        generation_id,
+        member_id,
+      }
+    )
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nom::*;
+  use nom::IResult::*;
+
+  #[test]
+  fn heartbeat_request_test() {
+    let input = &[
+      0x00, 0x01, 0x67,       // group_id = "g"
+      0x00, 0x00, 0x00, 0x02, // generation_id = 2
+      0x00, 0x01, 0x6d        // member_id = "m"
+    ];
+
+    let result = heartbeat_request(input, 0);
+    let expected = HeartbeatRequest {
+      group_id: "g",
+      generation_id: 2,
+      member_id: "m"
+    };
+
+    assert_eq!(result, Done(&[][..], expected))
+  }
+}
@@ -0,0 +1,165 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+
+use parser::primitive::*;
+
+use nom::{HexDisplay,Needed,IResult,ErrorKind,FileProducer};
+use nom::{Consumer,ConsumerState};
+use nom::IResult::*;
+use nom::ErrorKind::Custom;
+
+use parser::errors::*;
+
+/*
+JoinGroupRequest => GroupId SessionTimeout [RebalanceTimeout] MemberId ProtocolType [GroupProtocols]
+  GroupId => string
+  SessionTimeout => int32
+  RebalanceTimeout => int32 (since v1)
+  MemberId => string
+  ProtocolType => string
+  GroupProtocols => ProtocolName ProtocolMetadata
+    ProtocolName => string
+    ProtocolMetadata => bytes
+*/
+
+#[derive(PartialEq,Debug)]
+pub struct JoinGroupRequest<'a> {
+  pub group_id: KafkaString<'a>,
+  pub session_timeout: i32,
+  // version 0 uses the session timeout
+  pub rebalance_timeout: i32,
+  pub member_id: KafkaString<'a>,
+  pub protocol_type: KafkaString<'a>,
+  pub group_protocols: Vec<GroupProtocol<'a>>
+}
+
+#[derive(PartialEq,Debug)]
+pub struct GroupProtocol<'a> {
+  pub protocol_name: KafkaString<'a>,
+  pub protocol_metadata: KafkaBytes<'a>
+}
+
+pub fn join_group_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], JoinGroupRequest<'a>, InputError> {
+  match api_version {
+    0 => join_group_request_v0(input),
+    1 => join_group_request_v1(input),
+    _ => InputError::ParserError.fail(),
+  }
+}
+
+pub fn join_group_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], JoinGroupRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    group_id: kafka_string >>
+    session_timeout: be_i32 >>
+    member_id: kafka_string >>
+    protocol_type: kafka_string >>
+    group_protocols: apply!(kafka_array, group_protocol) >>
+    (
+      JoinGroupRequest {
+        group_id,
+        session_timeout,
+        rebalance_timeout: session_timeout,
+        member_id,
+        protocol_type,
+        group_protocols,
+      }
+    )
+  )
+}
+
+pub fn join_group_request_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], JoinGroupRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    group_id: kafka_string >>
+    session_timeout: be_i32 >>
+    rebalance_timeout: be_i32 >>
+    member_id: kafka_string >>
+    protocol_type: kafka_string >>
+    group_protocols: apply!(kafka_array, group_protocol) >>
+    (
+      JoinGroupRequest {
+        group_id,
+        session_timeout,
+        rebalance_timeout,
+        member_id,
+        protocol_type,
+        group_protocols,
+      }
+    )
+  )
+}
+
+pub fn group_protocol<'a>(input:&'a [u8]) -> IResult<&'a [u8], GroupProtocol<'a>, InputError> {
+  do_parse!(
+    input,
+    protocol_name: kafka_string >>
+    protocol_metadata: kafka_bytes >>
+    (
+      GroupProtocol {
+        protocol_name,
+        protocol_metadata,
+      }
+    )
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nom::*;
+  use nom::IResult::*;
+
+  #[test]
+  fn join_group_request_v0_test() {
+    let input = &[
+      0x00, 0x01, 0x67,       // group_id = "g"
+      0x00, 0x00, 0x75, 0x30, // session_timeout = 30000
+      0x00, 0x00,             // member_id = ""
+      0x00, 0x08, 0x63, 0x6f, 0x6e, 0x73, 0x75, 0x6d, 0x65, 0x72, // protocol_type = "consumer"
+      0x00, 0x00, 0x00, 0x01, // group_protocols = 1
+        0x00, 0x05, 0x72, 0x61, 0x6e, 0x67, 0x65, // protocol_name = "range"
+        0x00, 0x00, 0x00, 0x02, 0x01, 0x02        // protocol_metadata = [1, 2]
+    ];
+
+    let result = join_group_request(input, 0);
+    let expected = JoinGroupRequest {
+      group_id: "g",
+      session_timeout: 30000,
+      rebalance_timeout: 30000,
+      member_id: "",
+      protocol_type: "consumer",
+      group_protocols: vec![GroupProtocol {
+        protocol_name: "range",
+        protocol_metadata: &[1, 2][..]
+      }]
+    };
+
+    assert_eq!(result, Done(&[][..], expected))
+  }
+
+  #[test]
+  fn join_group_request_v1_test() {
+    let input = &[
+      0x00, 0x01, 0x67,       // group_id = "g"
+      0x00, 0x00, 0x75, 0x30, // session_timeout = 30000
+      0x00, 0x00, 0xea, 0x60, // rebalance_timeout = 60000
+      0x00, 0x01, 0x6d,       // member_id = "m"
+      0x00, 0x00,             // protocol_type = ""
+      0x00, 0x00, 0x00, 0x00  // group_protocols = []
+    ];
+
+    let result = join_group_request(input, 1);
+    let expected = JoinGroupRequest {
+      group_id: "g",
+      session_timeout: 30000,
+      rebalance_timeout: 60000,
+      member_id: "m",
+      protocol_type: "",
+      group_protocols: vec![]
+    };
+
+    assert_eq!(result, Done(&[][..], expected));
+    assert!(join_group_request(input, 2).is_err());
+  }
+}
@@ -0,0 +1,66 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+
+use parser::primitive::*;
+use parser::errors::*;
+
+use nom::{HexDisplay,Needed,IResult,FileProducer};
+use nom::{Consumer,ConsumerState};
+use nom::IResult::*;
+use nom::ErrorKind::Custom;
+
+/*
+LeaveGroupRequest => GroupId MemberId
+  GroupId => string
+  MemberId => string
+*/
+
+#[derive(PartialEq,Debug)]
+pub struct LeaveGroupRequest<'a> {
+  pub group_id: KafkaString<'a>,
+  pub member_id: KafkaString<'a>
+}
+
+pub fn leave_group_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], LeaveGroupRequest<'a>, InputError> {
+  match api_version {
+    0 => leave_group_request_v0(input),
+    _ => InputError::ParserError.fail(),
+  }
+}
+
+pub fn leave_group_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], LeaveGroupRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    group_id: kafka_string >>
+    member_id: kafka_string >>
+    (
+      LeaveGroupRequest {
+        group_id,
+        member_id,
+      }
+    )
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nom::*;
+  use nom::IResult::*;
+
+  #[test]
+  fn leave_group_request_test() {
+    let input = &[
+      0x00, 0x01, 0x67, // group_id = "g"
+      0x00, 0x01, 0x6d  // member_id = "m"
+    ];
+
+    let result = leave_group_request(input, 0);
+    let expected = LeaveGroupRequest {
+      group_id: "g",
+      member_id: "m"
+    };
+
+    assert_eq!(result, Done(&[][..], expected))
+  }
+}
@@ -3,12 +3,10 @@
 
 use parser::primitive::*;
 
-use nom::{HexDisplay,Needed,IResult,ErrorKind,FileProducer, be_i8, be_i16, be_i32, be_i64};
+use nom::{HexDisplay,Needed,IResult,ErrorKind,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
 
-use crc::{crc32, Hasher32};
-
 use parser::errors::*;
 
 #[derive(PartialEq, Debug)]
@@ -17,7 +15,7 @@ pub struct TopicMessageSet<'a> {
     pub partitions: Vec<PartitionMessageSet<'a>>
 }
 
-pub fn topic_message_set<'a>(input: &'a [u8]) -> IResult<&'a [u8], TopicMessageSet<'a>> {
+pub fn topic_message_set<'a>(input: &'a [u8]) -> IResult<&'a [u8], TopicMessageSet<'a>, InputError> {
   do_parse!(
     input,
     topic_name: kafka_string >>
@@ -34,39 +32,72 @@ pub fn topic_message_set<'a>(input: &'a [u8]) -> IResult<&'a [u8], TopicMessageS
 #[derive(PartialEq, Debug)]
 pub struct PartitionMessageSet<'a> {
     pub partition: i32,
-    pub message_set: MessageSet<'a>
+    pub records: Records<'a>,
+    // the message set as sent, to check its checksums
+    pub records_bytes: &'a [u8]
 }
 
-pub fn partition_message_set<'a>(input: &'a [u8]) -> IResult<&'a [u8], PartitionMessageSet<'a>> {
+pub fn partition_message_set<'a>(input: &'a [u8]) -> IResult<&'a [u8], PartitionMessageSet<'a>, InputError> {
   do_parse!(
     input,
     partition: be_i32 >>
     message_set_size: be_i32 >>
-    message_set: apply!(message_set, message_set_size) >>
+    records: apply!(records, message_set_size) >>
     (
       PartitionMessageSet {
         partition,
-        message_set,
+        records,
+        records_bytes: &input[8..8 + message_set_size as usize],
       }
     )
   )
 }
 
+// legacy messages and record batches both have their magic byte after
+// the offset, the size, and a 4 bytes field (crc or partition leader epoch)
+pub const MAGIC_POSITION: usize = 16;
+
+// a message set is sent either as legacy messages (magic 0 and 1),
+// or as record batches (magic 2) since Kafka 0.11
+#[derive(PartialEq, Debug)]
+pub enum Records<'a> {
+  MessageSet(MessageSet<'a>),
+  RecordBatches(Vec<RecordBatch<'a>>)
+}
+
+pub fn records<'a>(input: &'a [u8], size: i32) -> IResult<&'a [u8], Records<'a>, InputError> {
+  let records_bytes = |i: &'a [u8]| {
+    if size >= 0 {
+      take_bytes(i, size as usize)
+    } else {
+      InputError::InvalidMessageSetSize.fail()
+    }
+  };
+
+  flat_map!(input, records_bytes, |rb: &'a [u8]| {
+    if rb.len() > MAGIC_POSITION && rb[MAGIC_POSITION] == 2 {
+      map!(rb, record_batches, Records::RecordBatches)
+    } else {
+      map!(rb, message_set_messages, Records::MessageSet)
+    }
+  })
+}
+
 pub type MessageSet<'a> = Vec<OMsMessage<'a>>;
 
-pub fn message_set<'a>(input: &'a [u8], size: i32) -> IResult<&'a [u8], MessageSet<'a>> {
+pub fn message_set<'a>(input: &'a [u8], size: i32) -> IResult<&'a [u8], MessageSet<'a>, InputError> {
   let ms_bytes = |i: &'a [u8]| {
     if size >= 0 {
-      take!(i, size as usize)
+      take_bytes(i, size as usize)
     } else {
-      Error(ErrorKind::Custom(InputError::InvalidMessageSetSize.to_int()))
+      InputError::InvalidMessageSetSize.fail()
     }
   };
 
   flat_map!(input, ms_bytes, message_set_messages)
 }
 
-pub fn message_set_message<'a>(input: &'a [u8]) -> IResult<&'a [u8], MessageSet<'a>> {
+pub fn message_set_message<'a>(input: &'a [u8]) -> IResult<&'a [u8], MessageSet<'a>, InputError> {
   do_parse!(
     input,
     m: o_ms_message >>
@@ -79,7 +110,7 @@ pub fn message_set_message<'a>(input: &'a [u8]) -> IResult<&'a [u8], MessageSet<
   )
 }
 
-pub fn message_set_messages<'a>(input: &'a [u8]) -> IResult<&'a [u8], MessageSet<'a>> {
+pub fn message_set_messages<'a>(input: &'a [u8]) -> IResult<&'a [u8], MessageSet<'a>, InputError> {
   alt!(input,
     eof!() =>  { |_| vec![] }
     | message_set_message)
@@ -91,7 +122,7 @@ pub struct OMsMessage<'a> {
     pub message: Message<'a>
 }
 
-pub fn o_ms_message<'a>(input: &'a [u8]) -> IResult<&'a [u8], OMsMessage<'a>> {
+pub fn o_ms_message<'a>(input: &'a [u8]) -> IResult<&'a [u8], OMsMessage<'a>, InputError> {
   do_parse!(
     input,
     offset: be_i64 >>
@@ -106,57 +137,50 @@ pub fn o_ms_message<'a>(input: &'a [u8]) -> IResult<&'a [u8], OMsMessage<'a>> {
   )
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Message<'a> {
   pub magic_byte: i8,
   pub attributes: i8,
-  pub key: &'a [u8],
-  pub value: &'a [u8]
+  // since magic byte 1, in milliseconds
+  pub timestamp: Option<i64>,
+  // null keys are common, null values mark deletions
+  pub key: KafkaNullableBytes<'a>,
+  pub value: KafkaNullableBytes<'a>
 }
 
-pub fn message<'a>(input: &'a [u8], size: i32) -> IResult<&'a [u8], Message<'a>> {
-  let sz = size as usize; // Only valid if size >= 0
+// attributes of legacy messages
+pub const MESSAGE_COMPRESSION_CODEC_MASK: i8 = 0x07;
+pub const MESSAGE_TIMESTAMP_TYPE_MASK: i8    = 0x08;
 
+pub fn message<'a>(input: &'a [u8], size: i32) -> IResult<&'a [u8], Message<'a>, InputError> {
   let message_bytes = |i: &'a [u8]| {
     if size >= 0 {
-      take!(i, sz)
+      take_bytes(i, size as usize)
     } else {
-      Error(ErrorKind::Custom(InputError::InvalidMessageSize.to_int()))
+      InputError::InvalidMessageSize.fail()
     }
   };
 
-
-  // TODO make the code more robust / simple
-  // Right now the code in `crc_parser` is based on asumptions valid only because `message_bytes`
-  // is before in the parser do_parse
-  let crc_parser = |i: &'a [u8]| {
-    // The message_bytes parser has already checked the bound>>
-    let computed_crc = crc32::checksum_ieee(&input[4..sz]);
-    flat_map!(i, be_i32, |given_crc| {
-      if given_crc as u32 == computed_crc {
-        Done(i, given_crc as u32)
-      } else {
-        Error(ErrorKind::Custom(InputError::InvalidMessage.to_int()))
-      }
-    })
-  };
-
-  flat_map!(input, message_bytes, |mb| foo(mb, &crc_parser))
+  flat_map!(input, message_bytes, |mb: &'a [u8]| complete!(mb, message_body))
 }
 
-fn foo<'a>(mb: &'a [u8], crc_parser: &Fn(&'a [u8]) -> IResult<&'a [u8], u32>) -> IResult<&'a [u8], Message<'a>> {
+// the CRC is left to the `checksum` module, so that a corrupted message
+// can be refused without failing the parsing of the whole request
+fn message_body<'a>(mb: &'a [u8]) -> IResult<&'a [u8], Message<'a>, InputError> {
   do_parse!(
     mb,
-    crc_parser >>
+    be_u32 >>
     magic_byte: be_i8 >>
     attributes: be_i8 >>
-    key: kafka_bytes >>
-    value: kafka_bytes >>
+    timestamp: apply!(message_timestamp, magic_byte) >>
+    key: kafka_nullable_bytes >>
+    value: kafka_nullable_bytes >>
     eof!() >>
     (
       Message {
         magic_byte,
         attributes,
+        timestamp,
         key,
         value,
       }
@@ -164,6 +188,207 @@ fn foo<'a>(mb: &'a [u8], crc_parser: &Fn(&'a [u8]) -> IResult<&'a [u8], u32>) ->
   )
 }
 
+// only messages of magic 1 have a timestamp
+fn message_timestamp(input: &[u8], magic_byte: i8) -> IResult<&[u8], Option<i64>, InputError> {
+  if magic_byte == 1 {
+    map!(input, be_i64, Some)
+  } else {
+    Done(input, None)
+  }
+}
+
+// attributes of record batches
+pub const COMPRESSION_CODEC_MASK: i16 = 0x07;
+pub const TIMESTAMP_TYPE_MASK: i16    = 0x08;
+pub const TRANSACTIONAL_MASK: i16     = 0x10;
+pub const CONTROL_MASK: i16           = 0x20;
+
+// size of a record batch without records, from the partition leader epoch
+pub const RECORD_BATCH_OVERHEAD: usize = 49;
+
+/*
+RecordBatch => BaseOffset Length PartitionLeaderEpoch Magic Crc Attributes LastOffsetDelta
+               FirstTimestamp MaxTimestamp ProducerId ProducerEpoch BaseSequence [Record]
+  BaseOffset => int64
+  Length => int32
+  PartitionLeaderEpoch => int32
+  Magic => int8 (2)
+  Crc => uint32, CRC-32C of the batch from the attributes
+  Attributes => int16
+  LastOffsetDelta => int32
+  FirstTimestamp => int64
+  MaxTimestamp => int64
+  ProducerId => int64
+  ProducerEpoch => int16
+  BaseSequence => int32
+*/
+
+#[derive(PartialEq, Debug)]
+pub struct RecordBatch<'a> {
+  pub base_offset: i64,
+  pub partition_leader_epoch: i32,
+  pub attributes: i16,
+  pub last_offset_delta: i32,
+  pub first_timestamp: i64,
+  pub max_timestamp: i64,
+  pub producer_id: i64,
+  pub producer_epoch: i16,
+  pub base_sequence: i32,
+  pub record_count: i32,
+  // serialized records, compressed with the codec set in the attributes.
+  // Use `batch_records` to parse them
+  pub records: &'a [u8]
+}
+
+pub fn record_batches<'a>(input: &'a [u8]) -> IResult<&'a [u8], Vec<RecordBatch<'a>>, InputError> {
+  alt!(input,
+    eof!() => { |_| vec![] }
+    | do_parse!(
+        batch: record_batch >>
+        rest: record_batches >>
+        ({
+          let mut a = vec![batch];
+          a.extend(rest);
+          a
+        })
+      ))
+}
+
+pub fn record_batch<'a>(input: &'a [u8]) -> IResult<&'a [u8], RecordBatch<'a>, InputError> {
+  do_parse!(
+    input,
+    base_offset: be_i64 >>
+    length: be_i32 >>
+    batch: apply!(record_batch_body, base_offset, length) >>
+    (batch)
+  )
+}
+
+fn record_batch_body<'a>(input: &'a [u8], base_offset: i64, length: i32) -> IResult<&'a [u8], RecordBatch<'a>, InputError> {
+  if length < RECORD_BATCH_OVERHEAD as i32 {
+    return InputError::InvalidMessageSize.fail();
+  }
+
+  flat_map!(input, apply!(take_bytes, length as usize), |b: &'a [u8]| {
+    do_parse!(
+      b,
+      partition_leader_epoch: be_i32 >>
+      magic: verify!(be_i8, |m| m == 2) >>
+      be_u32 >>
+      attributes: be_i16 >>
+      last_offset_delta: be_i32 >>
+      first_timestamp: be_i64 >>
+      max_timestamp: be_i64 >>
+      producer_id: be_i64 >>
+      producer_epoch: be_i16 >>
+      base_sequence: be_i32 >>
+      record_count: be_i32 >>
+      records: rest >>
+      (
+        RecordBatch {
+          base_offset,
+          partition_leader_epoch,
+          attributes,
+          last_offset_delta,
+          first_timestamp,
+          max_timestamp,
+          producer_id,
+          producer_epoch,
+          base_sequence,
+          record_count,
+          records,
+        }
+      )
+    )
+  })
+}
+
+/*
+Record => Length Attributes TimestampDelta OffsetDelta Key Value [Header]
+  Length => varint
+  Attributes => int8
+  TimestampDelta => varlong
+  OffsetDelta => varint
+  Key => varint length, then bytes, -1 for null
+  Value => varint length, then bytes, -1 for null
+  Header => HeaderKey HeaderValue
+    HeaderKey => varint length, then string
+    HeaderValue => varint length, then bytes, -1 for null
+*/
+
+#[derive(PartialEq, Debug)]
+pub struct Record<'a> {
+  pub attributes: i8,
+  pub timestamp_delta: i64,
+  pub offset_delta: i32,
+  pub key: Option<&'a [u8]>,
+  pub value: Option<&'a [u8]>,
+  pub headers: Vec<Header<'a>>
+}
+
+pub type Header<'a> = (&'a str, Option<&'a [u8]>);
+
+// parses the records of an uncompressed batch
+pub fn batch_records<'a>(batch: &RecordBatch<'a>) -> IResult<&'a [u8], Vec<Record<'a>>, InputError> {
+  if batch.attributes & COMPRESSION_CODEC_MASK != 0 {
+    return InputError::InvalidMessage.fail();
+  }
+
+  batch_records_from(batch.records, batch.record_count)
+}
+
+// parses `record_count` records filling `input`, like the decompressed
+// records of a compressed batch
+pub fn batch_records_from<'a>(input: &'a [u8], record_count: i32) -> IResult<&'a [u8], Vec<Record<'a>>, InputError> {
+  if record_count < 0 {
+    return InputError::InvalidMessage.fail();
+  }
+
+  do_parse!(
+    input,
+    records: apply!(count, record, record_count as usize) >>
+    eof!() >>
+    (records)
+  )
+}
+
+pub fn record<'a>(input: &'a [u8]) -> IResult<&'a [u8], Record<'a>, InputError> {
+  do_parse!(
+    input,
+    length: verify!(varint, |l| l >= 0) >>
+    record: flat_map!(apply!(take_bytes, length as usize), record_body) >>
+    (record)
+  )
+}
+
+fn record_body<'a>(input: &'a [u8]) -> IResult<&'a [u8], Record<'a>, InputError> {
+  do_parse!(
+    input,
+    attributes: be_i8 >>
+    timestamp_delta: varlong >>
+    offset_delta: varint >>
+    key: varint_bytes >>
+    value: varint_bytes >>
+    header_count: verify!(varint, |c| c >= 0) >>
+    headers: apply!(count, header, header_count as usize) >>
+    eof!() >>
+    (
+      Record {
+        attributes,
+        timestamp_delta,
+        offset_delta,
+        key,
+        value,
+        headers,
+      }
+    )
+  )
+}
+
+fn header(input: &[u8]) -> IResult<&[u8], Header<'_>, InputError> {
+  pair!(input, varint_string, varint_bytes)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -192,15 +417,17 @@ mod tests {
         topic_name: "",
         partitions: vec![PartitionMessageSet {
           partition: 1,
-          message_set: vec![OMsMessage {
+          records: Records::MessageSet(vec![OMsMessage {
             offset: 1,
             message: Message {
               magic_byte: 0,
               attributes: 0,
-              key: &[][..],
-              value: &[][..]
+              timestamp: None,
+              key: Some(&[][..]),
+              value: Some(&[][..])
             }
-          }]
+          }]),
+          records_bytes: &input[14..]
         }]
       };
 
@@ -223,15 +450,17 @@ mod tests {
       let result = partition_message_set(input);
       let expected = PartitionMessageSet {
         partition: 1,
-        message_set: vec![OMsMessage {
+        records: Records::MessageSet(vec![OMsMessage {
           offset: 1,
           message: Message {
             magic_byte: 0,
             attributes: 0,
-            key: &[][..],
-            value: &[][..]
+            timestamp: None,
+            key: Some(&[][..]),
+            value: Some(&[][..])
           }
-        }]
+        }]),
+        records_bytes: &input[8..]
       };
 
       assert_eq!(result, Done(&[][..], expected))
@@ -255,8 +484,9 @@ mod tests {
           message: Message {
             magic_byte: 0,
             attributes: 0,
-            key: &[][..],
-            value: &[][..]
+            timestamp: None,
+            key: Some(&[][..]),
+            value: Some(&[][..])
           }
         }
       ];
@@ -283,8 +513,9 @@ mod tests {
           message: Message {
             magic_byte: 0,
             attributes: 0,
-            key: &[][..],
-            value: &[][..]
+            timestamp: None,
+            key: Some(&[][..]),
+            value: Some(&[][..])
           }
         }
       ];
@@ -337,8 +568,52 @@ mod tests {
       let expected = Message {
         magic_byte: 0,
         attributes: 0,
-        key: &[][..],
-        value: &[][..]
+        timestamp: None,
+        key: Some(&[][..]),
+        value: Some(&[][..])
+      };
+
+      assert_eq!(result, Done(&[][..], expected))
+  }
+
+  #[test]
+  fn message_v1_tests() {
+      let input = &[
+        0x84, 0xc2, 0xac, 0x30,                         // crc
+        0x01,                                           // magic_byte = 1
+        0x08,                                           // attributes = LogAppendTime
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xe8, // timestamp = 1000
+        0x00, 0x00, 0x00, 0x00,                         // key = []
+        0x00, 0x00, 0x00, 0x01, 0x61                    // value = "a"
+      ];
+      let result = message(input, 23);
+      let expected = Message {
+        magic_byte: 1,
+        attributes: MESSAGE_TIMESTAMP_TYPE_MASK,
+        timestamp: Some(1000),
+        key: Some(&[][..]),
+        value: Some(&b"a"[..])
+      };
+
+      assert_eq!(result, Done(&[][..], expected))
+  }
+
+  #[test]
+  fn message_null_key_tests() {
+      let input = &[
+        0x00, 0x00, 0x00, 0x00, // crc, checked elsewhere
+        0x00,                   // magic_byte = 0
+        0x00,                   // attributes = 0
+        0xff, 0xff, 0xff, 0xff, // key = null
+        0xff, 0xff, 0xff, 0xff  // value = null, a tombstone
+      ];
+      let result = message(input, 14);
+      let expected = Message {
+        magic_byte: 0,
+        attributes: 0,
+        timestamp: None,
+        key: None,
+        value: None
       };
 
       assert_eq!(result, Done(&[][..], expected))
@@ -358,8 +633,9 @@ mod tests {
       let expected = Message {
         magic_byte: 0,
         attributes: 0,
-        key: &[][..],
-        value: &[][..]
+        timestamp: None,
+        key: Some(&[][..]),
+        value: Some(&[][..])
       };
 
       assert_eq!(result, Done(&[0x00, 0x00, 0x00, 0x00][..], expected))
@@ -390,8 +666,8 @@ mod tests {
       ];
       let result = message(input, 12);
 
-      // The CRC doesn't include the last two bytes so the check fails
-      assert_eq!(result, Error(ErrorKind::Custom(InputError::InvalidMessage.to_int())));
+      // the message size cuts the value
+      assert_eq!(result, Error(ErrorKind::Complete));
   }
 
   #[test]
@@ -405,6 +681,42 @@ mod tests {
       ];
       let result = message(input, 14);
 
-      assert_eq!(result, Error(ErrorKind::Custom(InputError::InvalidMessage.to_int())));
+      // the CRC is checked by the `checksum` module
+      assert!(result.is_done());
+  }
+
+  #[test]
+  fn records_dispatch_test() {
+      use responses::fetch::ser_record_batch;
+
+      let batch = || RecordBatch {
+        base_offset: 0,
+        partition_leader_epoch: 0,
+        attributes: 0,
+        last_offset_delta: 0,
+        first_timestamp: 0,
+        max_timestamp: 0,
+        producer_id: -1,
+        producer_epoch: -1,
+        base_sequence: -1,
+        record_count: 1,
+        records: &[0x0e, 0x00, 0x00, 0x00, 0x01, 0x02, 0x61, 0x00][..]
+      };
+      let mut input: Vec<u8> = vec![];
+      ser_record_batch(&batch(), &mut input);
+      ser_record_batch(&batch(), &mut input);
+
+      let size = input.len() as i32;
+      assert_eq!(records(&input, size), Done(&[][..], Records::RecordBatches(vec![batch(), batch()])));
+
+      let batches = records(&input, size).unwrap().1;
+      if let Records::RecordBatches(ref b) = batches {
+        assert_eq!(batch_records(&b[0]), Done(&[][..], vec![Record {
+          attributes: 0, timestamp_delta: 0, offset_delta: 0, key: None, value: Some(&b"a"[..]), headers: vec![]
+        }]));
+      }
+
+      // a truncated batch is not taken for legacy messages
+      assert!(!records(&input[..size as usize - 1], size - 1).is_done());
   }
 }
@@ -2,14 +2,23 @@
 #![allow(unused_imports)]
 
 use parser::primitive::*;
+use parser::errors::*;
 
 use nom::{HexDisplay,Needed,IResult,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
+use nom::ErrorKind::Custom;
 
 pub type TopicMetadataRequest<'a> = Vec<KafkaString<'a>>;
 
-pub fn topic_metadata_request<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicMetadataRequest<'a>> {
+pub fn topic_metadata_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], TopicMetadataRequest<'a>, InputError> {
+  match api_version {
+    0 => topic_metadata_request_v0(input),
+    _ => InputError::ParserError.fail(),
+  }
+}
+
+pub fn topic_metadata_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicMetadataRequest<'a>, InputError> {
   kafka_array(input, kafka_string)
 }
 
@@ -24,7 +33,7 @@ mod tests {
       let input = &[
         0x00, 0x00, 0x00, 0x01, 0x00, 0x00  //  [""]
       ];
-      let result = topic_metadata_request(input);
+      let result = topic_metadata_request(input, 0);
       let expected = vec![""];
 
       assert_eq!(result, Done(&[][..], expected))
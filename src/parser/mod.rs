@@ -9,4 +9,9 @@ pub mod metadata;
 pub mod offset_commit;
 pub mod offset_fetch;
 pub mod consumer_metadata;
+pub mod join_group;
+pub mod heartbeat;
+pub mod leave_group;
+pub mod sync_group;
+pub mod api_versions;
 // pub mod zookeeper;
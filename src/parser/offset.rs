@@ -2,19 +2,29 @@
 #![allow(unused_imports)]
 
 use parser::primitive::*;
+use parser::errors::*;
 
-use nom::{HexDisplay,Needed,IResult,FileProducer, be_i16, be_i32, be_i64};
+use nom::{HexDisplay,Needed,IResult,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
+use nom::ErrorKind::Custom;
 use nom::ErrorKind;
 
 #[derive(PartialEq,Debug)]
 pub struct OffsetRequest<'a> {
-  replica_id: i32,
-  topics: Vec<TopicOffset<'a>>
+  pub replica_id: i32,
+  pub topics: Vec<TopicOffset<'a>>
 }
 
-pub fn offset_request<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetRequest<'a>> {
+pub fn offset_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], OffsetRequest<'a>, InputError> {
+  match api_version {
+    0 => offset_request_v0(input),
+    1 => offset_request_v1(input),
+    _ => InputError::ParserError.fail(),
+  }
+}
+
+pub fn offset_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetRequest<'a>, InputError> {
   do_parse!(
     input,
     replica_id: be_i32 >>
@@ -28,13 +38,28 @@ pub fn offset_request<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetRequest<'a>
   )
 }
 
+// version 1 asks for a single offset per partition, with its timestamp
+pub fn offset_request_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    replica_id: be_i32 >>
+    topics: apply!(kafka_array, topic_offset_v1) >>
+    (
+      OffsetRequest {
+        replica_id,
+        topics,
+      }
+    )
+  )
+}
+
 #[derive(PartialEq,Debug)]
 pub struct TopicOffset<'a> {
-  topic_name: KafkaString<'a>,
-  partitions: Vec<PartitionOffset>
+  pub topic_name: KafkaString<'a>,
+  pub partitions: Vec<PartitionOffset>
 }
 
-pub fn topic_offset<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffset<'a>> {
+pub fn topic_offset<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffset<'a>, InputError> {
   do_parse!(
     input,
     topic_name: kafka_string >>
@@ -48,14 +73,28 @@ pub fn topic_offset<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffset<'a>> {
   )
 }
 
+pub fn topic_offset_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffset<'a>, InputError> {
+  do_parse!(
+    input,
+    topic_name: kafka_string >>
+    partitions: apply!(kafka_array, partition_offset_v1) >>
+    (
+      TopicOffset {
+        topic_name,
+        partitions,
+      }
+    )
+  )
+}
+
 #[derive(PartialEq,Debug)]
 pub struct PartitionOffset {
-  partition: i32,
-  time: i64,
-  max_number_of_offsets: i32
+  pub partition: i32,
+  pub time: i64,
+  pub max_number_of_offsets: i32
 }
 
-pub fn partition_offset<'a>(input:&'a [u8]) -> IResult<&'a [u8], PartitionOffset> {
+pub fn partition_offset(input:&[u8]) -> IResult<&[u8], PartitionOffset, InputError> {
   do_parse!(
     input,
     partition: be_i32 >>
@@ -71,6 +110,21 @@ pub fn partition_offset<'a>(input:&'a [u8]) -> IResult<&'a [u8], PartitionOffset
   )
 }
 
+pub fn partition_offset_v1(input:&[u8]) -> IResult<&[u8], PartitionOffset, InputError> {
+  do_parse!(
+    input,
+    partition: be_i32 >>
+    time: be_i64 >>
+    (
+      PartitionOffset {
+        partition,
+        time,
+        max_number_of_offsets: 1,
+      }
+    )
+  )
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -88,7 +142,7 @@ mod tests {
                 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // time = 0
                 0x00, 0x00, 0x00, 0x00                          // max_number_of_offsets = 0
       ];
-      let result = offset_request(input);
+      let result = offset_request(input, 0);
       let expected = OffsetRequest {
         replica_id: 0,
         topics: vec![
@@ -107,4 +161,34 @@ mod tests {
 
       assert_eq!(result, Done(&[][..], expected))
   }
+
+  #[test]
+  fn offset_request_v1_tests() {
+      let input = &[
+        0xff, 0xff, 0xff, 0xff, // replica_id = -1
+        0x00, 0x00, 0x00, 0x01, // topics array length
+            0x00, 0x00,             // topic_name = ""
+            0x00, 0x00, 0x00, 0x01, // partitions array length
+                0x00, 0x00, 0x00, 0x02,                         // partition = 2
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xe8  // time = 1000
+      ];
+      let result = offset_request(input, 1);
+      let expected = OffsetRequest {
+        replica_id: -1,
+        topics: vec![
+          TopicOffset {
+            topic_name: "",
+            partitions: vec![
+              PartitionOffset {
+                partition: 2,
+                time: 1000,
+                max_number_of_offsets: 1
+              }
+            ]
+          }
+        ]
+      };
+
+      assert_eq!(result, Done(&[][..], expected))
+  }
 }
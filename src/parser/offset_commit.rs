@@ -3,7 +3,7 @@
 
 use parser::primitive::*;
 
-use nom::{HexDisplay,Needed,IResult,ErrorKind,FileProducer, be_i16, be_i32, be_i64};
+use nom::{HexDisplay,Needed,IResult,ErrorKind,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
 use nom::ErrorKind::Custom;
@@ -17,23 +17,23 @@ pub enum OffsetCommitRequest<'a> {
   V2(OffsetCommitRequestV2<'a>)
 }
 
-pub fn offset_commit_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], OffsetCommitRequest<'a>> {
+pub fn offset_commit_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], OffsetCommitRequest<'a>, InputError> {
   match api_version {
     0 => map!(input, offset_commit_request_v0, |p| { OffsetCommitRequest::V0(p) }),
     1 => map!(input, offset_commit_request_v1, |p| { OffsetCommitRequest::V1(p) }),
     2 => map!(input, offset_commit_request_v2, |p| { OffsetCommitRequest::V2(p) }),
-    _ => Error(Custom(InputError::ParserError.to_int())),
+    _ => InputError::ParserError.fail(),
   }
 }
 
 // v0
 #[derive(PartialEq,Debug)]
 pub struct OffsetCommitRequestV0<'a> {
-  consumer_group: KafkaString<'a>,
-  topics: Vec<TopicOffsetCommitV0<'a>>
+  pub consumer_group: KafkaString<'a>,
+  pub topics: Vec<TopicOffsetCommitV0<'a>>
 }
 
-pub fn offset_commit_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetCommitRequestV0<'a>> {
+pub fn offset_commit_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetCommitRequestV0<'a>, InputError> {
   do_parse!(
     input,
     consumer_group: kafka_string >>
@@ -49,11 +49,11 @@ pub fn offset_commit_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetC
 
 #[derive(PartialEq,Debug)]
 pub struct TopicOffsetCommitV0<'a> {
-  topic_name: KafkaString<'a>,
-  partitions: Vec<PartitionOffsetCommitV0<'a>>
+  pub topic_name: KafkaString<'a>,
+  pub partitions: Vec<PartitionOffsetCommitV0<'a>>
 }
 
-pub fn topic_offset_commit_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffsetCommitV0<'a>> {
+pub fn topic_offset_commit_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffsetCommitV0<'a>, InputError> {
   do_parse!(
     input,
     topic_name: kafka_string >>
@@ -69,12 +69,12 @@ pub fn topic_offset_commit_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffs
 
 #[derive(PartialEq,Debug)]
 pub struct PartitionOffsetCommitV0<'a> {
-  partition: i32,
-  offset: i64,
-  metadata: KafkaString<'a>
+  pub partition: i32,
+  pub offset: i64,
+  pub metadata: KafkaString<'a>
 }
 
-pub fn partition_offset_commit_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], PartitionOffsetCommitV0> {
+pub fn partition_offset_commit_v0(input:&[u8]) -> IResult<&[u8], PartitionOffsetCommitV0<'_>, InputError> {
   do_parse!(
     input,
     partition: be_i32 >>
@@ -93,13 +93,13 @@ pub fn partition_offset_commit_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], Parti
 // v1
 #[derive(PartialEq,Debug)]
 pub struct OffsetCommitRequestV1<'a> {
-  consumer_group: KafkaString<'a>,
-  consumer_group_generation_id: i32,
-  consumer_id: KafkaString<'a>,
-  topics: Vec<TopicOffsetCommitV1<'a>>
+  pub consumer_group: KafkaString<'a>,
+  pub consumer_group_generation_id: i32,
+  pub consumer_id: KafkaString<'a>,
+  pub topics: Vec<TopicOffsetCommitV1<'a>>
 }
 
-pub fn offset_commit_request_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetCommitRequestV1<'a>> {
+pub fn offset_commit_request_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetCommitRequestV1<'a>, InputError> {
   do_parse!(
     input,
     consumer_group: kafka_string >>
@@ -119,11 +119,11 @@ pub fn offset_commit_request_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetC
 
 #[derive(PartialEq,Debug)]
 pub struct TopicOffsetCommitV1<'a> {
-  topic_name: KafkaString<'a>,
-  partitions: Vec<PartitionOffsetCommitV1<'a>>
+  pub topic_name: KafkaString<'a>,
+  pub partitions: Vec<PartitionOffsetCommitV1<'a>>
 }
 
-pub fn topic_offset_commit_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffsetCommitV1<'a>> {
+pub fn topic_offset_commit_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffsetCommitV1<'a>, InputError> {
   do_parse!(
     input,
     topic_name: kafka_string >>
@@ -139,13 +139,13 @@ pub fn topic_offset_commit_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffs
 
 #[derive(PartialEq,Debug)]
 pub struct PartitionOffsetCommitV1<'a> {
-  partition: i32,
-  offset: i64,
-  timestamp: i64,
-  metadata: KafkaString<'a>
+  pub partition: i32,
+  pub offset: i64,
+  pub timestamp: i64,
+  pub metadata: KafkaString<'a>
 }
 
-pub fn partition_offset_commit_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], PartitionOffsetCommitV1> {
+pub fn partition_offset_commit_v1(input:&[u8]) -> IResult<&[u8], PartitionOffsetCommitV1<'_>, InputError> {
   do_parse!(
     input,
     partition: be_i32 >>
@@ -154,10 +154,10 @@ pub fn partition_offset_commit_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], Parti
     metadata: kafka_string >>
     (
       PartitionOffsetCommitV1 {
-        partition: partition,
-        offset: offset,
-        timestamp: timestamp,
-        metadata: metadata,
+        partition,
+        offset,
+        timestamp,
+        metadata,
       }
     )
   )
@@ -166,14 +166,14 @@ pub fn partition_offset_commit_v1<'a>(input:&'a [u8]) -> IResult<&'a [u8], Parti
 // v2
 #[derive(PartialEq,Debug)]
 pub struct OffsetCommitRequestV2<'a> {
-  consumer_group: KafkaString<'a>,
-  consumer_group_generation_id: i32,
-  consumer_id: KafkaString<'a>,
-  retention_time: i64,
-  topics: Vec<TopicOffsetCommitV0<'a>>
+  pub consumer_group: KafkaString<'a>,
+  pub consumer_group_generation_id: i32,
+  pub consumer_id: KafkaString<'a>,
+  pub retention_time: i64,
+  pub topics: Vec<TopicOffsetCommitV0<'a>>
 }
 
-pub fn offset_commit_request_v2<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetCommitRequestV2<'a>> {
+pub fn offset_commit_request_v2<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetCommitRequestV2<'a>, InputError> {
   do_parse!(
     input,
     consumer_group: kafka_string >>
@@ -2,18 +2,29 @@
 #![allow(unused_imports)]
 
 use parser::primitive::*;
+use parser::errors::*;
 
-use nom::{HexDisplay,Needed,IResult,FileProducer, be_i16, be_i32, be_i64};
+use nom::{HexDisplay,Needed,IResult,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
+use nom::ErrorKind::Custom;
 
 #[derive(PartialEq,Debug)]
 pub struct OffsetFetchRequest<'a> {
-  consumer_group: KafkaString<'a>,
-  topics: Vec<TopicOffsetFetch<'a>>
+  pub consumer_group: KafkaString<'a>,
+  pub topics: Vec<TopicOffsetFetch<'a>>
 }
 
-pub fn offset_fetch_request<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetFetchRequest<'a>> {
+pub fn offset_fetch_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], OffsetFetchRequest<'a>, InputError> {
+  // version 1 reads offsets committed to Kafka rather than ZooKeeper,
+  // which is the same storage here
+  match api_version {
+    0 | 1 => offset_fetch_request_v0(input),
+    _ => InputError::ParserError.fail(),
+  }
+}
+
+pub fn offset_fetch_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetFetchRequest<'a>, InputError> {
   do_parse!(
     input,
     consumer_group: kafka_string >>
@@ -29,11 +40,11 @@ pub fn offset_fetch_request<'a>(input:&'a [u8]) -> IResult<&'a [u8], OffsetFetch
 
 #[derive(PartialEq,Debug)]
 pub struct TopicOffsetFetch<'a> {
-  topic_name: KafkaString<'a>,
-  partitions: Vec<PartitionOffsetFetch>
+  pub topic_name: KafkaString<'a>,
+  pub partitions: Vec<PartitionOffsetFetch>
 }
 
-pub fn topic_offset_fetch<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffsetFetch<'a>> {
+pub fn topic_offset_fetch<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffsetFetch<'a>, InputError> {
   do_parse!(
     input,
     topic_name: kafka_string >>
@@ -49,10 +60,10 @@ pub fn topic_offset_fetch<'a>(input:&'a [u8]) -> IResult<&'a [u8], TopicOffsetFe
 
 #[derive(PartialEq,Debug)]
 pub struct PartitionOffsetFetch {
-  partition: i32
+  pub partition: i32
 }
 
-pub fn partition_offset_fetch<'a>(input:&'a [u8]) -> IResult<&'a [u8], PartitionOffsetFetch> {
+pub fn partition_offset_fetch(input:&[u8]) -> IResult<&[u8], PartitionOffsetFetch, InputError> {
   map!(input, be_i32, |p| { PartitionOffsetFetch { partition: p } })
 }
 
@@ -78,7 +89,7 @@ OffsetFetchRequest => ConsumerGroup [TopicName [Partition]]
             0x00, 0x00, 0x00, 0x01, // partitions array length
                 0x00, 0x00, 0x00, 0x00 // partition = 0
       ];
-      let result = offset_fetch_request(input);
+      let result = offset_fetch_request(input, 0);
       let expected = OffsetFetchRequest {
         consumer_group: "",
         topics: vec![
@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
-use nom::{HexDisplay,Needed,IResult,ErrorKind,FileProducer,be_i8,be_i16,be_i32,be_i64};
+use nom::{HexDisplay,Needed,IResult,ErrorKind,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
 use nom::ErrorKind::Custom;
@@ -27,20 +27,73 @@ pub type KafkaInt64 = i64;
 pub type KafkaBytes<'a> = &'a [u8];
 pub type KafkaString<'a> = &'a str;
 
-pub fn kafka_bytes<'a>(input:&'a [u8]) -> IResult<&'a [u8], KafkaBytes<'a>> {
+pub type KafkaNullableBytes<'a> = Option<&'a [u8]>;
+pub type KafkaNullableString<'a> = Option<&'a str>;
+
+// nom's number parsers, failing with InputError
+pub fn be_i8(input: &[u8]) -> IResult<&[u8], i8, InputError> {
+  fix_error!(input, InputError, call!(::nom::be_i8))
+}
+
+pub fn be_i16(input: &[u8]) -> IResult<&[u8], i16, InputError> {
+  fix_error!(input, InputError, call!(::nom::be_i16))
+}
+
+pub fn be_i32(input: &[u8]) -> IResult<&[u8], i32, InputError> {
+  fix_error!(input, InputError, call!(::nom::be_i32))
+}
+
+pub fn be_i64(input: &[u8]) -> IResult<&[u8], i64, InputError> {
+  fix_error!(input, InputError, call!(::nom::be_i64))
+}
+
+pub fn be_u8(input: &[u8]) -> IResult<&[u8], u8, InputError> {
+  fix_error!(input, InputError, call!(::nom::be_u8))
+}
+
+pub fn be_u32(input: &[u8]) -> IResult<&[u8], u32, InputError> {
+  fix_error!(input, InputError, call!(::nom::be_u32))
+}
+
+pub fn be_f32(input: &[u8]) -> IResult<&[u8], f32, InputError> {
+  fix_error!(input, InputError, call!(::nom::be_f32))
+}
+
+pub fn rest(input: &[u8]) -> IResult<&[u8], &[u8], InputError> {
+  Done(&input[input.len()..], input)
+}
+
+pub fn take_bytes(input: &[u8], size: usize) -> IResult<&[u8], &[u8], InputError> {
+  fix_error!(input, InputError, take!(size))
+}
+
+// like nom's count!, but keeps the error of the failing element
+pub fn count<'a, F, O>(input: &'a [u8], parser: F, n: usize) -> IResult<&'a [u8], Vec<O>, InputError>
+ where F : Fn(&'a [u8]) -> IResult<&'a [u8], O, InputError> {
+  let mut i = input;
+  let mut res = Vec::new();
+  while res.len() < n {
+    match parser(i) {
+      Done(rest, o)               => { res.push(o); i = rest; }
+      Error(e)                    => return Error(e),
+      Incomplete(Needed::Unknown) => return Incomplete(Needed::Unknown),
+      Incomplete(Needed::Size(s)) => return Incomplete(Needed::Size(s + input.len() - i.len()))
+    }
+  }
+  Done(i, res)
+}
+
+// bytes prefixed by their length as an int32, -1 meaning null
+pub fn kafka_nullable_bytes<'a>(input:&'a [u8]) -> IResult<&'a [u8], KafkaNullableBytes<'a>, InputError> {
   match be_i32(input) {
+    Done(i, -1)     => Done(i, None),
     Done(i, length) => {
-      if length >= 0 {
-        let sz: usize = length as usize;
-        if i.len() >= sz {
-          return Done(&i[sz..], &i[0..sz])
-        } else {
-          return Incomplete(Needed::Size(length as usize))
-        }
-      } else if length == -1 {
-        Error(Custom(InputError::NotImplemented.to_int())) // TODO: maybe make an optional parser which returns an option?
+      if length < 0 {
+        InputError::ParserError.fail()
+      } else if i.len() < length as usize {
+        Incomplete(Needed::Size(length as usize))
       } else {
-        Error(Custom(InputError::ParserError.to_int()))
+        Done(&i[length as usize..], Some(&i[..length as usize]))
       }
     }
     Error(e)      => Error(e),
@@ -48,20 +101,26 @@ pub fn kafka_bytes<'a>(input:&'a [u8]) -> IResult<&'a [u8], KafkaBytes<'a>> {
   }
 }
 
-pub fn kafka_bytestring<'a>(input:&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
+pub fn kafka_bytes<'a>(input:&'a [u8]) -> IResult<&'a [u8], KafkaBytes<'a>, InputError> {
+  match kafka_nullable_bytes(input) {
+    Done(i, Some(bs)) => Done(i, bs),
+    Done(_, None)     => InputError::ParserError.fail(),
+    Error(e)          => Error(e),
+    Incomplete(e)     => Incomplete(e)
+  }
+}
+
+// bytes prefixed by their length as an int16, -1 meaning null
+pub fn kafka_nullable_bytestring(input:&[u8]) -> IResult<&[u8], Option<&[u8]>, InputError> {
   match be_i16(input) {
+    Done(i, -1)     => Done(i, None),
     Done(i, length) => {
-      if length >= 0 {
-        let sz: usize = length as usize;
-        if i.len() >= sz {
-          return Done(&i[sz..], &i[0..sz])
-        } else {
-          return Incomplete(Needed::Size(length as usize))
-        }
-      } else if length == -1 {
-        Error(Custom(InputError::NotImplemented.to_int())) //TODO: maybe make an optional parser which returns an option?
+      if length < 0 {
+        InputError::ParserError.fail()
+      } else if i.len() < length as usize {
+        Incomplete(Needed::Size(length as usize))
       } else {
-        Error(Custom(InputError::ParserError.to_int()))
+        Done(&i[length as usize..], Some(&i[..length as usize]))
       }
     }
     Error(e)      => Error(e),
@@ -69,20 +128,41 @@ pub fn kafka_bytestring<'a>(input:&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
   }
 }
 
-pub fn kafka_string<'a>(input:&'a [u8]) -> IResult<&'a [u8], KafkaString<'a>> {
+pub fn kafka_bytestring(input:&[u8]) -> IResult<&[u8], &[u8], InputError> {
+  match kafka_nullable_bytestring(input) {
+    Done(i, Some(bs)) => Done(i, bs),
+    Done(_, None)     => InputError::ParserError.fail(),
+    Error(e)          => Error(e),
+    Incomplete(e)     => Incomplete(e)
+  }
+}
+
+pub fn kafka_nullable_string<'a>(input:&'a [u8]) -> IResult<&'a [u8], KafkaNullableString<'a>, InputError> {
+  match kafka_nullable_bytestring(input) {
+    Done(i, Some(bs)) => match str::from_utf8(bs) {
+      Ok(s)  => Done(i, Some(s)),
+      Err(_) => InputError::ParserError.fail()
+    },
+    Done(i, None) => Done(i, None),
+    Error(e)      => Error(e),
+    Incomplete(e) => Incomplete(e)
+  }
+}
+
+pub fn kafka_string<'a>(input:&'a [u8]) -> IResult<&'a [u8], KafkaString<'a>, InputError> {
   map_res!(input, kafka_bytestring, |bs| {
     str::from_utf8(bs)
   })
 }
 
-pub fn kafka_array<'a, F,O>(input: &'a[u8], closure: F) -> IResult<&'a[u8], Vec<O> >
- where F : Fn(&'a[u8]) -> IResult<&'a[u8], O> {
+pub fn kafka_array<'a, F,O>(input: &'a[u8], closure: F) -> IResult<&'a[u8], Vec<O> , InputError>
+ where F : Fn(&'a[u8]) -> IResult<&'a[u8], O, InputError> {
    match be_i32(input) {
     Done(i, size) => {
       if size >= 0 {
-        count!(i, closure, size as usize)
+        count(i, closure, size as usize)
       } else {
-        Error(Custom(InputError::ParserError.to_int()))
+        InputError::ParserError.fail()
       }
     }
     Error(e)      => Error(e),
@@ -90,11 +170,186 @@ pub fn kafka_array<'a, F,O>(input: &'a[u8], closure: F) -> IResult<&'a[u8], Vec<
    }
  }
 
-#[cfg(test)]
+// variable length integers: 7 bits per byte, least significant first, the
+// high bit set on every byte but the last
+fn unsigned_varint(input: &[u8], max_bytes: usize) -> IResult<&[u8], u64, InputError> {
+  let mut value: u64 = 0;
+  for (i, b) in input.iter().enumerate() {
+    if i == max_bytes {
+      break;
+    }
+
+    value |= ((b & 0x7f) as u64) << (7 * i);
+    if b & 0x80 == 0 {
+      return Done(&input[i+1..], value);
+    }
+  }
+
+  if input.len() >= max_bytes {
+    InputError::ParserError.fail()
+  } else {
+    Incomplete(Needed::Size(input.len() + 1))
+  }
+}
+
+// zigzag encoded variable length integers, used in record batches
+pub fn varint(input: &[u8]) -> IResult<&[u8], i32, InputError> {
+  map!(input, apply!(unsigned_varint, 5), |v: u64| {
+    let v = v as u32;
+    ((v >> 1) as i32) ^ -((v & 1) as i32)
+  })
+}
+
+pub fn varlong(input: &[u8]) -> IResult<&[u8], i64, InputError> {
+  map!(input, apply!(unsigned_varint, 10), |v: u64| {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+  })
+}
+
+// bytes prefixed by their length as a varint, -1 meaning null
+pub fn varint_bytes(input: &[u8]) -> IResult<&[u8], Option<&[u8]>, InputError> {
+  match varint(input) {
+    Done(i, -1)     => Done(i, None),
+    Done(i, length) => {
+      if length < 0 {
+        InputError::ParserError.fail()
+      } else if i.len() < length as usize {
+        Incomplete(Needed::Size(length as usize))
+      } else {
+        Done(&i[length as usize..], Some(&i[..length as usize]))
+      }
+    }
+    Error(e)      => Error(e),
+    Incomplete(e) => Incomplete(e)
+  }
+}
 
+pub fn varint_string(input: &[u8]) -> IResult<&[u8], &str, InputError> {
+  match varint_bytes(input) {
+    Done(i, Some(bs)) => match str::from_utf8(bs) {
+      Ok(s)  => Done(i, s),
+      Err(_) => InputError::ParserError.fail()
+    },
+    Done(_, None) => InputError::ParserError.fail(),
+    Error(e)      => Error(e),
+    Incomplete(e) => Incomplete(e)
+  }
+}
+
+// flexible versions (since Kafka 2.4) prefix compact fields with an
+// unsigned varint holding their length plus one, 0 meaning null
+pub fn unsigned_varint32(input: &[u8]) -> IResult<&[u8], u32, InputError> {
+  match unsigned_varint(input, 5) {
+    Done(i, v) => {
+      if v > u32::MAX as u64 {
+        InputError::ParserError.fail()
+      } else {
+        Done(i, v as u32)
+      }
+    }
+    Error(e)      => Error(e),
+    Incomplete(e) => Incomplete(e)
+  }
+}
+
+pub fn compact_nullable_bytes<'a>(input: &'a [u8]) -> IResult<&'a [u8], KafkaNullableBytes<'a>, InputError> {
+  match unsigned_varint32(input) {
+    Done(i, 0)      => Done(i, None),
+    Done(i, length) => {
+      let sz = (length - 1) as usize;
+      if i.len() < sz {
+        Incomplete(Needed::Size(sz))
+      } else {
+        Done(&i[sz..], Some(&i[..sz]))
+      }
+    }
+    Error(e)      => Error(e),
+    Incomplete(e) => Incomplete(e)
+  }
+}
+
+pub fn compact_bytes<'a>(input: &'a [u8]) -> IResult<&'a [u8], KafkaBytes<'a>, InputError> {
+  match compact_nullable_bytes(input) {
+    Done(i, Some(bs)) => Done(i, bs),
+    Done(_, None)     => InputError::ParserError.fail(),
+    Error(e)          => Error(e),
+    Incomplete(e)     => Incomplete(e)
+  }
+}
+
+pub fn compact_nullable_string<'a>(input: &'a [u8]) -> IResult<&'a [u8], KafkaNullableString<'a>, InputError> {
+  match compact_nullable_bytes(input) {
+    Done(i, Some(bs)) => match str::from_utf8(bs) {
+      Ok(s)  => Done(i, Some(s)),
+      Err(_) => InputError::ParserError.fail()
+    },
+    Done(i, None) => Done(i, None),
+    Error(e)      => Error(e),
+    Incomplete(e) => Incomplete(e)
+  }
+}
+
+pub fn compact_string<'a>(input: &'a [u8]) -> IResult<&'a [u8], KafkaString<'a>, InputError> {
+  match compact_nullable_string(input) {
+    Done(i, Some(s)) => Done(i, s),
+    Done(_, None)    => InputError::ParserError.fail(),
+    Error(e)         => Error(e),
+    Incomplete(e)    => Incomplete(e)
+  }
+}
+
+pub fn compact_nullable_array<'a, F,O>(input: &'a[u8], closure: F) -> IResult<&'a[u8], Option<Vec<O>>, InputError>
+ where F : Fn(&'a[u8]) -> IResult<&'a[u8], O, InputError> {
+  match unsigned_varint32(input) {
+    Done(i, 0)    => Done(i, None),
+    Done(i, size) => map!(i, apply!(count, closure, (size - 1) as usize), Some),
+    Error(e)      => Error(e),
+    Incomplete(e) => Incomplete(e)
+  }
+}
+
+pub fn compact_array<'a, F,O>(input: &'a[u8], closure: F) -> IResult<&'a[u8], Vec<O>, InputError>
+ where F : Fn(&'a[u8]) -> IResult<&'a[u8], O, InputError> {
+  match compact_nullable_array(input, closure) {
+    Done(i, Some(elems)) => Done(i, elems),
+    Done(_, None)        => InputError::ParserError.fail(),
+    Error(e)             => Error(e),
+    Incomplete(e)        => Incomplete(e)
+  }
+}
+
+// tagged fields end the structures of flexible versions: a count, then
+// each field with its tag and size, in increasing tag order. Fields are
+// kept as raw bytes, to be parsed by the structures knowing their tags
+pub type TaggedFields<'a> = Vec<(u32, &'a [u8])>;
+
+fn tagged_field(input: &[u8]) -> IResult<&[u8], (u32, &[u8]), InputError> {
+  do_parse!(
+    input,
+    tag: unsigned_varint32 >>
+    size: unsigned_varint32 >>
+    data: apply!(take_bytes, size as usize) >>
+    ((tag, data))
+  )
+}
+
+pub fn tagged_fields<'a>(input: &'a [u8]) -> IResult<&'a [u8], TaggedFields<'a>, InputError> {
+  match do_parse!(input, n: unsigned_varint32 >> fields: apply!(count, tagged_field, n as usize) >> (fields)) {
+    Done(i, fields) => {
+      if fields.windows(2).all(|w| w[0].0 < w[1].0) {
+        Done(i, fields)
+      } else {
+        InputError::ParserError.fail()
+      }
+    }
+    Error(e)      => Error(e),
+    Incomplete(e) => Incomplete(e)
+  }
+}
+
+#[cfg(test)]
 mod tests {
   use super::*;
-  use nom::*;
   use nom::IResult::*;
   use nom::ErrorKind::Custom;
 
@@ -114,18 +369,83 @@ mod tests {
     assert_eq!(kafka_string(&[0x00, 0x01]), Incomplete(Needed::Size(1)));
     assert_eq!(kafka_string(&[0x00, 0x02, 65, 66]), Done(&[][..], "AB"));
     assert_eq!(kafka_string(&[0x00, 0x01, 65, 0x00]), Done(&[0x00][..], "A"));
-    assert_eq!(kafka_string(&[0x80, 0x00]), Error(Custom(InputError::ParserError.to_int())));
+    assert_eq!(kafka_string(&[0x80, 0x00]), InputError::ParserError.fail());
     // TODO: test invalid utf8 strings
   }
 
+  #[test]
+  fn kafka_nullable_test() {
+    assert_eq!(kafka_nullable_bytes(&[0xff, 0xff, 0xff, 0xff]), Done(&[][..], None));
+    assert_eq!(kafka_nullable_bytes(&[0x00, 0x00, 0x00, 0x01, 0x61]), Done(&[][..], Some(&b"a"[..])));
+    assert_eq!(kafka_nullable_bytes(&[0xff, 0xff, 0xff, 0xfe]), InputError::ParserError.fail());
+    assert_eq!(kafka_bytes(&[0xff, 0xff, 0xff, 0xff]), InputError::ParserError.fail());
+
+    assert_eq!(kafka_nullable_string(&[0xff, 0xff, 0x00]), Done(&[0x00][..], None));
+    assert_eq!(kafka_nullable_string(&[0x00, 0x00]), Done(&[][..], Some("")));
+    assert_eq!(kafka_nullable_string(&[0x00, 0x02, 65, 66]), Done(&[][..], Some("AB")));
+    assert_eq!(kafka_nullable_string(&[0x00, 0x01, 0xff]), InputError::ParserError.fail());
+    assert!(!kafka_string(&[0xff, 0xff]).is_done());
+  }
+
   #[test]
   fn kafka_array_test() {
     assert_eq!(kafka_array(&[0x00, 0x00, 0x00, 0x00], be_i8), Done(&[][..], vec![]));
     assert_eq!(kafka_array(&[0x00, 0x00, 0x00, 0x01], be_i8), Incomplete(Needed::Size(1)));
     assert_eq!(kafka_array(&[0x00, 0x00, 0x00, 0x01, 0x00], be_i8), Done(&[][..], vec![0x00]));
     assert_eq!(kafka_array(&[0x00, 0x00, 0x00, 0x01, 0x00, 0x00], be_i8), Done(&[0x00][..], vec![0x00]));
-    assert_eq!(kafka_array(&[0x80, 0x00, 0x00, 0x00], be_i8), Error(Custom(InputError::ParserError.to_int())));
+    assert_eq!(kafka_array(&[0x80, 0x00, 0x00, 0x00], be_i8), InputError::ParserError.fail());
+  }
+
+  #[test]
+  fn varint_test() {
+    assert_eq!(varint(&[0x00]), Done(&[][..], 0));
+    assert_eq!(varint(&[0x01]), Done(&[][..], -1));
+    assert_eq!(varint(&[0x02, 0x00]), Done(&[0x00][..], 1));
+    assert_eq!(varint(&[0xac, 0x02]), Done(&[][..], 150));
+    assert_eq!(varint(&[0xfe, 0xff, 0xff, 0xff, 0x0f]), Done(&[][..], 2147483647));
+    assert_eq!(varint(&[0xff, 0xff, 0xff, 0xff, 0x0f]), Done(&[][..], -2147483648));
+    assert_eq!(varint(&[0xac]), Incomplete(Needed::Size(2)));
+    assert_eq!(varint(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]), InputError::ParserError.fail());
+    assert_eq!(varlong(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]), Done(&[][..], -9223372036854775808));
+  }
+
+  #[test]
+  fn varint_bytes_test() {
+    assert_eq!(varint_bytes(&[0x01]), Done(&[][..], None));
+    assert_eq!(varint_bytes(&[0x04, 65, 66]), Done(&[][..], Some(&[65, 66][..])));
+    assert_eq!(varint_bytes(&[0x04, 65]), Incomplete(Needed::Size(2)));
+    assert_eq!(varint_bytes(&[0x03]), InputError::ParserError.fail());
+    assert_eq!(varint_string(&[0x04, 65, 66]), Done(&[][..], "AB"));
+    assert_eq!(varint_string(&[0x01]), InputError::ParserError.fail());
   }
-}
 
+  #[test]
+  fn compact_test() {
+    assert_eq!(unsigned_varint32(&[0xff, 0xff, 0xff, 0xff, 0x0f]), Done(&[][..], 4294967295));
+    assert_eq!(unsigned_varint32(&[0xff, 0xff, 0xff, 0xff, 0x1f]), InputError::ParserError.fail());
+
+    assert_eq!(compact_nullable_bytes(&[0x00]), Done(&[][..], None));
+    assert_eq!(compact_nullable_bytes(&[0x01]), Done(&[][..], Some(&[][..])));
+    assert_eq!(compact_bytes(&[0x03, 65, 66, 67]), Done(&[67][..], &[65, 66][..]));
+    assert_eq!(compact_bytes(&[0x03, 65]), Incomplete(Needed::Size(2)));
+    assert_eq!(compact_bytes(&[0x00]), InputError::ParserError.fail());
+
+    assert_eq!(compact_nullable_string(&[0x00]), Done(&[][..], None));
+    assert_eq!(compact_string(&[0x03, 65, 66]), Done(&[][..], "AB"));
+    assert_eq!(compact_string(&[0x02, 0xff]), InputError::ParserError.fail());
+
+    assert_eq!(compact_nullable_array(&[0x00], be_i8), Done(&[][..], None));
+    assert_eq!(compact_array(&[0x01], be_i8), Done(&[][..], vec![]));
+    assert_eq!(compact_array(&[0x03, 0x01, 0x02], be_i8), Done(&[][..], vec![1, 2]));
+    assert_eq!(compact_array(&[0x00], be_i8), InputError::ParserError.fail());
+  }
 
+  #[test]
+  fn tagged_fields_test() {
+    assert_eq!(tagged_fields(&[0x00, 0x01]), Done(&[0x01][..], vec![]));
+    assert_eq!(tagged_fields(&[0x02, 0x00, 0x01, 0x61, 0x05, 0x00]), Done(&[][..], vec![(0, &b"a"[..]), (5, &[][..])]));
+    assert_eq!(tagged_fields(&[0x01, 0x00, 0x02, 0x61]), Incomplete(Needed::Size(5)));
+    // tags are sorted and unique
+    assert_eq!(tagged_fields(&[0x02, 0x01, 0x00, 0x01, 0x00]), InputError::ParserError.fail());
+  }
+}
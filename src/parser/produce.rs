@@ -2,27 +2,60 @@
 #![allow(unused_imports)]
 
 use parser::primitive::*;
+use parser::errors::*;
 use parser::message::*;
 
-use nom::{HexDisplay,Needed,IResult,FileProducer, be_i16, be_i32};
+use nom::{HexDisplay,Needed,IResult,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
+use nom::ErrorKind::Custom;
 
 #[derive(PartialEq, Debug)]
 pub struct ProduceRequest<'a> {
-    required_acks: i16,
-    timeout: i32,
-    topics: Vec<TopicMessageSet<'a>>
+    // since version 3, None for non transactional producers
+    pub transactional_id: KafkaNullableString<'a>,
+    pub required_acks: i16,
+    pub timeout: i32,
+    pub topics: Vec<TopicMessageSet<'a>>
 }
 
-pub fn produce_request<'a>(input:&'a [u8]) -> IResult<&'a [u8], ProduceRequest<'a>> {
+pub fn produce_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], ProduceRequest<'a>, InputError> {
+  match api_version {
+    // versions 1 and 2 only change the response, and the message format
+    // accepted in the message set
+    0..=2 => produce_request_v0(input),
+    3 => produce_request_v3(input),
+    _ => InputError::ParserError.fail(),
+  }
+}
+
+pub fn produce_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], ProduceRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    required_acks: be_i16 >>
+    timeout: be_i32 >>
+    topics: apply!(kafka_array, topic_message_set) >>
+    (
+      ProduceRequest {
+        transactional_id: None,
+        required_acks,
+        timeout,
+        topics,
+      }
+    )
+  )
+}
+
+pub fn produce_request_v3<'a>(input:&'a [u8]) -> IResult<&'a [u8], ProduceRequest<'a>, InputError> {
   do_parse!(
     input,
+    transactional_id: kafka_nullable_string >>
     required_acks: be_i16 >>
     timeout: be_i32 >>
     topics: apply!(kafka_array, topic_message_set) >>
     (
       ProduceRequest {
+        transactional_id,
         required_acks,
         timeout,
         topics,
@@ -61,9 +94,10 @@ mod tests {
                     0x00, 0x00, 0x00, 0x00  // value = []
 
       ];
-      let result = produce_request(input);
+      let result = produce_request(input, 0);
 
       assert_eq!(result, Done(&[][..], ProduceRequest {
+        transactional_id: None,
         required_acks: 0,
         timeout: 0,
         topics: vec![
@@ -72,21 +106,54 @@ mod tests {
             partitions: vec![
               PartitionMessageSet {
                 partition: 0,
-                message_set: vec![
+                records: Records::MessageSet(vec![
                   OMsMessage {
                     offset: 0,
                     message: Message {
                       magic_byte: 0,
                       attributes: 0,
-                      key: &[][..],
-                      value: &[][..]
+                      timestamp: None,
+                      key: Some(&[][..]),
+                      value: Some(&[][..])
                     }
                   }
-                ]
+                ]),
+                records_bytes: &input[24..]
               }
             ]
           }
         ]
       }));
   }
+
+  #[test]
+  fn produce_request_v3_tests() {
+      let input = &[
+        0x00, 0x02, 0x74, 0x31, // transactional_id = "t1"
+        0xff, 0xff,             // required_acks = -1
+        0x00, 0x00, 0x03, 0xe8, // timeout = 1000
+        0x00, 0x00, 0x00, 0x00  // TopicMessageSet array length
+      ];
+
+      assert_eq!(produce_request(input, 3), Done(&[][..], ProduceRequest {
+        transactional_id: Some("t1"),
+        required_acks: -1,
+        timeout: 1000,
+        topics: vec![]
+      }));
+
+      let input = &[
+        0xff, 0xff,             // transactional_id = null
+        0x00, 0x01,             // required_acks = 1
+        0x00, 0x00, 0x03, 0xe8, // timeout = 1000
+        0x00, 0x00, 0x00, 0x00  // TopicMessageSet array length
+      ];
+
+      assert_eq!(produce_request(input, 3), Done(&[][..], ProduceRequest {
+        transactional_id: None,
+        required_acks: 1,
+        timeout: 1000,
+        topics: vec![]
+      }));
+  }
 }
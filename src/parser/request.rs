@@ -3,8 +3,9 @@
 
 use parser::primitive::*;
 use parser::errors::*;
+use error::BrokerError;
 
-use nom::{HexDisplay,Needed,IResult,ErrorKind,FileProducer,be_i8,be_i16,be_i32,be_i64,be_f32};
+use nom::{HexDisplay,Needed,IResult,ErrorKind,FileProducer};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
 
@@ -15,16 +16,22 @@ use parser::metadata::*;
 use parser::offset_commit::*;
 use parser::offset_fetch::*;
 use parser::consumer_metadata::*;
+use parser::join_group::*;
+use parser::heartbeat::*;
+use parser::leave_group::*;
+use parser::sync_group::*;
+use parser::api_versions::*;
 
 #[derive(PartialEq,Debug)]
 pub struct RequestMessage<'a> {
     pub api_version: i16,
     pub correlation_id: i32,
-    pub client_id: KafkaString<'a>,
+    pub client_id: KafkaNullableString<'a>,
     pub request_payload: RequestPayload<'a>
 }
 
 // ToDo other requests
+#[allow(clippy::enum_variant_names)]
 #[derive(PartialEq,Debug)]
 pub enum RequestPayload<'a> {
     ProduceRequest(ProduceRequest<'a>),
@@ -33,47 +40,94 @@ pub enum RequestPayload<'a> {
     MetadataRequest(TopicMetadataRequest<'a>),
     OffsetCommitRequest(OffsetCommitRequest<'a>),
     OffsetFetchRequest(OffsetFetchRequest<'a>),
-    ConsumerMetadataRequest(ConsumerMetadataRequest<'a>)
+    ConsumerMetadataRequest(ConsumerMetadataRequest<'a>),
+    JoinGroupRequest(JoinGroupRequest<'a>),
+    HeartbeatRequest(HeartbeatRequest<'a>),
+    LeaveGroupRequest(LeaveGroupRequest<'a>),
+    SyncGroupRequest(SyncGroupRequest<'a>),
+    ApiVersionsRequest
 }
 
-pub fn parse_request_payload<'a>(input:&'a [u8], api_version: i16, api_key: i16) -> IResult<&'a [u8], RequestPayload<'a>> {
+pub fn parse_request_payload<'a>(input:&'a [u8], api_version: i16, api_key: i16) -> IResult<&'a [u8], RequestPayload<'a>, InputError> {
+    if version_range(api_key).is_some() && !supported_version(api_key, api_version) {
+        // clients retry with a version from the ApiVersions response, which
+        // is sent even if they asked with a newer request format
+        if api_key == API_VERSIONS_KEY {
+            return map!(input, rest, |_| { RequestPayload::ApiVersionsRequest });
+        }
+        return InputError::UnsupportedVersion.fail();
+    }
+
     match api_key {
-        0  => map!(input, produce_request, |p| { RequestPayload::ProduceRequest(p) }),
-        1  => map!(input, fetch_request, |p| { RequestPayload::FetchRequest(p) }),
-        2  => map!(input, offset_request, |p| { RequestPayload::OffsetRequest(p) }),
-        3  => map!(input, topic_metadata_request, |p| { RequestPayload::MetadataRequest(p) }),
+        0  => {
+           let pp = |i| { produce_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::ProduceRequest(p) })
+        }
+        1  => {
+           let pp = |i| { fetch_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::FetchRequest(p) })
+        }
+        2  => {
+           let pp = |i| { offset_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::OffsetRequest(p) })
+        }
+        3  => {
+           let pp = |i| { topic_metadata_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::MetadataRequest(p) })
+        }
 
         // Non user-facing control APIs
         // Given proust topology, implementing all of them may not be necessary
-        4  => Error(ErrorKind::Custom(InputError::NotImplemented.to_int())), // LeaderAndIsr
-        5  => Error(ErrorKind::Custom(InputError::NotImplemented.to_int())), // StopReplica
-        6  => Error(ErrorKind::Custom(InputError::NotImplemented.to_int())), // UpdateMetadata
-        7  => Error(ErrorKind::Custom(InputError::NotImplemented.to_int())), // ControlledShutdown
+        4  => InputError::NotImplemented.fail(), // LeaderAndIsr
+        5  => InputError::NotImplemented.fail(), // StopReplica
+        6  => InputError::NotImplemented.fail(), // UpdateMetadata
+        7  => InputError::NotImplemented.fail(), // ControlledShutdown
 
         8  => {
            let pp = |i| { offset_commit_request(i, api_version) };
            map!(input, pp, |p| { RequestPayload::OffsetCommitRequest(p) })
         }
-        9  => map!(input, offset_fetch_request, |p| { RequestPayload::OffsetFetchRequest(p) }),
-        10 => map!(input, consumer_metadata_request, |p| { RequestPayload::ConsumerMetadataRequest(p) }),
+        9  => {
+           let pp = |i| { offset_fetch_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::OffsetFetchRequest(p) })
+        }
+        10 => {
+           let pp = |i| { consumer_metadata_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::ConsumerMetadataRequest(p) })
+        }
 
-        // Not documented, but those exist in the code
-        // Given proust topology, implementing all of them may not be necessary
-        11 => Error(ErrorKind::Custom(InputError::NotImplemented.to_int())), // JoinGroup
-        12 => Error(ErrorKind::Custom(InputError::NotImplemented.to_int())), // Heartbeat
+        // group membership
+        11 => {
+           let pp = |i| { join_group_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::JoinGroupRequest(p) })
+        }
+        12 => {
+           let pp = |i| { heartbeat_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::HeartbeatRequest(p) })
+        }
+        13 => {
+           let pp = |i| { leave_group_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::LeaveGroupRequest(p) })
+        }
+        14 => {
+           let pp = |i| { sync_group_request(i, api_version) };
+           map!(input, pp, |p| { RequestPayload::SyncGroupRequest(p) })
+        }
 
-        _  => Error(ErrorKind::Custom(InputError::ParserError.to_int()))
+        18 => Done(input, RequestPayload::ApiVersionsRequest),
+
+        _  => InputError::ParserError.fail()
     }
 }
 
-pub fn request_message_with_length<'a>(input:&'a [u8]) -> IResult<&'a [u8], RequestMessage<'a>> {
+pub fn request_message_with_length<'a>(input:&'a [u8]) -> IResult<&'a [u8], RequestMessage<'a>, InputError> {
   match be_i32(input) {
     Done(i, size) => {
       let request_bytes = |i: &'a [u8]| {
         if size >= 0 {
-          take!(i, size as usize)
+          take_bytes(i, size as usize)
         } else {
-          Error(ErrorKind::Custom(InputError::InvalidRequestSize.to_int()))
+          InputError::InvalidRequestSize.fail()
         }
       };
       flat_map!(i, request_bytes, |rb| {
@@ -85,13 +139,13 @@ pub fn request_message_with_length<'a>(input:&'a [u8]) -> IResult<&'a [u8], Requ
   }
 }
 
-pub fn request_message<'a>(input:&'a [u8]) -> IResult<&'a [u8], RequestMessage<'a>> {
+pub fn request_message<'a>(input:&'a [u8]) -> IResult<&'a [u8], RequestMessage<'a>, InputError> {
   do_parse!(
     input,
     key: be_i16 >>
     api_version: be_i16 >>
     correlation_id: be_i32 >>
-    client_id: kafka_string >>
+    client_id: kafka_nullable_string >>
     request_payload: apply!(parse_request_payload, api_version, key) >>
     eof!() >>
     (
@@ -105,6 +159,37 @@ pub fn request_message<'a>(input:&'a [u8]) -> IResult<&'a [u8], RequestMessage<'
   )
 }
 
+fn request_header(input:&[u8]) -> IResult<&[u8], (i16, i16, i32), InputError> {
+  do_parse!(
+    input,
+    key: be_i16 >>
+    api_version: be_i16 >>
+    correlation_id: be_i32 >>
+    ((key, api_version, correlation_id))
+  )
+}
+
+// parses a whole request, and reports failures with the header fields if
+// they could be read
+pub fn parse_request<'a>(input:&'a [u8]) -> Result<RequestMessage<'a>, BrokerError> {
+  let error = match request_message(input) {
+    Done(_, req)  => return Ok(req),
+    Error(e)      => BrokerError::parse(InputError::from_error_kind(&e)),
+    // the buffer holds the whole request
+    Incomplete(_) => BrokerError::parse(InputError::InvalidRequestSize),
+  };
+
+  Err(with_request_header(error, input))
+}
+
+// adds the header fields of the request to the error, if they can be read
+fn with_request_header(error: BrokerError, input: &[u8]) -> BrokerError {
+  match request_header(input) {
+    Done(_, (api_key, api_version, correlation_id)) => error.with_request(api_key, api_version, correlation_id),
+    _                                               => error,
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -127,7 +212,7 @@ mod tests {
       let expected = RequestMessage {
         api_version: 0,
         correlation_id: 0,
-        client_id: "",
+        client_id: Some(""),
         request_payload: RequestPayload::MetadataRequest(vec![])
       };
 
@@ -146,7 +231,7 @@ mod tests {
       ];
       let result = request_message_with_length(input);
 
-      assert_eq!(result, Error(ErrorKind::Custom(InputError::InvalidRequestSize.to_int())));
+      assert_eq!(result, InputError::InvalidRequestSize.fail());
   }
 
   #[test]
@@ -164,7 +249,7 @@ mod tests {
       let expected = RequestMessage {
         api_version: 0,
         correlation_id: 0,
-        client_id: "",
+        client_id: Some(""),
         request_payload: RequestPayload::MetadataRequest(vec![])
       };
 
@@ -201,4 +286,86 @@ mod tests {
       // Will fail trying to parse request_payload's array length (4 bytes)
       assert_eq!(result, Incomplete(Needed::Size(14)))
   }
+
+  #[test]
+  fn api_versions_table_test() {
+      let unsupported = InputError::UnsupportedVersion.fail();
+
+      // every advertised version reaches a parser, the next one is rejected
+      for v in API_VERSIONS {
+        for api_version in v.min_version..v.max_version + 1 {
+          if let Error(e) = parse_request_payload(&[], api_version, v.api_key) {
+            panic!("api key {} version {} is advertised but fails with {:?}", v.api_key, api_version, e);
+          }
+        }
+
+        if v.api_key != API_VERSIONS_KEY {
+          assert_eq!(parse_request_payload(&[], v.max_version + 1, v.api_key), unsupported);
+        }
+      }
+  }
+
+  #[test]
+  fn api_versions_request_test() {
+      let input = &[
+        0x00, 0x00, 0x00, 0x0e, // size = 14
+        0x00, 0x12,             // api_key = 18
+        0x00, 0x03,             // api_version = 3
+        0x00, 0x00, 0x00, 0x07, // correlation_id = 7
+        0x00, 0x00,             // client_id = ""
+        0x00, 0x00, 0x00, 0x00  // version 3 fields, not parsed
+      ];
+      let result = request_message_with_length(input);
+      let expected = RequestMessage {
+        api_version: 3,
+        correlation_id: 7,
+        client_id: Some(""),
+        request_payload: RequestPayload::ApiVersionsRequest
+      };
+
+      assert_eq!(result, Done(&[][..], expected))
+  }
+
+  #[test]
+  fn null_client_id_test() {
+      let input = &[
+        0x00, 0x00, 0x00, 0x0a, // size = 10
+        0x00, 0x12,             // api_key = 18
+        0x00, 0x00,             // api_version = 0
+        0x00, 0x00, 0x00, 0x07, // correlation_id = 7
+        0xff, 0xff              // client_id = null
+      ];
+      let result = request_message_with_length(input);
+      let expected = RequestMessage {
+        api_version: 0,
+        correlation_id: 7,
+        client_id: None,
+        request_payload: RequestPayload::ApiVersionsRequest
+      };
+
+      assert_eq!(result, Done(&[][..], expected))
+  }
+
+  #[test]
+  fn parse_request_test() {
+      use responses::error_code::ErrorCode;
+
+      let input = &[
+        0x00, 0x12,             // api_key = 18
+        0x00, 0x00,             // api_version = 0
+        0x00, 0x00, 0x00, 0x07, // correlation_id = 7
+        0x00, 0x00              // client_id = ""
+      ];
+      assert!(parse_request(input).is_ok());
+
+      // produce v99
+      let input = &[0x00, 0x00, 0x00, 0x63, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00];
+      let e = parse_request(input).unwrap_err();
+      assert_eq!(e.error_code(), ErrorCode::UnsupportedVersion);
+      assert_eq!((e.context.api_key, e.context.api_version, e.context.correlation_id), (Some(0), Some(99), Some(7)));
+
+      let e = parse_request(&input[..3]).unwrap_err();
+      assert_eq!(e.error_code(), ErrorCode::InvalidRequest);
+      assert_eq!(e.context.api_key, None);
+  }
 }
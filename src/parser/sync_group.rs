@@ -0,0 +1,91 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+
+use parser::primitive::*;
+use parser::errors::*;
+
+use nom::{HexDisplay,Needed,IResult,FileProducer};
+use nom::{Consumer,ConsumerState};
+use nom::IResult::*;
+use nom::ErrorKind::Custom;
+
+/*
+SyncGroupRequest => GroupId GenerationId MemberId [GroupAssignment]
+  GroupId => string
+  GenerationId => int32
+  MemberId => string
+  GroupAssignment => MemberId MemberAssignment
+    MemberId => string
+    MemberAssignment => bytes
+*/
+
+#[derive(PartialEq,Debug)]
+pub struct SyncGroupRequest<'a> {
+  pub group_id: KafkaString<'a>,
+  pub generation_id: i32,
+  pub member_id: KafkaString<'a>,
+  pub group_assignment: Vec<(KafkaString<'a>, KafkaBytes<'a>)>
+}
+
+pub fn sync_group_request<'a>(input:&'a [u8], api_version: i16) -> IResult<&'a [u8], SyncGroupRequest<'a>, InputError> {
+  match api_version {
+    0 => sync_group_request_v0(input),
+    _ => InputError::ParserError.fail(),
+  }
+}
+
+pub fn sync_group_request_v0<'a>(input:&'a [u8]) -> IResult<&'a [u8], SyncGroupRequest<'a>, InputError> {
+  do_parse!(
+    input,
+    group_id: kafka_string >>
+    generation_id: be_i32 >>
+    member_id: kafka_string >>
+    group_assignment: apply!(kafka_array, member_assignment) >>
+    (
+      SyncGroupRequest {
+        group_id,
+        generation_id,
+        member_id,
+        group_assignment,
+      }
+    )
+  )
+}
+
+pub fn member_assignment<'a>(input:&'a [u8]) -> IResult<&'a [u8], (KafkaString<'a>, KafkaBytes<'a>), InputError> {
+  do_parse!(
+    input,
+    member_id: kafka_string >>
+    assignment: kafka_bytes >>
+    ((member_id, assignment))
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nom::*;
+  use nom::IResult::*;
+
+  #[test]
+  fn sync_group_request_test() {
+    let input = &[
+      0x00, 0x01, 0x67,       // group_id = "g"
+      0x00, 0x00, 0x00, 0x02, // generation_id = 2
+      0x00, 0x01, 0x6d,       // member_id = "m"
+      0x00, 0x00, 0x00, 0x01, // group_assignment = 1
+        0x00, 0x01, 0x6d,                   // member_id = "m"
+        0x00, 0x00, 0x00, 0x02, 0x01, 0x02  // member_assignment = [1, 2]
+    ];
+
+    let result = sync_group_request(input, 0);
+    let expected = SyncGroupRequest {
+      group_id: "g",
+      generation_id: 2,
+      member_id: "m",
+      group_assignment: vec![("m", &[1, 2][..])]
+    };
+
+    assert_eq!(result, Done(&[][..], expected))
+  }
+}
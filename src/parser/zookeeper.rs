@@ -166,20 +166,17 @@ pub fn message(input: &[u8]) -> IResult<&[u8], Message> {
 }
 */
 
-pub fn buffer<'a>(input:&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
+// jute buffers use -1 for null, like kafka nullable bytes
+pub fn nullable_buffer<'a>(input:&'a [u8]) -> IResult<&'a [u8], Option<&'a [u8]>, InputError> {
   match be_i32(input) {
+    Done(i, -1)     => Done(i, None),
     Done(i, length) => {
-      if length >= 0 {
-        let sz: usize = length as usize;
-        if i.len() >= sz {
-          return Done(&i[sz..], &i[0..sz])
-        } else {
-          return Incomplete(Needed::Size(length as usize))
-        }
-      } else if length == -1 {
-        Error(Code(ErrorKind::Custom(InputError::NotImplemented.to_int()))) // TODO maybe make an optional parser which returns an option?
+      if length < 0 {
+        InputError::ParserError.fail()
+      } else if i.len() < length as usize {
+        Incomplete(Needed::Size(length as usize))
       } else {
-        Error(Code(ErrorKind::Custom(InputError::ParserError.to_int())))
+        Done(&i[length as usize..], Some(&i[..length as usize]))
       }
     }
     Error(e)      => Error(e),
@@ -187,11 +184,20 @@ pub fn buffer<'a>(input:&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
   }
 }
 
+pub fn buffer<'a>(input:&'a [u8]) -> IResult<&'a [u8], &'a [u8], InputError> {
+  match nullable_buffer(input) {
+    Done(i, Some(b)) => Done(i, b),
+    Done(_, None)    => InputError::ParserError.fail(),
+    Error(e)         => Error(e),
+    Incomplete(e)    => Incomplete(e)
+  }
+}
+
 named!(pub ustring<&[u8], &str>, map_res!(buffer, str::from_utf8));
 
 named!(pub vector_ustring<&[u8], Vec<&str> >, length_value!(be_i32, ustring));
 
-pub fn ser_connection_response<'a>(c: &ConnectResponse<'a>, o: &mut Vec<u8>) -> () {
+pub fn ser_connection_response<'a>(c: &ConnectResponse<'a>, o: &mut Vec<u8>) {
   ser_i32(16+4+c.password.len() as i32, o);
   ser_i32(c.protocol_version, o);
   ser_i32(c.timeout, o);
@@ -200,7 +206,7 @@ pub fn ser_connection_response<'a>(c: &ConnectResponse<'a>, o: &mut Vec<u8>) ->
   o.extend(c.password.iter().cloned());
 }
 
-pub fn ser_reply_header(r: &ReplyHeader, o: &mut Vec<u8>) -> () {
+pub fn ser_reply_header(r: &ReplyHeader, o: &mut Vec<u8>) {
   //ser_i32(16, o);
   ser_i32(r.xid, o);
   ser_i64(r.zxid, o);
@@ -1,39 +1,1854 @@
+use std::cmp;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex,RwLock};
+use std::sync::atomic::{AtomicI64,AtomicU64,Ordering};
+
+use nom::IResult::Done;
+
 use parser::request::{RequestMessage,RequestPayload};
+use parser::produce::ProduceRequest;
+use parser::fetch::FetchRequest;
+use parser::offset::OffsetRequest;
+use parser::metadata::TopicMetadataRequest;
+use parser::offset_commit::OffsetCommitRequest;
+use parser::offset_fetch::OffsetFetchRequest;
+use parser::consumer_metadata::{ConsumerMetadataRequest,GROUP_COORDINATOR};
+use parser::api_versions::{API_VERSIONS,API_VERSIONS_KEY,supported_version,version_range};
+use parser::message::{Message,MessageSet,OMsMessage,RecordBatch,Records,COMPRESSION_CODEC_MASK,TIMESTAMP_TYPE_MASK,MESSAGE_COMPRESSION_CODEC_MASK,MESSAGE_TIMESTAMP_TYPE_MASK,batch_records_from,message_set_messages};
+use parser::primitive::KafkaString;
 use responses::response::{ResponseMessage,ResponsePayload};
-use responses::metadata::{MetadataResponse,Broker,TopicMetadata,PartitionMetadata};
-
-
-pub fn handle_request(req: RequestMessage) -> Result<ResponseMessage,u8> {
-    match req.request_payload {
-      RequestPayload::MetadataRequest(_) => {
-        Ok(ResponseMessage {
-          correlation_id: req.correlation_id,
-          response_payload: ResponsePayload::MetadataResponse(MetadataResponse {
-            brokers: vec![Broker {
-              node_id: 1234,
-              host: "localhost",
-              port: 9092
-            }],
-            topics: vec![TopicMetadata {
-              topic_error_code: 0,
-              topic_name: "topic1",
-              partitions: vec![PartitionMetadata {
-                partition_error_code: 0,
-                partition_id: 0,
-                leader: 1234,
-                replicas: vec![0],
-                isr: vec![1]
-              }]
-            }]
-          })
-        })
+use responses::metadata::{MetadataResponse,Broker as BrokerMetadata,TopicMetadata,PartitionMetadata};
+use responses::produce::ProduceResponse;
+use responses::fetch::FetchResponse;
+use responses::offset::OffsetResponse;
+use responses::offset_commit::OffsetCommitResponse;
+use responses::offset_fetch::OffsetFetchResponse;
+use responses::consumer_metadata::ConsumerMetadataResponse;
+use responses::api_versions::ApiVersionsResponse;
+use responses::join_group::JoinGroupResponse;
+use responses::sync_group::SyncGroupResponse;
+use responses::fetch::{ser_message,ser_message_set,ser_record_batch};
+use storage::segment::ENTRY_HEADER_SIZE;
+use responses::error_code::ErrorCode;
+use storage::SharedBytes;
+use storage::log::PartitionLog;
+use storage::log_manager::{LogManager,SharedLog};
+use topics::{Node,TopicRegistry,valid_topic_name};
+use offset_store::{OffsetStore,CommittedOffset,LoadedChunk,OFFSETS_TOPIC,OFFSETS_PARTITION,DEFAULT_OFFSET_RETENTION_MS,MAX_METADATA_SIZE,READ_CHUNK_SIZE};
+use groups::GroupCoordinator;
+use util::now_ms;
+use compression::Compression;
+use checksum::check_entries;
+use error::{BrokerError,ErrorKind};
+
+// largest message accepted by produce requests
+pub const MAX_MESSAGE_SIZE: usize = 1000012;
+// partitions of topics created on first use
+pub const DEFAULT_NUM_PARTITIONS: i32 = 1;
+// how often segments past the retention time are looked for
+const RETENTION_CHECK_INTERVAL_MS: i64 = 5 * 60 * 1000;
+
+// timestamp stored with produced messages: the one set by the producer,
+// or the time at which the broker appended them
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum TimestampType {
+  CreateTime,
+  LogAppendTime,
+}
+
+// shared by the request handlers: the logs of each partition, the offset
+// store and the group coordinator have their own locks, taken one at a
+// time, except the offsets log which is locked after the offset store
+pub struct Broker {
+  pub node: Node,
+  pub logs: LogManager,
+  pub topics: RwLock<TopicRegistry>,
+  pub offsets: Mutex<OffsetStore>,
+  pub groups: Mutex<GroupCoordinator>,
+  // unknown topics referenced by metadata and produce requests are created
+  // with `num_partitions` partitions
+  pub auto_create_topics: bool,
+  pub num_partitions: i32,
+  // segments older than this are deleted, None keeps them forever
+  pub retention_ms: Option<i64>,
+  pub message_timestamp_type: TimestampType,
+  // codec produced messages are stored with, None keeps the producer's
+  pub compression: Option<Compression>,
+  // produced messages with a wrong CRC are refused, unless the producers
+  // are trusted to send valid ones
+  pub check_crcs: bool,
+  next_retention_check: AtomicI64,
+  // incremented on every produce that appended messages, so that delayed
+  // fetches only check the logs again when there might be new data
+  appends: AtomicU64
+}
+
+impl Broker {
+  pub fn new(node: Node, data_dir: &Path, segment_size: usize) -> io::Result<Broker> {
+    let logs = LogManager::open(data_dir, segment_size)?;
+    // committed offsets are loaded from `maintenance`, once the broker runs
+    let offsets = OffsetStore::new(&logs.create(OFFSETS_TOPIC, OFFSETS_PARTITION)?.lock().unwrap());
+
+    let mut topics = TopicRegistry::new(node.id);
+    for (topic, partition) in logs.partitions() {
+      topics.add_partition(&topic, partition);
+    }
+
+    Ok(Broker {
+      node,
+      logs,
+      topics: RwLock::new(topics),
+      offsets: Mutex::new(offsets),
+      groups: Mutex::new(GroupCoordinator::new()),
+      auto_create_topics: true,
+      num_partitions: DEFAULT_NUM_PARTITIONS,
+      retention_ms: None,
+      message_timestamp_type: TimestampType::CreateTime,
+      compression: None,
+      check_crcs: true,
+      next_retention_check: AtomicI64::new(0),
+      appends: AtomicU64::new(0)
+    })
+  }
+
+  // creates the logs of a new topic and announces it in metadata responses
+  pub fn create_topic(&self, topic: &str, partitions: i32) -> io::Result<()> {
+    if !valid_topic_name(topic) {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid topic name: {:?}", topic)));
+    }
+
+    for partition in 0..partitions {
+      self.logs.create(topic, partition)?;
+      self.topics.write().unwrap().add_partition(topic, partition);
+    }
+    Ok(())
+  }
+
+  pub fn appends(&self) -> u64 {
+    self.appends.load(Ordering::SeqCst)
+  }
+
+  fn offsets_log(&self) -> SharedLog {
+    self.logs.get(OFFSETS_TOPIC, OFFSETS_PARTITION).expect("the offsets log is created at startup")
+  }
+
+  // locks each log in turn, so that requests only wait for the partition
+  // being cleaned
+  pub fn enforce_retention(&self, now: i64) {
+    let retention_ms = match self.retention_ms {
+      Some(r) => r,
+      None    => return,
+    };
+
+    for (topic, _, log) in self.logs.logs() {
+      // committed offsets expire on their own
+      if topic == OFFSETS_TOPIC {
+        continue;
+      }
+
+      let mut log = log.lock().unwrap();
+      if let Err(e) = log.delete_segments_before(now - retention_ms) {
+        error!("could not delete old segments of {:?}: {:?}", log.dir(), e);
+      }
+    }
+
+    self.offsets.lock().unwrap().remove_expired(now);
+  }
+
+  // periodic work: loading committed offsets, compacting them, retention
+  // checks and group session timeouts. Returns when it should be called again
+  pub fn maintenance(&self, now: i64) -> i64 {
+    let load_position = self.offsets.lock().unwrap().load_position();
+    if let Some(offset) = load_position {
+      // the chunk is parsed without holding the locks
+      let chunk = self.offsets_log().lock().unwrap().read(offset, READ_CHUNK_SIZE);
+      let loaded = LoadedChunk::parse(chunk.as_ref().map(|c| &c[..]), offset);
+      let mut offsets = self.offsets.lock().unwrap();
+      offsets.load_chunk(loaded);
+      if offsets.loading() {
+        return now;
+      }
+    }
+
+    {
+      let mut offsets = self.offsets.lock().unwrap();
+      if offsets.needs_compaction() {
+        if let Err(e) = offsets.compact(&mut self.offsets_log().lock().unwrap(), now) {
+          error!("could not compact the offsets log: {:?}", e);
+        }
+      }
+    }
+
+    let mut next_retention_check = self.next_retention_check.load(Ordering::SeqCst);
+    if now >= next_retention_check {
+      self.enforce_retention(now);
+      next_retention_check = now + RETENTION_CHECK_INTERVAL_MS;
+      self.next_retention_check.store(next_retention_check, Ordering::SeqCst);
+    }
+
+    match self.groups.lock().unwrap().tick(now) {
+      Some(deadline) => cmp::min(deadline, next_retention_check),
+      None           => next_retention_check,
+    }
+  }
+
+  // creates a topic referenced by a client if auto creation is enabled
+  pub fn maybe_create_topic(&self, topic: &str) {
+    if !self.auto_create_topics || !valid_topic_name(topic) || self.topics.read().unwrap().partitions(topic).is_some() {
+      return;
+    }
+
+    info!("auto creating topic {} with {} partitions", topic, self.num_partitions);
+    if let Err(e) = self.create_topic(topic, self.num_partitions) {
+      error!("could not create topic {}: {:?}", topic, e);
+    }
+  }
+}
+
+// requests that cannot be answered right away are parked by the network
+// layer until `complete_delayed` returns their response
+#[derive(Debug,Clone,PartialEq)]
+pub enum DelayedOperation {
+  // waiting for min_bytes, `appends` is the produce count last checked
+  Fetch { appends: u64 },
+  // waiting for every member to join
+  JoinGroup { group_id: String, member_id: String },
+  // waiting for the leader's assignment
+  SyncGroup { group_id: String, member_id: String, generation_id: i32 },
+}
+
+impl DelayedOperation {
+  // cheap check done before parsing the request again
+  pub fn may_complete(&self, broker: &Broker) -> bool {
+    match *self {
+      DelayedOperation::Fetch { appends } => appends != broker.appends(),
+      _                                   => true,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum RequestResult<'a> {
+  Response(ResponseMessage<'a>),
+  // produce requests with acks = 0 are not answered
+  NoResponse,
+  // the operation times out after the number of milliseconds
+  Delayed(DelayedOperation, i64),
+}
+
+// the broker locks are released before returning, the response only
+// borrows the request and the broker's node
+pub fn handle_request<'a>(broker: &'a Broker, req: RequestMessage<'a>) -> RequestResult<'a> {
+    let now = now_ms();
+    // group requests need the committed offsets
+    let loading = broker.offsets.lock().unwrap().loading();
+    let payload = match req.request_payload {
+      RequestPayload::MetadataRequest(x) => {
+        for topic in x.iter() {
+          broker.maybe_create_topic(topic);
+        }
+        ResponsePayload::MetadataResponse(handle_metadata(broker, x))
       }
       RequestPayload::ProduceRequest(x) => {
-        Ok(ResponseMessage {
+        if x.required_acks == 0 {
+          handle_produce(broker, x);
+          return RequestResult::NoResponse;
+        }
+        ResponsePayload::ProduceResponse(req.api_version, handle_produce(broker, x))
+      }
+      RequestPayload::FetchRequest(x) => {
+        if !fetch_ready(broker, &x) {
+          let operation = DelayedOperation::Fetch { appends: broker.appends() };
+          return RequestResult::Delayed(operation, x.max_wait_time as i64);
+        }
+        ResponsePayload::FetchResponse(req.api_version, handle_fetch(broker, x))
+      }
+      RequestPayload::OffsetRequest(x) => ResponsePayload::OffsetResponse(req.api_version, handle_offset(broker, x, req.api_version)),
+      RequestPayload::OffsetCommitRequest(x) => ResponsePayload::OffsetCommitResponse(handle_offset_commit(broker, x)),
+      RequestPayload::OffsetFetchRequest(x) => ResponsePayload::OffsetFetchResponse(handle_offset_fetch(broker, x)),
+      RequestPayload::ApiVersionsRequest => ResponsePayload::ApiVersionsResponse(handle_api_versions(req.api_version)),
+      RequestPayload::ConsumerMetadataRequest(x) => {
+        let coordinator = handle_consumer_metadata(broker, &x);
+        if req.api_version == 0 {
+          ResponsePayload::ConsumerMetadataResponse(coordinator)
+        } else {
+          ResponsePayload::FindCoordinatorResponse(coordinator)
+        }
+      }
+      RequestPayload::JoinGroupRequest(x) if loading => {
+        ResponsePayload::JoinGroupResponse(join_group_error(ErrorCode::GroupLoadInProgress, x.member_id))
+      }
+      RequestPayload::SyncGroupRequest(_) if loading => {
+        ResponsePayload::SyncGroupResponse(SyncGroupResponse { error_code: ErrorCode::GroupLoadInProgress.to_int(), member_assignment: vec![] })
+      }
+      RequestPayload::HeartbeatRequest(_) if loading => ResponsePayload::HeartbeatResponse(ErrorCode::GroupLoadInProgress.to_int()),
+      RequestPayload::LeaveGroupRequest(_) if loading => ResponsePayload::LeaveGroupResponse(ErrorCode::GroupLoadInProgress.to_int()),
+      RequestPayload::JoinGroupRequest(x) => {
+        let mut groups = broker.groups.lock().unwrap();
+        let member_id = match groups.join(&x, req.client_id.unwrap_or(""), now) {
+          Ok(id) => id,
+          Err(e) => return RequestResult::Response(ResponseMessage {
             correlation_id: req.correlation_id,
-            response_payload: ResponsePayload::ProduceResponse(vec![( "topic1", vec![(0, 0, 1337)])])
-        })
+            response_payload: ResponsePayload::JoinGroupResponse(join_group_error(e, x.member_id))
+          })
+        };
+
+        match join_group_response(&groups, x.group_id, &member_id) {
+          Some(res) => ResponsePayload::JoinGroupResponse(res),
+          None      => {
+            let deadline = groups.rebalance_deadline(x.group_id).unwrap_or(now);
+            let operation = DelayedOperation::JoinGroup { group_id: x.group_id.to_string(), member_id };
+            return RequestResult::Delayed(operation, deadline - now);
+          }
+        }
+      }
+      RequestPayload::SyncGroupRequest(x) => {
+        let mut groups = broker.groups.lock().unwrap();
+        let result = groups.sync(x.group_id, x.generation_id, x.member_id, &x.group_assignment, now);
+        if result.is_ok() && groups.sync_result(x.group_id, x.generation_id, x.member_id).is_none() {
+          let timeout = groups.session_timeout(x.group_id, x.member_id).unwrap_or(0);
+          let operation = DelayedOperation::SyncGroup {
+            group_id: x.group_id.to_string(),
+            member_id: x.member_id.to_string(),
+            generation_id: x.generation_id
+          };
+          return RequestResult::Delayed(operation, timeout);
+        }
+
+        match result {
+          Ok(())  => ResponsePayload::SyncGroupResponse(sync_group_response(&groups, x.group_id, x.generation_id, x.member_id)),
+          Err(e)  => ResponsePayload::SyncGroupResponse(SyncGroupResponse { error_code: e.to_int(), member_assignment: vec![] })
+        }
+      }
+      RequestPayload::HeartbeatRequest(x) => {
+        ResponsePayload::HeartbeatResponse(broker.groups.lock().unwrap().heartbeat(x.group_id, x.generation_id, x.member_id, now).to_int())
+      }
+      RequestPayload::LeaveGroupRequest(x) => {
+        ResponsePayload::LeaveGroupResponse(broker.groups.lock().unwrap().leave(x.group_id, x.member_id, now).to_int())
+      }
+    };
+
+    RequestResult::Response(ResponseMessage {
+      correlation_id: req.correlation_id,
+      response_payload: payload
+    })
+}
+
+// called with the parked request when the operation may be complete,
+// or when it timed out
+pub fn complete_delayed<'a>(broker: &'a Broker, operation: &mut DelayedOperation, req: RequestMessage<'a>, expired: bool) -> Option<ResponseMessage<'a>> {
+  let payload = match *operation {
+    DelayedOperation::Fetch { ref mut appends } => {
+      let fetch = match req.request_payload {
+        RequestPayload::FetchRequest(x) => x,
+        _                               => return None
+      };
+
+      if !expired && !fetch_ready(broker, &fetch) {
+        *appends = broker.appends();
+        return None;
+      }
+      ResponsePayload::FetchResponse(req.api_version, handle_fetch(broker, fetch))
+    },
+    DelayedOperation::JoinGroup { ref group_id, ref member_id } => {
+      let mut groups = broker.groups.lock().unwrap();
+      groups.tick(now_ms());
+      match join_group_response(&groups, group_id, member_id) {
+        Some(res)       => ResponsePayload::JoinGroupResponse(res),
+        // the group is still waiting for members at the deadline
+        None if expired => ResponsePayload::JoinGroupResponse(join_group_error(ErrorCode::RebalanceInProgress, "")),
+        None            => return None,
+      }
+    },
+    DelayedOperation::SyncGroup { ref group_id, ref member_id, generation_id } => {
+      let mut groups = broker.groups.lock().unwrap();
+      groups.tick(now_ms());
+      if !expired && groups.sync_result(group_id, generation_id, member_id).is_none() {
+        return None;
+      }
+      // without an assignment at the deadline, the member has to join again
+      ResponsePayload::SyncGroupResponse(sync_group_response(&groups, group_id, generation_id, member_id))
+    },
+  };
+
+  Some(ResponseMessage {
+    correlation_id: req.correlation_id,
+    response_payload: payload
+  })
+}
+
+// response to a request that failed, if its correlation id and API are
+// known. APIs with an error code per partition get a response without
+// topics, since the failed request does not tell which partitions to
+// answer for, so that clients fail the partitions they asked for right
+// away instead of waiting for their request timeout. Unsupported versions
+// are answered in the API's lowest version, which every client can parse
+pub fn error_response(error: &BrokerError) -> Option<ResponseMessage<'static>> {
+  let correlation_id = error.context.correlation_id?;
+  let api_key = error.context.api_key?;
+  let range = version_range(api_key)?;
+  let api_version = match error.context.api_version {
+    Some(v) if supported_version(api_key, v) => v,
+    _                                        => range.min_version,
+  };
+
+  let code = error.error_code();
+  let error_code = code.to_int();
+
+  let payload = match api_key {
+    0  => ResponsePayload::ProduceResponse(api_version, vec![]),
+    1  => ResponsePayload::FetchResponse(api_version, vec![]),
+    2  => ResponsePayload::OffsetResponse(api_version, vec![]),
+    3  => ResponsePayload::MetadataResponse(MetadataResponse { brokers: vec![], topics: vec![] }),
+    8  => ResponsePayload::OffsetCommitResponse(vec![]),
+    9  => ResponsePayload::OffsetFetchResponse(vec![]),
+    10 => {
+      let coordinator = ConsumerMetadataResponse { error_code, coordinator_id: -1, coordinator_host: "", coordinator_port: -1 };
+      if api_version == 0 {
+        ResponsePayload::ConsumerMetadataResponse(coordinator)
+      } else {
+        ResponsePayload::FindCoordinatorResponse(coordinator)
+      }
+    },
+    11 => ResponsePayload::JoinGroupResponse(join_group_error(code, "")),
+    12 => ResponsePayload::HeartbeatResponse(error_code),
+    13 => ResponsePayload::LeaveGroupResponse(error_code),
+    14 => ResponsePayload::SyncGroupResponse(SyncGroupResponse { error_code, member_assignment: vec![] }),
+    API_VERSIONS_KEY => ResponsePayload::ApiVersionsResponse(ApiVersionsResponse {
+      error_code,
+      api_versions: API_VERSIONS,
+      throttle_time_ms: if api_version >= 1 { Some(0) } else { None }
+    }),
+    _  => return None,
+  };
+
+  Some(ResponseMessage { correlation_id, response_payload: payload })
+}
+
+// None while the member waits for the rest of the group.
+// Only the leader receives the metadata of the members
+fn join_group_response(groups: &GroupCoordinator, group_id: &str, member_id: &str) -> Option<JoinGroupResponse> {
+  let (group, member) = match groups.join_result(group_id, member_id)? {
+    Ok(r)  => r,
+    Err(e) => return Some(join_group_error(e, ""))
+  };
+
+  let members = if member.id == group.leader_id {
+    group.members.values().map(|m| (m.id.clone(), m.metadata(&group.protocol).to_vec())).collect()
+  } else {
+    vec![]
+  };
+
+  Some(JoinGroupResponse {
+    error_code: ErrorCode::NoError.to_int(),
+    generation_id: group.generation_id,
+    group_protocol: group.protocol.clone(),
+    leader_id: group.leader_id.clone(),
+    member_id: member.id.clone(),
+    members
+  })
+}
+
+fn join_group_error(error: ErrorCode, member_id: &str) -> JoinGroupResponse {
+  JoinGroupResponse {
+    error_code: error.to_int(),
+    generation_id: -1,
+    group_protocol: String::new(),
+    leader_id: String::new(),
+    member_id: member_id.to_string(),
+    members: vec![]
+  }
+}
+
+fn sync_group_response(groups: &GroupCoordinator, group_id: &str, generation_id: i32, member_id: &str) -> SyncGroupResponse {
+  match groups.sync_result(group_id, generation_id, member_id) {
+    Some(Ok(assignment)) => SyncGroupResponse { error_code: ErrorCode::NoError.to_int(), member_assignment: assignment.to_vec() },
+    Some(Err(e))         => SyncGroupResponse { error_code: e.to_int(), member_assignment: vec![] },
+    None                 => SyncGroupResponse { error_code: ErrorCode::RebalanceInProgress.to_int(), member_assignment: vec![] },
+  }
+}
+
+// an empty topic list asks for the metadata of every topic
+fn handle_metadata<'a>(broker: &'a Broker, req: TopicMetadataRequest<'a>) -> MetadataResponse<'a> {
+  let registry = broker.topics.read().unwrap();
+  let names = if req.is_empty() { registry.topics() } else { req.iter().map(|t| t.to_string()).collect() };
+
+  let topics = names.into_iter().map(|topic_name| {
+    match registry.partitions(&topic_name) {
+      Some(leaders) => TopicMetadata {
+        topic_error_code: ErrorCode::NoError.to_int(),
+        topic_name,
+        partitions: leaders.iter().enumerate().map(|(partition_id, &leader)| PartitionMetadata {
+          partition_error_code: ErrorCode::NoError.to_int(),
+          partition_id: partition_id as i32,
+          leader,
+          replicas: vec![leader],
+          isr: vec![leader]
+        }).collect()
+      },
+      None          => TopicMetadata {
+        topic_error_code: if valid_topic_name(&topic_name) {
+          ErrorCode::UnknownTopicOrPartition.to_int()
+        } else {
+          ErrorCode::InvalidTopic.to_int()
+        },
+        topic_name,
+        partitions: vec![]
       }
-      _ => Err(0)
     }
+  }).collect();
+
+  MetadataResponse {
+    brokers: vec![BrokerMetadata {
+      node_id: broker.node.id,
+      host: &broker.node.host,
+      port: broker.node.port
+    }],
+    topics
+  }
+}
+
+// without replicas, acks = -1 (all the in sync replicas) is the same as 1
+fn handle_produce<'a>(broker: &Broker, req: ProduceRequest<'a>) -> ProduceResponse<'a> {
+  if req.required_acks < -1 || req.required_acks > 1 {
+    return req.topics.iter().map(|topic| {
+      let partitions = topic.partitions.iter().map(|p| (p.partition, ErrorCode::InvalidRequiredAcks.to_int(), -1, -1)).collect();
+      (topic.topic_name, partitions)
+    }).collect();
+  }
+
+  req.topics.iter().map(|topic| {
+    if topic.topic_name == OFFSETS_TOPIC {
+      let partitions = topic.partitions.iter().map(|p| (p.partition, ErrorCode::InvalidTopic.to_int(), -1, -1)).collect();
+      return (topic.topic_name, partitions);
+    }
+
+    broker.maybe_create_topic(topic.topic_name);
+
+    let timestamp_type = broker.message_timestamp_type;
+    let compression = broker.compression;
+    let check_crcs = broker.check_crcs;
+    let partitions = topic.partitions.iter().map(|p| {
+      let result = match broker.logs.get(topic.topic_name, p.partition) {
+        Some(_) if check_crcs && !check_entries(p.records_bytes) => Err(ErrorCode::CorruptMessage.into()),
+        Some(log) => append_records(&mut log.lock().unwrap(), &p.records, timestamp_type, compression, check_crcs, now_ms()),
+        None      => Err(ErrorCode::UnknownTopicOrPartition.into())
+      };
+
+      match result {
+        Ok((base_offset, log_append_time)) => {
+          broker.appends.fetch_add(1, Ordering::SeqCst);
+          (p.partition, ErrorCode::NoError.to_int(), base_offset, log_append_time)
+        },
+        Err(e) => {
+          let e = e.with_partition(topic.topic_name, p.partition);
+          if let ErrorKind::Storage(_) = e.kind {
+            error!("could not append: {}", e);
+          }
+          (p.partition, e.error_code().to_int(), -1, -1)
+        }
+      }
+    }).collect();
+
+    (topic.topic_name, partitions)
+  }).collect()
+}
+
+// validates every message or record batch of the set before appending
+// them, so that a partition either gets the whole message set or nothing.
+// Checksums of the inner messages of compressed messages are checked with
+// `check_crcs`, the message set itself is checked before.
+// Compressed messages are decompressed to check them and assign offsets to
+// the inner messages, then stored with `compression`, or with the codec
+// they were produced with if it is None.
+// With LogAppendTime, timestamps are replaced with `now`.
+// Returns the offset assigned to the first message, and the log append time
+// if it was used
+fn append_records(log: &mut PartitionLog, records: &Records, timestamp_type: TimestampType, compression: Option<Compression>, check_crcs: bool, now: i64) -> Result<(i64, i64), BrokerError> {
+  let log_append_time = timestamp_type == TimestampType::LogAppendTime;
+  let base_offset = log.log_end_offset();
+  // entries as stored in the log: the offset written in their header, the
+  // bytes following it, and the timestamp used in the time index.
+  // Messages without a timestamp are indexed with the time they were appended
+  let mut entries: Vec<(i64, Vec<u8>, i64)> = Vec::new();
+  match *records {
+    Records::MessageSet(ref message_set) => {
+      // the values of wrapper messages are decompressed first, since the
+      // inner messages borrow them
+      let mut values: Vec<Option<Vec<u8>>> = Vec::new();
+      for m in message_set.iter() {
+        values.push(match codec((m.message.attributes & MESSAGE_COMPRESSION_CODEC_MASK) as i16)? {
+          Compression::None => None,
+          c                 => {
+            let value = m.message.value.ok_or(ErrorCode::CorruptMessage)?;
+            Some(c.decompress(value).map_err(|_| ErrorCode::CorruptMessage)?)
+          },
+        });
+      }
+
+      // messages grouped by the entry they were produced in
+      let mut groups: Vec<(Compression, Vec<Message>)> = Vec::new();
+      for (m, value) in message_set.iter().zip(values.iter()) {
+        let value = match *value {
+          Some(ref v) => v,
+          None        => {
+            groups.push((Compression::None, vec![stamp_message(&m.message, log_append_time, now)?]));
+            continue;
+          }
+        };
+
+        if check_crcs && !check_entries(value) {
+          return Err(ErrorCode::CorruptMessage.into());
+        }
+
+        // inner messages have the magic byte of their wrapper, and cannot
+        // be compressed again
+        let inner = match message_set_messages(value) {
+          Done(_, ref inner) if !inner.is_empty() => inner.iter().map(|i| {
+            if i.message.magic_byte != m.message.magic_byte || i.message.attributes & MESSAGE_COMPRESSION_CODEC_MASK != 0 {
+              Err(ErrorCode::CorruptMessage)
+            } else {
+              stamp_message(&i.message, log_append_time, now)
+            }
+          }).collect::<Result<Vec<Message>, ErrorCode>>()?,
+          _ => return Err(ErrorCode::CorruptMessage.into()),
+        };
+        let c = codec((m.message.attributes & MESSAGE_COMPRESSION_CODEC_MASK) as i16)?;
+        groups.push((c, inner));
+      }
+
+      let groups = match compression {
+        None                    => groups,
+        Some(Compression::None) => groups.into_iter()
+          .flat_map(|(_, messages)| messages)
+          .map(|m| (Compression::None, vec![m]))
+          .collect(),
+        // a wrapper holds messages of a single magic byte
+        Some(c)                 => {
+          let mut merged: Vec<(Compression, Vec<Message>)> = Vec::new();
+          for m in groups.into_iter().flat_map(|(_, messages)| messages) {
+            match merged.last_mut() {
+              Some(&mut (_, ref mut messages)) if messages[0].magic_byte == m.magic_byte => messages.push(m),
+              _ => merged.push((c, vec![m])),
+            }
+          }
+          merged
+        },
+      };
+
+      let mut next_offset = base_offset;
+      for (c, messages) in groups {
+        if c == Compression::None {
+          for message in messages.iter() {
+            let mut bytes: Vec<u8> = Vec::new();
+            ser_message(message, &mut bytes);
+            entries.push((next_offset, bytes, message.timestamp.filter(|t| *t >= 0).unwrap_or(now)));
+            next_offset += 1;
+          }
+          continue;
+        }
+
+        // inner offsets are absolute for magic byte 0, and relative to the
+        // first inner message for magic byte 1. The wrapper holds the offset
+        // of the last one
+        let magic_byte = messages[0].magic_byte;
+        let max_timestamp = messages.iter().filter_map(|m| m.timestamp).max().unwrap_or(-1);
+        let last_offset = next_offset + messages.len() as i64 - 1;
+        let inner: MessageSet = messages.into_iter().enumerate().map(|(i, message)| OMsMessage {
+          offset: if magic_byte == 0 { next_offset + i as i64 } else { i as i64 },
+          message,
+        }).collect();
+
+        let mut inner_bytes: Vec<u8> = Vec::new();
+        ser_message_set(&inner, &mut inner_bytes);
+        let value = c.compress(&inner_bytes).map_err(|_| ErrorCode::CorruptMessage)?;
+
+        let timestamp = if log_append_time { now } else { max_timestamp };
+        let wrapper = Message {
+          magic_byte,
+          attributes: c.id() as i8 | if magic_byte == 1 && log_append_time { MESSAGE_TIMESTAMP_TYPE_MASK } else { 0 },
+          timestamp: if magic_byte == 1 { Some(timestamp) } else { None },
+          key: None,
+          value: Some(&value),
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        ser_message(&wrapper, &mut bytes);
+        entries.push((last_offset, bytes, if timestamp < 0 { now } else { timestamp }));
+        next_offset = last_offset + 1;
+      }
+    },
+    Records::RecordBatches(ref batches) => {
+      // records of compressed batches, decompressed
+      let mut decompressed: Vec<(Compression, Option<Vec<u8>>)> = Vec::new();
+      for batch in batches.iter() {
+        let c = codec(batch.attributes)?;
+        decompressed.push(match c {
+          Compression::None => (c, None),
+          _                 => (c, Some(c.decompress(batch.records).map_err(|_| ErrorCode::CorruptMessage)?)),
+        });
+      }
+
+      // records compressed again when another codec is configured
+      let mut recompressed: Vec<Option<(Compression, Vec<u8>)>> = Vec::new();
+      for (batch, &(c, ref value)) in batches.iter().zip(decompressed.iter()) {
+        let records = value.as_ref().map(|v| &v[..]).unwrap_or(batch.records);
+        // offsets are assigned from the base offset, so the batch must
+        // hold one record per offset delta
+        match batch_records_from(records, batch.record_count) {
+          Done(_, ref r) if !r.is_empty() && batch.last_offset_delta == r.len() as i32 - 1 => {},
+          _ => return Err(ErrorCode::CorruptMessage.into()),
+        }
+
+        let target = compression.unwrap_or(c);
+        recompressed.push(if target == c {
+          None
+        } else {
+          Some((target, target.compress(records).map_err(|_| ErrorCode::CorruptMessage)?))
+        });
+      }
+
+      let mut next_offset = base_offset;
+      for (batch, recompressed) in batches.iter().zip(recompressed.iter()) {
+        let mut batch = if log_append_time {
+          RecordBatch { attributes: batch.attributes | TIMESTAMP_TYPE_MASK, max_timestamp: now, ..*batch }
+        } else {
+          RecordBatch { attributes: batch.attributes & !TIMESTAMP_TYPE_MASK, ..*batch }
+        };
+        if let Some((target, ref records)) = *recompressed {
+          batch.attributes = (batch.attributes & !COMPRESSION_CODEC_MASK) | target.id();
+          batch.records = records;
+        }
+
+        // the log writes its own base offset before the batch
+        let mut bytes: Vec<u8> = Vec::new();
+        ser_record_batch(&batch, &mut bytes);
+        let timestamp = if batch.max_timestamp < 0 { now } else { batch.max_timestamp };
+        entries.push((next_offset, bytes.split_off(ENTRY_HEADER_SIZE), timestamp));
+        next_offset += batch.last_offset_delta as i64 + 1;
+      }
+    },
+  }
+
+  if entries.iter().any(|(_, bytes, _)| bytes.len() > MAX_MESSAGE_SIZE) {
+    return Err(ErrorCode::MessageTooLarge.into());
+  }
+
+  for (offset, entry, timestamp) in entries.iter() {
+    log.append_entry(*offset, entry, *timestamp)?;
+  }
+
+  Ok((base_offset, if log_append_time { now } else { -1 }))
+}
+
+// codec of the compression bits of message or batch attributes
+fn codec(attributes: i16) -> Result<Compression, ErrorCode> {
+  Compression::from_id(attributes & COMPRESSION_CODEC_MASK).ok_or(ErrorCode::CorruptMessage)
+}
+
+// sets the timestamp of a legacy message according to the timestamp type
+fn stamp_message<'a>(message: &Message<'a>, log_append_time: bool, now: i64) -> Result<Message<'a>, ErrorCode> {
+  match message.magic_byte {
+    0 => Ok(message.clone()),
+    1 if log_append_time => Ok(Message {
+      attributes: message.attributes | MESSAGE_TIMESTAMP_TYPE_MASK,
+      timestamp: Some(now),
+      ..*message
+    }),
+    1 => Ok(Message { attributes: message.attributes & !MESSAGE_TIMESTAMP_TYPE_MASK, ..*message }),
+    _ => Err(ErrorCode::CorruptMessage),
+  }
+}
+
+// partitions share the `max_bytes` limit of the request, in order. Like
+// the limit of each partition, it is exceeded to return at least one entry
+fn handle_fetch<'a>(broker: &'a Broker, req: FetchRequest<'a>) -> FetchResponse<'a> {
+  let mut remaining = req.max_bytes.max(0) as usize;
+  let mut first = true;
+  req.topics.iter().map(|topic| {
+    let partitions = topic.partitions.iter().map(|p| {
+      match broker.logs.get(topic.topic_name, p.partition) {
+        None      => (p.partition, ErrorCode::UnknownTopicOrPartition.to_int(), -1, SharedBytes::from(vec![])),
+        Some(log) => {
+          let log = log.lock().unwrap();
+          let high_watermark = log.log_end_offset();
+          match log.read(p.fetch_offset, cmp::min(p.max_bytes.max(0) as usize, remaining)) {
+            Some(bytes) if bytes.len() > remaining && !first => (p.partition, ErrorCode::NoError.to_int(), high_watermark, SharedBytes::from(vec![])),
+            Some(bytes) => {
+              first = first && bytes.is_empty();
+              remaining = remaining.saturating_sub(bytes.len());
+              (p.partition, ErrorCode::NoError.to_int(), high_watermark, bytes)
+            },
+            None        => (p.partition, ErrorCode::OffsetOutOfRange.to_int(), high_watermark, SharedBytes::from(vec![]))
+          }
+        }
+      }
+    }).collect();
+
+    (topic.topic_name, partitions)
+  }).collect()
+}
+
+// version 0 lists segment offsets, version 1 finds the offset of a timestamp
+fn handle_offset<'a>(broker: &Broker, req: OffsetRequest<'a>, api_version: i16) -> OffsetResponse<'a> {
+  req.topics.iter().map(|topic| {
+    let partitions = topic.partitions.iter().map(|p| {
+      match broker.logs.get(topic.topic_name, p.partition) {
+        Some(log) if api_version == 0 => {
+          let offsets = log.lock().unwrap().offsets_before(p.time, p.max_number_of_offsets.max(0) as usize);
+          (p.partition, ErrorCode::NoError.to_int(), -1, offsets)
+        },
+        Some(log) => {
+          let (timestamp, offset) = log.lock().unwrap().offset_for_time(p.time);
+          (p.partition, ErrorCode::NoError.to_int(), timestamp, vec![offset])
+        },
+        None      => (p.partition, ErrorCode::UnknownTopicOrPartition.to_int(), -1, vec![])
+      }
+    }).collect();
+
+    (topic.topic_name, partitions)
+  }).collect()
+}
+
+// (partition, offset, commit timestamp, metadata) of each committed partition
+type PartitionCommits<'a> = Vec<(KafkaString<'a>, Vec<(i32, i64, i64, KafkaString<'a>)>)>;
+
+fn handle_offset_commit<'a>(broker: &Broker, req: OffsetCommitRequest<'a>) -> OffsetCommitResponse<'a> {
+  let now = now_ms();
+
+  // version 1 commits carry their own timestamp, and version 2 a retention time.
+  // Since version 1, commits name the group generation and member
+  let (group, member, topics, retention_ms): (KafkaString<'a>, Option<(i32, KafkaString<'a>)>, PartitionCommits<'a>, i64) = match req {
+    OffsetCommitRequest::V0(r) => {
+      let topics = r.topics.iter().map(|t| {
+        (t.topic_name, t.partitions.iter().map(|p| (p.partition, p.offset, now, p.metadata)).collect())
+      }).collect();
+      (r.consumer_group, None, topics, DEFAULT_OFFSET_RETENTION_MS)
+    },
+    OffsetCommitRequest::V1(r) => {
+      let topics = r.topics.iter().map(|t| {
+        (t.topic_name, t.partitions.iter().map(|p| {
+          (p.partition, p.offset, if p.timestamp == -1 { now } else { p.timestamp }, p.metadata)
+        }).collect())
+      }).collect();
+      (r.consumer_group, Some((r.consumer_group_generation_id, r.consumer_id)), topics, DEFAULT_OFFSET_RETENTION_MS)
+    },
+    OffsetCommitRequest::V2(r) => {
+      let topics = r.topics.iter().map(|t| {
+        (t.topic_name, t.partitions.iter().map(|p| (p.partition, p.offset, now, p.metadata)).collect())
+      }).collect();
+      let retention_ms = if r.retention_time == -1 { DEFAULT_OFFSET_RETENTION_MS } else { r.retention_time };
+      (r.consumer_group, Some((r.consumer_group_generation_id, r.consumer_id)), topics, retention_ms)
+    },
+  };
+
+  // the group coordinator is not locked while committing
+  let member_error = member.and_then(|(generation_id, member_id)| {
+    broker.groups.lock().unwrap().validate_commit(group, generation_id, member_id).err()
+  });
+
+  let mut offsets = broker.offsets.lock().unwrap();
+  let member_error = if offsets.loading() { Some(ErrorCode::GroupLoadInProgress) } else { member_error };
+  let offsets_log = broker.offsets_log();
+
+  // the valid commits are written together, then each partition is answered
+  let mut commits = Vec::new();
+  let checked: Vec<_> = topics.into_iter().map(|(topic_name, partitions)| {
+    let partitions: Vec<(i32, Option<ErrorCode>)> = partitions.into_iter().map(|(partition, offset, commit_timestamp, metadata)| {
+      if let Some(e) = member_error {
+        return (partition, Some(e));
+      }
+      if broker.logs.get(topic_name, partition).is_none() {
+        return (partition, Some(ErrorCode::UnknownTopicOrPartition));
+      }
+      if metadata.len() > MAX_METADATA_SIZE {
+        return (partition, Some(ErrorCode::OffsetMetadataTooLarge));
+      }
+
+      commits.push((topic_name, partition, CommittedOffset {
+        offset,
+        metadata: metadata.to_string(),
+        commit_timestamp,
+        expire_timestamp: commit_timestamp + retention_ms,
+      }));
+      (partition, None)
+    }).collect();
+
+    (topic_name, partitions)
+  }).collect();
+
+  let commit_error = if commits.is_empty() {
+    ErrorCode::NoError
+  } else {
+    match offsets.commit(&mut offsets_log.lock().unwrap(), group, commits) {
+      Ok(())  => ErrorCode::NoError,
+      Err(e)  => {
+        let e = BrokerError::from(e);
+        error!("could not commit offsets for group {}: {}", group, e);
+        e.error_code()
+      }
+    }
+  };
+
+  checked.into_iter().map(|(topic_name, partitions)| {
+    (topic_name, partitions.into_iter().map(|(partition, error)| (partition, error.unwrap_or(commit_error).to_int())).collect())
+  }).collect()
+}
+
+fn handle_api_versions(api_version: i16) -> ApiVersionsResponse<'static> {
+  if !supported_version(API_VERSIONS_KEY, api_version) {
+    return ApiVersionsResponse {
+      error_code: ErrorCode::UnsupportedVersion.to_int(),
+      api_versions: API_VERSIONS,
+      throttle_time_ms: None
+    };
+  }
+
+  ApiVersionsResponse {
+    error_code: ErrorCode::NoError.to_int(),
+    api_versions: API_VERSIONS,
+    throttle_time_ms: if api_version >= 1 { Some(0) } else { None }
+  }
+}
+
+// this broker coordinates every group, once the committed offsets are loaded
+fn handle_consumer_metadata<'a>(broker: &'a Broker, req: &ConsumerMetadataRequest) -> ConsumerMetadataResponse<'a> {
+  let error = if req.coordinator_type != GROUP_COORDINATOR || broker.offsets.lock().unwrap().loading() {
+    ErrorCode::GroupCoordinatorNotAvailable
+  } else if req.coordinator_key.is_empty() {
+    ErrorCode::InvalidGroupId
+  } else {
+    ErrorCode::NoError
+  };
+
+  if error != ErrorCode::NoError {
+    return ConsumerMetadataResponse {
+      error_code: error.to_int(),
+      coordinator_id: -1,
+      coordinator_host: "",
+      coordinator_port: -1
+    };
+  }
+
+  let node = &broker.node;
+  ConsumerMetadataResponse {
+    error_code: ErrorCode::NoError.to_int(),
+    coordinator_id: node.id,
+    coordinator_host: &node.host,
+    coordinator_port: node.port
+  }
+}
+
+// partitions without a committed offset get -1 and no error
+fn handle_offset_fetch<'a>(broker: &Broker, req: OffsetFetchRequest<'a>) -> OffsetFetchResponse<'a> {
+  let now = now_ms();
+  let offsets = broker.offsets.lock().unwrap();
+
+  req.topics.iter().map(|topic| {
+    let partitions = topic.partitions.iter().map(|p| {
+      if offsets.loading() {
+        return (p.partition, -1, String::new(), ErrorCode::GroupLoadInProgress.to_int());
+      }
+
+      match offsets.get(req.consumer_group, topic.topic_name, p.partition, now) {
+        Some(c) => (p.partition, c.offset, c.metadata.clone(), ErrorCode::NoError.to_int()),
+        None    => (p.partition, -1, String::new(), ErrorCode::NoError.to_int())
+      }
+    }).collect();
+
+    (topic.topic_name, partitions)
+  }).collect()
+}
+
+// a fetch can be answered once `min_bytes` are available in the requested
+// partitions, or right away if one of them would return an error
+fn fetch_ready(broker: &Broker, req: &FetchRequest) -> bool {
+  if req.max_wait_time <= 0 || req.min_bytes <= 0 {
+    return true;
+  }
+
+  let mut available: usize = 0;
+  for topic in req.topics.iter() {
+    for p in topic.partitions.iter() {
+      match broker.logs.get(topic.topic_name, p.partition).and_then(|log| log.lock().unwrap().read(p.fetch_offset, p.max_bytes.max(0) as usize)) {
+        Some(bytes) => available += bytes.len(),
+        None        => return true
+      }
+    }
+  }
+
+  available >= req.min_bytes as usize
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use parser::message::*;
+  use parser::fetch::*;
+  use parser::offset::*;
+  use parser::offset_commit::*;
+  use parser::offset_fetch::*;
+  use parser::consumer_metadata::*;
+  use parser::join_group::*;
+  use parser::sync_group::SyncGroupRequest;
+  use parser::heartbeat::HeartbeatRequest;
+  use parser::leave_group::LeaveGroupRequest;
+  use responses::fetch::{ser_message_set,ser_records};
+  use responses::primitive::{ser_i16,ser_i32,ser_kafka_string};
+  use parser::produce::produce_request as produce_request_parser;
+  use util::test_dir;
+
+  fn test_broker(dir: &Path) -> Broker {
+    let node = Node { id: 1, host: "localhost".to_string(), port: 9092 };
+    let mut broker = Broker::new(node, dir, 1024).unwrap();
+    broker.auto_create_topics = false;
+    broker.create_topic("topic1", 1).unwrap();
+    while broker.offsets.lock().unwrap().loading() {
+      broker.maintenance(0);
+    }
+    broker
+  }
+
+  fn message_set<'a>(values: &[&'a [u8]], magic_byte: i8) -> MessageSet<'a> {
+    values.iter().map(|v| OMsMessage {
+      offset: 0,
+      message: Message {
+        magic_byte,
+        attributes: 0,
+        timestamp: None,
+        key: None,
+        value: Some(v)
+      }
+    }).collect()
+  }
+
+  fn produce_request<'a>(partition: i32, message_set: MessageSet<'a>) -> ProduceRequest<'a> {
+    produce_records(partition, Records::MessageSet(message_set))
+  }
+
+  fn produce_records<'a>(partition: i32, records: Records<'a>) -> ProduceRequest<'a> {
+    ProduceRequest {
+      transactional_id: None,
+      required_acks: 1,
+      timeout: 1000,
+      topics: vec![TopicMessageSet {
+        topic_name: "topic1",
+        // checksums are only checked on parsed message sets
+        partitions: vec![PartitionMessageSet { partition, records, records_bytes: &[] }]
+      }]
+    }
+  }
+
+  #[test]
+  fn handle_metadata_test() {
+    let dir = test_dir("metadata");
+    let broker = test_broker(&dir);
+    broker.create_topic("topic2", 2).unwrap();
+
+    let topic1 = TopicMetadata {
+      topic_error_code: 0,
+      topic_name: "topic1".to_string(),
+      partitions: vec![PartitionMetadata { partition_error_code: 0, partition_id: 0, leader: 1, replicas: vec![1], isr: vec![1] }]
+    };
+
+    let res = handle_metadata(&broker, vec!["topic1", "topic3"]);
+    assert_eq!(res.brokers, vec![BrokerMetadata { node_id: 1, host: "localhost", port: 9092 }]);
+    assert_eq!(res.topics.len(), 2);
+    assert_eq!(res.topics[0], topic1);
+    assert_eq!(res.topics[1], TopicMetadata {
+      topic_error_code: ErrorCode::UnknownTopicOrPartition.to_int(),
+      topic_name: "topic3".to_string(),
+      partitions: vec![]
+    });
+
+    let res = handle_metadata(&broker, vec![]);
+    let names: Vec<&str> = res.topics.iter().map(|t| &t.topic_name[..]).collect();
+    assert_eq!(names, vec![OFFSETS_TOPIC, "topic1", "topic2"]);
+    assert_eq!(res.topics[2].partitions.len(), 2);
+
+    // partitions found on disk are announced after a restart
+    let broker = Broker::new(broker.node.clone(), &dir, 1024).unwrap();
+    assert_eq!(broker.topics.read().unwrap().topics(), vec![OFFSETS_TOPIC, "topic1", "topic2"]);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn auto_create_topics_test() {
+    let dir = test_dir("auto-create");
+    let mut broker = test_broker(&dir);
+    broker.auto_create_topics = true;
+    broker.num_partitions = 2;
+
+    broker.maybe_create_topic("topic2");
+    broker.maybe_create_topic("../topic3");
+    assert_eq!(broker.topics.read().unwrap().topics(), vec![OFFSETS_TOPIC, "topic1", "topic2"]);
+    assert!(broker.logs.get("topic2", 1).is_some());
+
+    let res = handle_metadata(&broker, vec!["../topic3"]);
+    assert_eq!(res.topics[0].topic_error_code, ErrorCode::InvalidTopic.to_int());
+
+    let mut req = produce_request(1, message_set(&[b"a"], 0));
+    req.topics[0].topic_name = "topic4";
+    let res = handle_produce(&broker, req);
+    assert_eq!(res, vec![("topic4", vec![(1, 0, 0, -1)])]);
+
+    // disabled by test_broker
+    let broker = test_broker(&dir);
+    broker.maybe_create_topic("topic5");
+    assert_eq!(broker.topics.read().unwrap().partitions("topic5"), None);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn handle_produce_test() {
+    let dir = test_dir("produce");
+    let broker = test_broker(&dir);
+
+    let res = handle_produce(&broker, produce_request(0, message_set(&[b"a", b"b"], 0)));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 0, -1)])]);
+    let res = handle_produce(&broker, produce_request(0, message_set(&[b"c"], 0)));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 2, -1)])]);
+    assert_eq!(broker.logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 3);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  fn fetch_request<'a>(partition: i32, fetch_offset: i64, max_bytes: i32) -> FetchRequest<'a> {
+    FetchRequest {
+      replica_id: -1,
+      max_wait_time: 0,
+      min_bytes: 0,
+      max_bytes: i32::MAX,
+      isolation_level: 0,
+      topics: vec![TopicFetch {
+        topic_name: "topic1",
+        partitions: vec![PartitionFetch { partition, fetch_offset, max_bytes }]
+      }]
+    }
+  }
+
+  #[test]
+  fn handle_fetch_test() {
+    let dir = test_dir("fetch");
+    let broker = test_broker(&dir);
+    handle_produce(&broker, produce_request(0, message_set(&[b"a", b"b", b"c"], 0)));
+
+    let mut expected = message_set(&[b"b", b"c"], 0);
+    expected[0].offset = 1;
+    expected[1].offset = 2;
+    let mut expected_bytes: Vec<u8> = vec![];
+    ser_message_set(&expected, &mut expected_bytes);
+
+    let res = handle_fetch(&broker, fetch_request(0, 1, 1024));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 3, SharedBytes::from(expected_bytes.clone()))])]);
+
+    // each message takes 27 bytes in the log
+    let res = handle_fetch(&broker, fetch_request(0, 0, 60));
+    assert_eq!(res[0].1[0].3.len(), 54);
+
+    let res = handle_fetch(&broker, fetch_request(0, 3, 1024));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 3, SharedBytes::from(vec![]))])]);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  fn batch<'a>(records: &'a [u8], attributes: i16, last_offset_delta: i32) -> RecordBatch<'a> {
+    RecordBatch {
+      base_offset: 0,
+      partition_leader_epoch: -1,
+      attributes,
+      last_offset_delta,
+      first_timestamp: 1000,
+      max_timestamp: 1001,
+      producer_id: -1,
+      producer_epoch: -1,
+      base_sequence: -1,
+      record_count: 2,
+      records
+    }
+  }
+
+  #[test]
+  fn handle_produce_record_batches_test() {
+    let dir = test_dir("produce-batches");
+    let broker = test_broker(&dir);
+
+    let records = vec![
+      Record { attributes: 0, timestamp_delta: 0, offset_delta: 0, key: None, value: Some(&b"a"[..]), headers: vec![] },
+      Record { attributes: 0, timestamp_delta: 1, offset_delta: 1, key: None, value: Some(&b"b"[..]), headers: vec![] },
+    ];
+    let mut records_bytes: Vec<u8> = vec![];
+    ser_records(&records, &mut records_bytes);
+
+    handle_produce(&broker, produce_request(0, message_set(&[b"x"], 0)));
+    let res = handle_produce(&broker, produce_records(0, Records::RecordBatches(vec![batch(&records_bytes, 0, 1)])));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 1, -1)])]);
+    assert_eq!(broker.logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 3);
+
+    // the batch is returned whole, with the offset assigned by the log
+    let mut expected = batch(&records_bytes, 0, 1);
+    expected.base_offset = 1;
+    let mut expected_bytes: Vec<u8> = vec![];
+    ser_record_batch(&expected, &mut expected_bytes);
+    let res = handle_fetch(&broker, fetch_request(0, 2, 1024));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 3, SharedBytes::from(expected_bytes.clone()))])]);
+
+    let res = handle_produce(&broker, produce_records(0, Records::RecordBatches(vec![batch(&records_bytes, 1, 1)])));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)])]);
+    let res = handle_produce(&broker, produce_records(0, Records::RecordBatches(vec![batch(&records_bytes, 0, 0)])));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)])]);
+    assert_eq!(broker.logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 3);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  fn wrapper<'a>(codec: Compression, inner: &MessageSet, magic_byte: i8, buffer: &'a mut Vec<u8>) -> MessageSet<'a> {
+    let mut inner_bytes: Vec<u8> = vec![];
+    ser_message_set(inner, &mut inner_bytes);
+    *buffer = codec.compress(&inner_bytes).unwrap();
+    let mut set = message_set(&[&buffer[..]], magic_byte);
+    set[0].message.attributes = codec.id() as i8;
+    if magic_byte == 1 {
+      set[0].message.timestamp = Some(0);
+    }
+    set
+  }
+
+  fn inner_messages(entry: &[u8]) -> (i64, i8, Vec<(i64, Vec<u8>)>) {
+    let wrapper = o_ms_message(entry).unwrap().1;
+    let c = Compression::from_id((wrapper.message.attributes & MESSAGE_COMPRESSION_CODEC_MASK) as i16).unwrap();
+    let value = c.decompress(wrapper.message.value.unwrap()).unwrap();
+    let inner = message_set_messages(&value).unwrap().1.iter().map(|m| (m.offset, m.message.value.unwrap().to_vec())).collect();
+    (wrapper.offset, wrapper.message.attributes, inner)
+  }
+
+  #[test]
+  fn handle_produce_compressed_messages_test() {
+    let dir = test_dir("produce-compressed");
+    let mut broker = test_broker(&dir);
+    handle_produce(&broker, produce_request(0, message_set(&[b"x"], 0)));
+
+    // inner offsets are relative with magic byte 1, and the wrapper holds
+    // the offset of the last inner message
+    let mut inner = message_set(&[b"a", b"b", b"c"], 1);
+    for (i, m) in inner.iter_mut().enumerate() {
+      m.message.timestamp = Some(1000 * (i as i64 + 1));
+    }
+    let mut buffer = vec![];
+    let res = handle_produce(&broker, produce_request(0, wrapper(Compression::Gzip, &inner, 1, &mut buffer)));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 1, -1)])]);
+    assert_eq!(broker.logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 4);
+
+    let res = handle_fetch(&broker, fetch_request(0, 2, 1024));
+    let wrapper_message = o_ms_message(&res[0].1[0].3).unwrap().1.message;
+    assert_eq!(wrapper_message.timestamp, Some(3000));
+    assert_eq!(inner_messages(&res[0].1[0].3), (3, 1, vec![(0, b"a".to_vec()), (1, b"b".to_vec()), (2, b"c".to_vec())]));
+
+    // every message gets its own entry when stored uncompressed
+    broker.compression = Some(Compression::None);
+    let mut buffer = vec![];
+    let res = handle_produce(&broker, produce_request(0, wrapper(Compression::Snappy, &inner, 1, &mut buffer)));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 4, -1)])]);
+    let res = handle_fetch(&broker, fetch_request(0, 5, 1024));
+    let stored = o_ms_message(&res[0].1[0].3).unwrap().1;
+    assert_eq!((stored.offset, stored.message.attributes, stored.message.value), (5, 0, Some(&b"b"[..])));
+
+    // uncompressed messages are grouped in a wrapper with absolute inner
+    // offsets for magic byte 0
+    broker.compression = Some(Compression::Zstd);
+    let res = handle_produce(&broker, produce_request(0, message_set(&[b"d", b"e"], 0)));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 7, -1)])]);
+    let res = handle_fetch(&broker, fetch_request(0, 7, 1024));
+    assert_eq!(inner_messages(&res[0].1[0].3), (8, 4, vec![(7, b"d".to_vec()), (8, b"e".to_vec())]));
+
+    let mut garbage = message_set(&[b"not gzip"], 0);
+    garbage[0].message.attributes = 1;
+    let res = handle_produce(&broker, produce_request(0, garbage));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)])]);
+
+    // wrappers cannot be nested, nor mix magic bytes
+    let mut buffer = vec![];
+    let nested = wrapper(Compression::Gzip, &message_set(&[b"f"], 0), 0, &mut buffer);
+    let mut buffer2 = vec![];
+    let res = handle_produce(&broker, produce_request(0, wrapper(Compression::Lz4, &nested, 0, &mut buffer2)));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)])]);
+    let mut buffer = vec![];
+    let res = handle_produce(&broker, produce_request(0, wrapper(Compression::Lz4, &inner, 0, &mut buffer)));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)])]);
+    assert_eq!(broker.logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 9);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn handle_produce_crc_test() {
+    let dir = test_dir("produce-crc");
+    let mut broker = test_broker(&dir);
+    broker.create_topic("topic2", 1).unwrap();
+
+    let mut set: Vec<u8> = vec![];
+    ser_message_set(&message_set(&[b"a"], 0), &mut set);
+    let mut corrupted = set.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 1;
+
+    // only the partition with the corrupted message is refused
+    let mut input: Vec<u8> = vec![];
+    ser_i16(1, &mut input);
+    ser_i32(1000, &mut input);
+    ser_i32(2, &mut input);
+    for &(topic, bytes) in [("topic1", &corrupted), ("topic2", &set)].iter() {
+      ser_kafka_string(topic, &mut input);
+      ser_i32(1, &mut input);
+      ser_i32(0, &mut input);
+      ser_i32(bytes.len() as i32, &mut input);
+      input.extend_from_slice(bytes);
+    }
+    let req = produce_request_parser(&input, 0).unwrap().1;
+    let res = handle_produce(&broker, req);
+    assert_eq!(res, vec![
+      ("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)]),
+      ("topic2", vec![(0, 0, 0, -1)]),
+    ]);
+
+    // inner messages of compressed messages are checked too
+    let mut buffer = vec![];
+    let inner = message_set(&[b"b"], 0);
+    let compressed_set = wrapper(Compression::Gzip, &inner, 0, &mut buffer);
+    let mut inner_bytes: Vec<u8> = vec![];
+    ser_message_set(&inner, &mut inner_bytes);
+    let last = inner_bytes.len() - 1;
+    inner_bytes[last] ^= 1;
+    let corrupted_value = Compression::Gzip.compress(&inner_bytes).unwrap();
+    let corrupted_wrapper = || {
+      let mut set = message_set(&[&corrupted_value[..]], 0);
+      set[0].message.attributes = 1;
+      set
+    };
+
+    let res = handle_produce(&broker, produce_request(0, corrupted_wrapper()));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)])]);
+    let res = handle_produce(&broker, produce_request(0, compressed_set));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 0, -1)])]);
+
+    // trusted producers are not checked
+    broker.check_crcs = false;
+    let req = produce_request_parser(&input, 0).unwrap().1;
+    let res = handle_produce(&broker, req);
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 1, -1)]), ("topic2", vec![(0, 0, 1, -1)])]);
+    let res = handle_produce(&broker, produce_request(0, corrupted_wrapper()));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 2, -1)])]);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn handle_produce_compressed_batches_test() {
+    let dir = test_dir("produce-compressed-batches");
+    let mut broker = test_broker(&dir);
+
+    let records = vec![
+      Record { attributes: 0, timestamp_delta: 0, offset_delta: 0, key: None, value: Some(&b"a"[..]), headers: vec![] },
+      Record { attributes: 0, timestamp_delta: 1, offset_delta: 1, key: None, value: Some(&b"b"[..]), headers: vec![] },
+    ];
+    let mut records_bytes: Vec<u8> = vec![];
+    ser_records(&records, &mut records_bytes);
+    let compressed = Compression::Lz4.compress(&records_bytes).unwrap();
+
+    // batches are kept as produced without a configured codec
+    let res = handle_produce(&broker, produce_records(0, Records::RecordBatches(vec![batch(&compressed, 3, 1)])));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 0, -1)])]);
+    let mut expected_bytes: Vec<u8> = vec![];
+    ser_record_batch(&batch(&compressed, 3, 1), &mut expected_bytes);
+    let res = handle_fetch(&broker, fetch_request(0, 0, 1024));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 2, SharedBytes::from(expected_bytes.clone()))])]);
+
+    broker.compression = Some(Compression::Snappy);
+    let res = handle_produce(&broker, produce_records(0, Records::RecordBatches(vec![batch(&compressed, 3, 1)])));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 2, -1)])]);
+    let res = handle_fetch(&broker, fetch_request(0, 2, 1024));
+    let stored = record_batch(&res[0].1[0].3).unwrap().1;
+    assert_eq!((stored.base_offset, stored.attributes & COMPRESSION_CODEC_MASK), (2, 2));
+    assert_eq!(Compression::Snappy.decompress(stored.records).unwrap(), records_bytes);
+
+    // the record count is checked against the decompressed records
+    let res = handle_produce(&broker, produce_records(0, Records::RecordBatches(vec![batch(&compressed, 3, 0)])));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)])]);
+    let res = handle_produce(&broker, produce_records(0, Records::RecordBatches(vec![batch(&records_bytes, 3, 1)])));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)])]);
+    assert_eq!(broker.logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 4);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn handle_fetch_max_bytes_test() {
+    let dir = test_dir("fetch-max-bytes");
+    let broker = test_broker(&dir);
+    handle_produce(&broker, produce_request(0, message_set(&[b"a", b"b", b"c"], 0)));
+
+    // the first partition gets 27 bytes of the request limit, leaving
+    // too little for the second one
+    let mut req = fetch_request(0, 0, 1024);
+    req.max_bytes = 30;
+    req.topics[0].partitions.push(PartitionFetch { partition: 0, fetch_offset: 1, max_bytes: 1024 });
+    let res = handle_fetch(&broker, req);
+    assert_eq!(res[0].1[0].3.len(), 27);
+    assert_eq!(res[0].1[1].3.len(), 0);
+
+    // a message larger than the limit is still returned to the first partition
+    let mut req = fetch_request(0, 0, 1024);
+    req.max_bytes = 10;
+    let res = handle_fetch(&broker, req);
+    assert_eq!(res[0].1[0].3.len(), 27);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn fetch_ready_test() {
+    let dir = test_dir("fetch-ready");
+    let broker = test_broker(&dir);
+
+    let mut req = fetch_request(0, 0, 1024);
+    assert!(fetch_ready(&broker, &req));
+
+    req.max_wait_time = 100;
+    req.min_bytes = 50;
+    assert!(!fetch_ready(&broker, &req));
+
+    handle_produce(&broker, produce_request(0, message_set(&[b"a"], 0)));
+    assert_eq!(broker.appends(), 1);
+    assert!(!fetch_ready(&broker, &req));
+
+    handle_produce(&broker, produce_request(0, message_set(&[b"b"], 0)));
+    assert!(fetch_ready(&broker, &req));
+
+    // errors are returned without waiting
+    let mut req = fetch_request(1, 0, 1024);
+    req.max_wait_time = 100;
+    req.min_bytes = 50;
+    assert!(fetch_ready(&broker, &req));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn handle_fetch_errors_test() {
+    let dir = test_dir("fetch-errors");
+    let broker = test_broker(&dir);
+
+    let res = handle_fetch(&broker, fetch_request(1, 0, 1024));
+    assert_eq!(res, vec![("topic1", vec![(1, ErrorCode::UnknownTopicOrPartition.to_int(), -1, SharedBytes::from(vec![]))])]);
+
+    let res = handle_fetch(&broker, fetch_request(0, 4, 1024));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::OffsetOutOfRange.to_int(), 0, SharedBytes::from(vec![]))])]);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  fn offset_request<'a>(partition: i32, time: i64, max_number_of_offsets: i32) -> OffsetRequest<'a> {
+    OffsetRequest {
+      replica_id: -1,
+      topics: vec![TopicOffset {
+        topic_name: "topic1",
+        partitions: vec![PartitionOffset { partition, time, max_number_of_offsets }]
+      }]
+    }
+  }
+
+  #[test]
+  fn handle_offset_test() {
+    let dir = test_dir("offset");
+    let broker = test_broker(&dir);
+    handle_produce(&broker, produce_request(0, message_set(&[b"a", b"b", b"c"], 0)));
+
+    let res = handle_offset(&broker, offset_request(0, -1, 1), 0);
+    assert_eq!(res, vec![("topic1", vec![(0, 0, -1, vec![3])])]);
+    let res = handle_offset(&broker, offset_request(0, -1, 10), 0);
+    assert_eq!(res, vec![("topic1", vec![(0, 0, -1, vec![3, 0])])]);
+    let res = handle_offset(&broker, offset_request(0, -2, 10), 0);
+    assert_eq!(res, vec![("topic1", vec![(0, 0, -1, vec![0])])]);
+    let res = handle_offset(&broker, offset_request(1, -1, 1), 0);
+    assert_eq!(res, vec![("topic1", vec![(1, ErrorCode::UnknownTopicOrPartition.to_int(), -1, vec![])])]);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn handle_produce_timestamps_test() {
+    let dir = test_dir("produce-timestamps");
+    let mut broker = test_broker(&dir);
+
+    let mut messages = message_set(&[b"a", b"b"], 1);
+    messages[0].message.timestamp = Some(1000);
+    messages[1].message.timestamp = Some(2000);
+    let res = handle_produce(&broker, produce_request(0, messages));
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 0, -1)])]);
+
+    let res = handle_offset(&broker, offset_request(0, 1500, 1), 1);
+    assert_eq!(res, vec![("topic1", vec![(0, 0, 2000, vec![1])])]);
+    let res = handle_offset(&broker, offset_request(0, 3000, 1), 1);
+    assert_eq!(res, vec![("topic1", vec![(0, 0, -1, vec![2])])]);
+
+    // the producer's timestamp is replaced with the log append time
+    broker.message_timestamp_type = TimestampType::LogAppendTime;
+    let mut messages = message_set(&[b"c"], 1);
+    messages[0].message.timestamp = Some(500);
+    let res = handle_produce(&broker, produce_request(0, messages));
+    let log_append_time = res[0].1[0].3;
+    assert!(log_append_time > 2000);
+
+    let res = handle_fetch(&broker, fetch_request(0, 2, 1024));
+    let stored = o_ms_message(&res[0].1[0].3).unwrap().1;
+    assert_eq!(stored.message.timestamp, Some(log_append_time));
+    assert_eq!(stored.message.attributes & MESSAGE_TIMESTAMP_TYPE_MASK, MESSAGE_TIMESTAMP_TYPE_MASK);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  fn offset_commit_v2<'a>(group: &'a str, partition: i32, offset: i64, metadata: &'a str) -> OffsetCommitRequest<'a> {
+    OffsetCommitRequest::V2(OffsetCommitRequestV2 {
+      consumer_group: group,
+      consumer_group_generation_id: -1,
+      consumer_id: "",
+      retention_time: -1,
+      topics: vec![TopicOffsetCommitV0 {
+        topic_name: "topic1",
+        partitions: vec![PartitionOffsetCommitV0 { partition, offset, metadata }]
+      }]
+    })
+  }
+
+  fn offset_fetch_request<'a>(group: &'a str, partitions: &[i32]) -> OffsetFetchRequest<'a> {
+    OffsetFetchRequest {
+      consumer_group: group,
+      topics: vec![TopicOffsetFetch {
+        topic_name: "topic1",
+        partitions: partitions.iter().map(|&partition| PartitionOffsetFetch { partition }).collect()
+      }]
+    }
+  }
+
+  #[test]
+  fn handle_offset_commit_test() {
+    let dir = test_dir("offset-commit");
+    {
+      let broker = test_broker(&dir);
+
+      let res = handle_offset_commit(&broker, offset_commit_v2("group1", 0, 42, "meta"));
+      assert_eq!(res, vec![("topic1", vec![(0, 0)])]);
+
+      let res = handle_offset_commit(&broker, OffsetCommitRequest::V1(OffsetCommitRequestV1 {
+        consumer_group: "group2",
+        consumer_group_generation_id: -1,
+        consumer_id: "",
+        topics: vec![TopicOffsetCommitV1 {
+          topic_name: "topic1",
+          partitions: vec![PartitionOffsetCommitV1 { partition: 0, offset: 7, timestamp: now_ms(), metadata: "" }]
+        }]
+      }));
+      assert_eq!(res, vec![("topic1", vec![(0, 0)])]);
+
+      let res = handle_offset_commit(&broker, offset_commit_v2("group1", 1, 42, ""));
+      assert_eq!(res, vec![("topic1", vec![(1, ErrorCode::UnknownTopicOrPartition.to_int())])]);
+
+      let large = "a".repeat(MAX_METADATA_SIZE + 1);
+      let res = handle_offset_commit(&broker, offset_commit_v2("group1", 0, 43, &large));
+      assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::OffsetMetadataTooLarge.to_int())])]);
+
+      let res = handle_offset_fetch(&broker, offset_fetch_request("group1", &[0, 1]));
+      assert_eq!(res, vec![("topic1", vec![(0, 42, "meta".to_string(), 0), (1, -1, String::new(), 0)])]);
+    }
+
+    // commits are replayed from the offsets topic after a restart
+    let broker = test_broker(&dir);
+    let res = handle_offset_fetch(&broker, offset_fetch_request("group1", &[0]));
+    assert_eq!(res, vec![("topic1", vec![(0, 42, "meta".to_string(), 0)])]);
+    let res = handle_offset_fetch(&broker, offset_fetch_request("group2", &[0]));
+    assert_eq!(res, vec![("topic1", vec![(0, 7, String::new(), 0)])]);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn handle_produce_errors_test() {
+    let dir = test_dir("produce-errors");
+    let broker = test_broker(&dir);
+
+    let res = handle_produce(&broker, produce_request(1, message_set(&[b"a"], 0)));
+    assert_eq!(res, vec![("topic1", vec![(1, ErrorCode::UnknownTopicOrPartition.to_int(), -1, -1)])]);
+
+    let res = handle_produce(&broker, produce_request(0, message_set(&[b"a"], 42)));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::CorruptMessage.to_int(), -1, -1)])]);
+
+    let large = vec![0u8; MAX_MESSAGE_SIZE];
+    let res = handle_produce(&broker, produce_request(0, message_set(&[b"a", &large[..]], 0)));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::MessageTooLarge.to_int(), -1, -1)])]);
+
+    assert_eq!(broker.logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 0);
+
+    let mut req = produce_request(0, message_set(&[b"a"], 0));
+    req.topics[0].topic_name = OFFSETS_TOPIC;
+    let res = handle_produce(&broker, req);
+    assert_eq!(res, vec![(OFFSETS_TOPIC, vec![(0, ErrorCode::InvalidTopic.to_int(), -1, -1)])]);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn produce_acks_test() {
+    let dir = test_dir("produce-acks");
+    let broker = test_broker(&dir);
+
+    let mut req = produce_request(0, message_set(&[b"a"], 0));
+    req.required_acks = 2;
+    let res = handle_produce(&broker, req);
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::InvalidRequiredAcks.to_int(), -1, -1)])]);
+    assert_eq!(broker.logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 0);
+
+    // the message is appended, but the client does not expect a response
+    let mut req = produce_request(0, message_set(&[b"a"], 0));
+    req.required_acks = 0;
+    match handle_request(&broker, request(RequestPayload::ProduceRequest(req))) {
+      RequestResult::NoResponse => {},
+      other                     => panic!("expected no response, got {:?}", other),
+    }
+    assert_eq!(broker.logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 1);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  fn request<'a>(request_payload: RequestPayload<'a>) -> RequestMessage<'a> {
+    RequestMessage { api_version: 1, correlation_id: 1, client_id: Some("client"), request_payload }
+  }
+
+  fn response(result: RequestResult) -> ResponsePayload {
+    match result {
+      RequestResult::Response(res) => res.response_payload,
+      other                        => panic!("expected a response, got {:?}", other),
+    }
+  }
+
+  fn delayed(result: RequestResult) -> DelayedOperation {
+    match result {
+      RequestResult::Delayed(operation, _) => operation,
+      other                                => panic!("expected a delayed operation, got {:?}", other),
+    }
+  }
+
+  fn join_group<'a>(member_id: &'a str) -> RequestMessage<'a> {
+    request(RequestPayload::JoinGroupRequest(JoinGroupRequest {
+      group_id: "group1",
+      session_timeout: 10000,
+      rebalance_timeout: 10000,
+      member_id,
+      protocol_type: "consumer",
+      group_protocols: vec![GroupProtocol { protocol_name: "range", protocol_metadata: &[1] }]
+    }))
+  }
+
+  fn sync_group<'a>(generation_id: i32, member_id: &'a str, group_assignment: Vec<(&'a str, &'a [u8])>) -> RequestMessage<'a> {
+    request(RequestPayload::SyncGroupRequest(SyncGroupRequest { group_id: "group1", generation_id, member_id, group_assignment }))
+  }
+
+  fn commit_generation<'a>(generation_id: i32, member_id: &'a str) -> OffsetCommitRequest<'a> {
+    match offset_commit_v2("group1", 0, 1, "") {
+      OffsetCommitRequest::V2(r) => OffsetCommitRequest::V2(OffsetCommitRequestV2 {
+        consumer_group_generation_id: generation_id,
+        consumer_id: member_id,
+        ..r
+      }),
+      _ => unreachable!(),
+    }
+  }
+
+  #[test]
+  fn handle_group_test() {
+    let dir = test_dir("group");
+    let broker = test_broker(&dir);
+
+    let m1 = match response(handle_request(&broker, join_group(""))) {
+      ResponsePayload::JoinGroupResponse(r) => {
+        assert_eq!((r.error_code, r.generation_id, &r.group_protocol[..]), (0, 1, "range"));
+        assert_eq!(r.leader_id, r.member_id);
+        assert_eq!(r.members, vec![(r.member_id.clone(), vec![1])]);
+        r.member_id
+      },
+      other => panic!("unexpected response {:?}", other),
+    };
+    assert_eq!(response(handle_request(&broker, sync_group(1, &m1, vec![(&m1, &[5])]))),
+      ResponsePayload::SyncGroupResponse(SyncGroupResponse { error_code: 0, member_assignment: vec![5] }));
+
+    // a second member triggers a rebalance
+    let mut join2 = delayed(handle_request(&broker, join_group("")));
+    let m2 = match join2 {
+      DelayedOperation::JoinGroup { ref member_id, .. } => member_id.clone(),
+      _ => panic!("unexpected operation {:?}", join2),
+    };
+    let heartbeat = request(RequestPayload::HeartbeatRequest(HeartbeatRequest { group_id: "group1", generation_id: 1, member_id: &m1 }));
+    assert_eq!(response(handle_request(&broker, heartbeat)),
+      ResponsePayload::HeartbeatResponse(ErrorCode::RebalanceInProgress.to_int()));
+    assert_eq!(complete_delayed(&broker, &mut join2, join_group(""), false), None);
+
+    match response(handle_request(&broker, join_group(&m1))) {
+      ResponsePayload::JoinGroupResponse(r) => assert_eq!((r.generation_id, r.leader_id, r.members.len()), (2, m1.clone(), 2)),
+      other => panic!("unexpected response {:?}", other),
+    }
+    match complete_delayed(&broker, &mut join2, join_group(""), false).map(|r| r.response_payload) {
+      Some(ResponsePayload::JoinGroupResponse(r)) => {
+        assert_eq!((r.generation_id, r.leader_id, r.member_id), (2, m1.clone(), m2.clone()));
+        assert_eq!(r.members, vec![]);
+      },
+      other => panic!("unexpected response {:?}", other),
+    }
+
+    // the follower gets its assignment once the leader sent it
+    let mut sync2 = delayed(handle_request(&broker, sync_group(2, &m2, vec![])));
+    assert_eq!(complete_delayed(&broker, &mut sync2, sync_group(2, &m2, vec![]), false), None);
+    response(handle_request(&broker, sync_group(2, &m1, vec![(&m1, &[6]), (&m2, &[7])])));
+    assert_eq!(complete_delayed(&broker, &mut sync2, sync_group(2, &m2, vec![]), false).map(|r| r.response_payload),
+      Some(ResponsePayload::SyncGroupResponse(SyncGroupResponse { error_code: 0, member_assignment: vec![7] })));
+
+    // commits are checked against the current generation
+    let res = handle_offset_commit(&broker, commit_generation(1, &m2));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::IllegalGeneration.to_int())])]);
+    let res = handle_offset_commit(&broker, commit_generation(2, "unknown"));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::UnknownMemberId.to_int())])]);
+    let res = handle_offset_commit(&broker, commit_generation(2, &m2));
+    assert_eq!(res, vec![("topic1", vec![(0, 0)])]);
+
+    let leave = request(RequestPayload::LeaveGroupRequest(LeaveGroupRequest { group_id: "group1", member_id: &m2 }));
+    assert_eq!(response(handle_request(&broker, leave)), ResponsePayload::LeaveGroupResponse(0));
+    let res = handle_offset_commit(&broker, commit_generation(2, &m1));
+    assert_eq!(res, vec![("topic1", vec![(0, ErrorCode::RebalanceInProgress.to_int())])]);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn expired_join_group_test() {
+    let dir = test_dir("expired-join");
+    let broker = test_broker(&dir);
+
+    response(handle_request(&broker, join_group("")));
+
+    // the first member does not join again before the deadline
+    let mut join2 = delayed(handle_request(&broker, join_group("")));
+    assert_eq!(complete_delayed(&broker, &mut join2, join_group(""), false), None);
+    match complete_delayed(&broker, &mut join2, join_group(""), true).map(|r| r.response_payload) {
+      Some(ResponsePayload::JoinGroupResponse(r)) => assert_eq!(r, join_group_error(ErrorCode::RebalanceInProgress, "")),
+      other => panic!("unexpected response {:?}", other),
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+
+  #[test]
+  fn handle_consumer_metadata_test() {
+    let dir = test_dir("consumer-metadata");
+    let broker = Broker::new(Node { id: 1, host: "localhost".to_string(), port: 9092 }, &dir, 1024).unwrap();
+
+    let find_coordinator = |api_version, coordinator_type| RequestMessage {
+      api_version,
+      correlation_id: 1,
+      client_id: Some("client"),
+      request_payload: RequestPayload::ConsumerMetadataRequest(ConsumerMetadataRequest { coordinator_key: "group1", coordinator_type })
+    };
+    let not_available = ConsumerMetadataResponse {
+      error_code: ErrorCode::GroupCoordinatorNotAvailable.to_int(),
+      coordinator_id: -1,
+      coordinator_host: "",
+      coordinator_port: -1
+    };
+
+    // the offsets topic is not loaded yet
+    assert_eq!(response(handle_request(&broker, find_coordinator(0, GROUP_COORDINATOR))),
+      ResponsePayload::ConsumerMetadataResponse(not_available));
+    assert_eq!(handle_offset_fetch(&broker, offset_fetch_request("group1", &[0])),
+      vec![("topic1", vec![(0, -1, String::new(), ErrorCode::GroupLoadInProgress.to_int())])]);
+    match response(handle_request(&broker, join_group(""))) {
+      ResponsePayload::JoinGroupResponse(r) => assert_eq!(r.error_code, ErrorCode::GroupLoadInProgress.to_int()),
+      other => panic!("unexpected response {:?}", other),
+    }
+
+    broker.maintenance(0);
+    assert!(!broker.offsets.lock().unwrap().loading());
+    let coordinator = ConsumerMetadataResponse { error_code: 0, coordinator_id: 1, coordinator_host: "localhost", coordinator_port: 9092 };
+    assert_eq!(response(handle_request(&broker, find_coordinator(0, GROUP_COORDINATOR))),
+      ResponsePayload::ConsumerMetadataResponse(coordinator));
+    let coordinator = ConsumerMetadataResponse { error_code: 0, coordinator_id: 1, coordinator_host: "localhost", coordinator_port: 9092 };
+    assert_eq!(response(handle_request(&broker, find_coordinator(1, GROUP_COORDINATOR))),
+      ResponsePayload::FindCoordinatorResponse(coordinator));
+    let not_available = ConsumerMetadataResponse {
+      error_code: ErrorCode::GroupCoordinatorNotAvailable.to_int(),
+      coordinator_id: -1,
+      coordinator_host: "",
+      coordinator_port: -1
+    };
+    assert_eq!(response(handle_request(&broker, find_coordinator(1, TRANSACTION_COORDINATOR))),
+      ResponsePayload::FindCoordinatorResponse(not_available));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn handle_api_versions_test() {
+    let res = handle_api_versions(1);
+    assert_eq!((res.error_code, res.throttle_time_ms), (0, Some(0)));
+    assert_eq!(res.api_versions, API_VERSIONS);
+
+    let res = handle_api_versions(0);
+    assert_eq!((res.error_code, res.throttle_time_ms), (0, None));
+
+    let res = handle_api_versions(3);
+    assert_eq!((res.error_code, res.throttle_time_ms), (ErrorCode::UnsupportedVersion.to_int(), None));
+    assert_eq!(res.api_versions, API_VERSIONS);
+  }
+
+  #[test]
+  fn error_response_test() {
+    use parser::errors::InputError;
+
+    let res = error_response(&BrokerError::parse(InputError::ParserError).with_request(12, 0, 8)).unwrap();
+    assert_eq!(res.response_payload, ResponsePayload::HeartbeatResponse(ErrorCode::InvalidRequest.to_int()));
+
+    let res = error_response(&BrokerError::parse(InputError::InvalidMessage).with_request(14, 0, 9)).unwrap();
+    assert_eq!(res.response_payload,
+      ResponsePayload::SyncGroupResponse(SyncGroupResponse { error_code: ErrorCode::CorruptMessage.to_int(), member_assignment: vec![] }));
+
+    let res = error_response(&BrokerError::parse(InputError::ParserError).with_request(10, 1, 10)).unwrap();
+    match res.response_payload {
+      ResponsePayload::FindCoordinatorResponse(r) => assert_eq!((r.error_code, r.coordinator_id), (ErrorCode::InvalidRequest.to_int(), -1)),
+      other                                       => panic!("unexpected response {:?}", other),
+    }
+
+    // ApiVersions is answered in version 0 whatever the version asked
+    let res = error_response(&BrokerError::parse(InputError::ParserError).with_request(API_VERSIONS_KEY, 5, 11)).unwrap();
+    assert_eq!(res.response_payload, ResponsePayload::ApiVersionsResponse(ApiVersionsResponse {
+      error_code: ErrorCode::InvalidRequest.to_int(),
+      api_versions: API_VERSIONS,
+      throttle_time_ms: None
+    }));
+
+    // unsupported versions are answered in the lowest version's layout
+    let res = error_response(&BrokerError::parse(InputError::UnsupportedVersion).with_request(12, 1, 7)).unwrap();
+    assert_eq!(res.response_payload, ResponsePayload::HeartbeatResponse(ErrorCode::UnsupportedVersion.to_int()));
+    let res = error_response(&BrokerError::parse(InputError::UnsupportedVersion).with_request(0, 99, 7)).unwrap();
+    assert_eq!(res, ResponseMessage { correlation_id: 7, response_payload: ResponsePayload::ProduceResponse(0, vec![]) });
+
+    // APIs with per partition error codes are answered without topics
+    let res = error_response(&BrokerError::parse(InputError::InvalidMessage).with_request(0, 2, 12)).unwrap();
+    assert_eq!(res, ResponseMessage { correlation_id: 12, response_payload: ResponsePayload::ProduceResponse(2, vec![]) });
+    let res = error_response(&BrokerError::parse(InputError::ParserError).with_request(1, 4, 13)).unwrap();
+    assert_eq!(res, ResponseMessage { correlation_id: 13, response_payload: ResponsePayload::FetchResponse(4, vec![]) });
+
+    // without a correlation id or a known API, there is nothing to answer
+    assert_eq!(error_response(&BrokerError::parse(InputError::ParserError)), None);
+    assert_eq!(error_response(&BrokerError::parse(InputError::NotImplemented).with_request(4, 0, 11)), None);
+  }
 }
@@ -0,0 +1,74 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+
+use parser::primitive::*;
+use parser::api_versions::ApiVersion;
+
+use nom::{HexDisplay,Needed,IResult,FileProducer};
+use nom::{Consumer,ConsumerState};
+use nom::IResult::*;
+
+use responses::primitive::*;
+
+/*
+ApiVersionsResponse => ErrorCode [ApiVersions] ThrottleTimeMs
+  ErrorCode => int16
+  ApiVersions => ApiKey MinVersion MaxVersion
+    ApiKey => int16
+    MinVersion => int16
+    MaxVersion => int16
+  ThrottleTimeMs => int32 (since v1)
+*/
+
+#[derive(Debug,PartialEq)]
+pub struct ApiVersionsResponse<'a> {
+  pub error_code: i16,
+  pub api_versions: &'a [ApiVersion],
+  // None for version 0
+  pub throttle_time_ms: Option<i32>
+}
+
+pub fn ser_api_versions_response<'a>(r: &ApiVersionsResponse<'a>, output: &mut Vec<u8>) {
+  ser_i16(r.error_code, output);
+  ser_i32(r.api_versions.len() as i32, output);
+  for v in r.api_versions {
+    ser_i16(v.api_key, output);
+    ser_i16(v.min_version, output);
+    ser_i16(v.max_version, output);
+  }
+  if let Some(throttle_time_ms) = r.throttle_time_ms {
+    ser_i32(throttle_time_ms, output);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ser_api_versions_response_test() {
+    let api_versions = [ApiVersion { api_key: 8, min_version: 0, max_version: 2 }];
+    let mut v: Vec<u8> = vec![];
+    ser_api_versions_response(&ApiVersionsResponse {
+      error_code: 0,
+      api_versions: &api_versions,
+      throttle_time_ms: Some(0)
+    }, &mut v);
+    assert_eq!(&v[..], &[
+      0x00, 0x00,             // error_code = 0
+      0x00, 0x00, 0x00, 0x01, // api_versions array length
+        0x00, 0x08,           // api_key = 8
+        0x00, 0x00,           // min_version = 0
+        0x00, 0x02,           // max_version = 2
+      0x00, 0x00, 0x00, 0x00  // throttle_time_ms = 0
+    ][..]);
+
+    let mut v: Vec<u8> = vec![];
+    ser_api_versions_response(&ApiVersionsResponse {
+      error_code: 35,
+      api_versions: &[],
+      throttle_time_ms: None
+    }, &mut v);
+    assert_eq!(&v[..], &[0x00, 0x23, 0x00, 0x00, 0x00, 0x00][..]);
+  }
+}
@@ -17,15 +17,25 @@ pub struct ConsumerMetadataResponse<'a> {
   pub coordinator_port: i32
 }
 
-pub fn ser_consumer_metadata_response<'a>(r: ConsumerMetadataResponse<'a>, output: &mut Vec<u8>) -> () {
+pub fn ser_consumer_metadata_response<'a>(r: ConsumerMetadataResponse<'a>, output: &mut Vec<u8>) {
   ser_i16(r.error_code, output);
   ser_i32(r.coordinator_id, output);
   ser_kafka_string(r.coordinator_host, output);
   ser_i32(r.coordinator_port, output);
 }
 
-#[cfg(test)]
+// FindCoordinator versions 1 and 2 add a throttle time and an error message.
+// The message is left null, clients log the error code
+pub fn ser_find_coordinator_response<'a>(r: ConsumerMetadataResponse<'a>, output: &mut Vec<u8>) {
+  ser_i32(0, output);
+  ser_i16(r.error_code, output);
+  ser_i16(-1, output);
+  ser_i32(r.coordinator_id, output);
+  ser_kafka_string(r.coordinator_host, output);
+  ser_i32(r.coordinator_port, output);
+}
 
+#[cfg(test)]
 mod tests {
   use super::*;
   use nom::*;
@@ -48,4 +58,24 @@ mod tests {
       0x00, 0x00, 0x23, 0x28  // coordinator_port = 9000
     ][..]);
   }
+
+  #[test]
+  fn ser_find_coordinator_response_test() {
+    let mut v: Vec<u8> = vec![];
+    ser_find_coordinator_response(
+      ConsumerMetadataResponse {
+        error_code: 15,
+        coordinator_id: -1,
+        coordinator_host: "",
+        coordinator_port: -1
+      }, &mut v);
+    assert_eq!(&v[..], &[
+      0x00, 0x00, 0x00, 0x00, // throttle_time_ms = 0
+      0x00, 0x0f,             // error_code = 15
+      0xff, 0xff,             // error_message = null
+      0xff, 0xff, 0xff, 0xff, // coordinator_id = -1
+      0x00, 0x00,             // coordinator_host = ""
+      0xff, 0xff, 0xff, 0xff  // coordinator_port = -1
+    ][..]);
+  }
 }
@@ -0,0 +1,59 @@
+// error codes sent in responses, as defined by the Kafka protocol
+// every code is listed, including the ones this broker does not send
+#[allow(dead_code)]
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ErrorCode {
+  Unknown                       = -1,
+  NoError                       = 0,
+  OffsetOutOfRange              = 1,
+  CorruptMessage                = 2,
+  UnknownTopicOrPartition       = 3,
+  InvalidFetchSize              = 4,
+  LeaderNotAvailable            = 5,
+  NotLeaderForPartition         = 6,
+  RequestTimedOut               = 7,
+  BrokerNotAvailable            = 8,
+  ReplicaNotAvailable           = 9,
+  MessageTooLarge               = 10,
+  StaleControllerEpoch          = 11,
+  OffsetMetadataTooLarge        = 12,
+  NetworkException              = 13,
+  GroupLoadInProgress           = 14,
+  GroupCoordinatorNotAvailable  = 15,
+  NotCoordinatorForGroup        = 16,
+  InvalidTopic                  = 17,
+  RecordListTooLarge            = 18,
+  NotEnoughReplicas             = 19,
+  NotEnoughReplicasAfterAppend  = 20,
+  InvalidRequiredAcks           = 21,
+  IllegalGeneration             = 22,
+  InconsistentGroupProtocol     = 23,
+  InvalidGroupId                = 24,
+  UnknownMemberId               = 25,
+  InvalidSessionTimeout         = 26,
+  RebalanceInProgress           = 27,
+  InvalidCommitOffsetSize       = 28,
+  TopicAuthorizationFailed      = 29,
+  GroupAuthorizationFailed      = 30,
+  ClusterAuthorizationFailed    = 31,
+  InvalidTimestamp              = 32,
+  UnsupportedSaslMechanism      = 33,
+  IllegalSaslState              = 34,
+  UnsupportedVersion            = 35,
+  TopicAlreadyExists            = 36,
+  InvalidPartitions             = 37,
+  InvalidReplicationFactor      = 38,
+  InvalidReplicaAssignment      = 39,
+  InvalidConfig                 = 40,
+  NotController                 = 41,
+  InvalidRequest                = 42,
+  UnsupportedForMessageFormat   = 43,
+  KafkaStorageError             = 56,
+}
+
+impl ErrorCode {
+  #[inline]
+  pub fn to_int(self) -> i16 {
+    self as i16
+  }
+}
@@ -8,40 +8,59 @@ use nom::{HexDisplay,Needed,IResult,FileProducer, be_i16, be_i32, be_i64};
 use nom::{Consumer,ConsumerState};
 use nom::IResult::*;
 
-use crc::crc32;
+use checksum::{message_crc,record_batch_crc};
 
 use responses::primitive::*;
+use storage::SharedBytes;
 
 /*
-FetchResponse => [TopicName [Partition ErrorCode HighwaterMarkOffset MessageSetSize MessageSet]]
+FetchResponse => [ThrottleTime] [TopicName [Partition ErrorCode HighwaterMarkOffset [LastStableOffset AbortedTransactions] MessageSetSize MessageSet]]
+  ThrottleTime => int32 (since v1)
   TopicName => string
   Partition => int32
   ErrorCode => int16
   HighwaterMarkOffset => int64
+  LastStableOffset => int64 (since v4)
+  AbortedTransactions => [ProducerId FirstOffset] (since v4)
   MessageSetSize => int32
   */
 
-pub type FetchResponse<'a> = Vec<(KafkaString<'a>, Vec<(i32, i16, i64, MessageSet<'a>)>)>;
+// message sets are stored in the log in their wire format, so they are
+// sent as the byte range read from the segment instead of being parsed
+// and serialized again
+pub type FetchResponse<'a> = Vec<(KafkaString<'a>, Vec<(i32, i16, i64, SharedBytes)>)>;
 
-pub fn ser_fetch_response(response: FetchResponse, output: &mut Vec<u8>) -> () {
+// the message sets are not copied to `output`, they are returned with the
+// position in `output` where they should be written
+pub fn ser_fetch_response(response: FetchResponse, api_version: i16, output: &mut Vec<u8>) -> Vec<(usize, SharedBytes)> {
+  if api_version >= 1 {
+    ser_i32(0, output);
+  }
+
+  let mut message_sets = Vec::new();
   ser_kafka_array(&response, |topic, oo| {
     let (name, ref ps) = *topic;
     ser_kafka_string(name, oo);
     ser_kafka_array(ps, |p, ooo| {
       let (partition_id, error_code, highwater_mark_offset, ref ms) = *p;
-      let mut ms_output: Vec<u8> = vec![];
-      ser_message_set(ms, &mut ms_output);
-
       ser_i32(partition_id, ooo);
       ser_i16(error_code, ooo);
       ser_i64(highwater_mark_offset, ooo);
-      ser_i32(ms_output.len() as i32, ooo);
-      ooo.extend(ms_output);
+      // without transactions, every message is stable
+      if api_version >= 4 {
+        ser_i64(highwater_mark_offset, ooo);
+        ser_i32(0, ooo);
+      }
+      ser_i32(ms.len() as i32, ooo);
+      if !ms.is_empty() {
+        message_sets.push((ooo.len(), ms.clone()));
+      }
     }, oo);
   }, output);
+  message_sets
 }
 
-pub fn ser_message_set(message_set: &MessageSet, output: &mut Vec<u8>) -> () {
+pub fn ser_message_set(message_set: &MessageSet, output: &mut Vec<u8>) {
   ser_kafka_array_without_size_prefix(message_set, |oms, oo| {
     let OMsMessage { offset, ref message } = *oms;
     let mut m_output: Vec<u8> = vec![];
@@ -53,49 +72,106 @@ pub fn ser_message_set(message_set: &MessageSet, output: &mut Vec<u8>) -> () {
   }, output);
 }
 
-pub fn ser_message(message: &Message, output: &mut Vec<u8>) -> () {
-  let Message { magic_byte, attributes, ref key, ref value } = *message;
+pub fn ser_message(message: &Message, output: &mut Vec<u8>) {
+  let Message { magic_byte, attributes, timestamp, key, value } = *message;
   let mut message_body: Vec<u8> = vec![];
 
   ser_i8(magic_byte, &mut message_body);
   ser_i8(attributes, &mut message_body);
-  ser_kafka_bytes(key, &mut message_body);
-  ser_kafka_bytes(value, &mut message_body);
+  if let Some(t) = timestamp {
+    ser_i64(t, &mut message_body);
+  }
+  ser_kafka_nullable_bytes(key, &mut message_body);
+  ser_kafka_nullable_bytes(value, &mut message_body);
 
-  ser_i32(crc32::checksum_ieee(&message_body[..]) as i32, output);
+  ser_i32(message_crc(&message_body[..]) as i32, output);
   output.extend(message_body);
 }
 
+pub fn ser_record_batch(batch: &RecordBatch, output: &mut Vec<u8>) {
+  let mut body: Vec<u8> = Vec::with_capacity(RECORD_BATCH_OVERHEAD - 9 + batch.records.len());
+  ser_i16(batch.attributes, &mut body);
+  ser_i32(batch.last_offset_delta, &mut body);
+  ser_i64(batch.first_timestamp, &mut body);
+  ser_i64(batch.max_timestamp, &mut body);
+  ser_i64(batch.producer_id, &mut body);
+  ser_i16(batch.producer_epoch, &mut body);
+  ser_i32(batch.base_sequence, &mut body);
+  ser_i32(batch.record_count, &mut body);
+  body.extend_from_slice(batch.records);
+
+  ser_i64(batch.base_offset, output);
+  ser_i32((body.len() + 9) as i32, output);
+  ser_i32(batch.partition_leader_epoch, output);
+  ser_i8(2, output);
+  ser_i32(record_batch_crc(&body[..]) as i32, output);
+  output.extend(body);
+}
+
+// serializes uncompressed records, to be used as `RecordBatch::records`
+pub fn ser_records(records: &[Record], output: &mut Vec<u8>) {
+  for record in records.iter() {
+    let mut body: Vec<u8> = vec![];
+    ser_i8(record.attributes, &mut body);
+    ser_varlong(record.timestamp_delta, &mut body);
+    ser_varint(record.offset_delta, &mut body);
+    ser_varint_bytes(record.key, &mut body);
+    ser_varint_bytes(record.value, &mut body);
+    ser_varint(record.headers.len() as i32, &mut body);
+    for &(key, value) in record.headers.iter() {
+      ser_varint_string(key, &mut body);
+      ser_varint_bytes(value, &mut body);
+    }
+
+    ser_varint(body.len() as i32, output);
+    output.extend(body);
+  }
+}
+
 
 
 #[cfg(test)]
 mod tests {
   use super::*;
-  use nom::*;
   use nom::IResult::*;
 
   use parser::message::*;
+  use checksum::check_entries;
+
+  // inserts the message sets returned by the serializer at their position
+  fn with_message_sets(mut output: Vec<u8>, message_sets: Vec<(usize, SharedBytes)>) -> Vec<u8> {
+    for (position, ms) in message_sets.into_iter().rev() {
+      output.splice(position..position, ms.iter().cloned());
+    }
+    output
+  }
 
   #[test]
   fn ser_fetch_response_test() {
+    let mut ms: Vec<u8> = vec![];
+    ser_message_set(&vec![OMsMessage {
+      offset: 0,
+      message: Message {
+        magic_byte: 0,
+        attributes: 0,
+        timestamp: None,
+        key: Some(&[][..]),
+        value: Some(&[][..])
+      }
+    }], &mut ms);
+
     let mut v: Vec<u8> = vec![];
-    ser_fetch_response(vec![(
+    let message_sets = ser_fetch_response(vec![(
       "",
       vec![(
         0,
         0,
         0,
-        vec![OMsMessage {
-              offset: 0,
-              message: Message {
-                magic_byte: 0,
-                attributes: 0,
-                key: &[][..],
-                value: &[][..]
-              }
-            }]
+        SharedBytes::from(ms)
       )]
-    )], &mut v);
+    )], 0, &mut v);
+    assert_eq!(message_sets.len(), 1);
+    let v = with_message_sets(v, message_sets);
 
     assert_eq!(&v[..], &[
       0x00, 0x00, 0x00, 0x01, // topics array length = 1
@@ -123,8 +199,9 @@ mod tests {
       message: Message {
         magic_byte: 0,
         attributes: 0,
-        key: &[][..],
-        value: &[][..]
+        timestamp: None,
+        key: Some(&[][..]),
+        value: Some(&[][..])
       }
     }], &mut v);
 
@@ -145,8 +222,9 @@ mod tests {
     ser_message(&Message {
       magic_byte: 0,
       attributes: 0,
-      key: &[][..],
-      value: &[][..]
+      timestamp: None,
+      key: Some(&[][..]),
+      value: Some(&[][..])
     }, &mut v);
 
     assert_eq!(&v[..], &[
@@ -157,4 +235,87 @@ mod tests {
       0x00, 0x00, 0x00, 0x00  // value = []
     ][..]);
   }
+
+  #[test]
+  fn ser_message_v1_test() {
+    let mut v: Vec<u8> = vec![];
+    ser_message(&Message {
+      magic_byte: 1,
+      attributes: MESSAGE_TIMESTAMP_TYPE_MASK,
+      timestamp: Some(1000),
+      key: Some(&[][..]),
+      value: Some(&b"a"[..])
+    }, &mut v);
+
+    assert_eq!(&v[..], &[
+      0x84, 0xc2, 0xac, 0x30,                         // crc
+      0x01,                                           // magic_byte = 1
+      0x08,                                           // attributes = LogAppendTime
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xe8, // timestamp = 1000
+      0x00, 0x00, 0x00, 0x00,                         // key = []
+      0x00, 0x00, 0x00, 0x01, 0x61                    // value = "a"
+    ][..]);
+  }
+
+  #[test]
+  fn ser_fetch_response_v4_test() {
+    let mut v: Vec<u8> = vec![];
+    assert_eq!(ser_fetch_response(vec![("", vec![(0, 0, 3, SharedBytes::from(vec![]))])], 4, &mut v), vec![]);
+
+    assert_eq!(&v[..], &[
+      0x00, 0x00, 0x00, 0x00, // throttle_time_ms = 0
+      0x00, 0x00, 0x00, 0x01, // topics array length = 1
+          0x00, 0x00,             // topic_name = ""
+          0x00, 0x00, 0x00, 0x01, // partitions array length = 1
+              0x00, 0x00, 0x00, 0x00,                         // partition_id = 0
+              0x00, 0x00,                                     // error_code = 0
+              0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, // highwater_mark_offset = 3
+              0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, // last_stable_offset = 3
+              0x00, 0x00, 0x00, 0x00,                         // aborted_transactions = []
+              0x00, 0x00, 0x00, 0x00                          // message_set_size = 0
+    ][..]);
+  }
+
+  #[test]
+  fn ser_record_batch_test() {
+    let records = vec![
+      Record { attributes: 0, timestamp_delta: 0, offset_delta: 0, key: None, value: Some(&b"a"[..]), headers: vec![] },
+      Record { attributes: 0, timestamp_delta: 5, offset_delta: 1, key: Some(&b"k"[..]), value: None, headers: vec![("h", Some(&b"v"[..]))] },
+    ];
+    let mut records_bytes: Vec<u8> = vec![];
+    ser_records(&records, &mut records_bytes);
+    assert_eq!(&records_bytes[..], &[
+      0x0e, 0x00, 0x00, 0x00, 0x01, 0x02, 0x61, 0x00,             // length 7, key null, value "a", no headers
+      0x16, 0x00, 0x0a, 0x02, 0x02, 0x6b, 0x01, 0x02, 0x02, 0x68, 0x02, 0x76 // length 11, key "k", value null, header h=v
+    ][..]);
+
+    let batch = RecordBatch {
+      base_offset: 10,
+      partition_leader_epoch: -1,
+      attributes: 0,
+      last_offset_delta: 1,
+      first_timestamp: 1000,
+      max_timestamp: 1005,
+      producer_id: -1,
+      producer_epoch: -1,
+      base_sequence: -1,
+      record_count: 2,
+      records: &records_bytes
+    };
+    let mut v: Vec<u8> = vec![];
+    ser_record_batch(&batch, &mut v);
+    assert_eq!(v.len(), 12 + RECORD_BATCH_OVERHEAD + records_bytes.len());
+    assert_eq!(v[MAGIC_POSITION], 2);
+
+    let (rest, parsed) = record_batch(&v).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(parsed, batch);
+    assert_eq!(batch_records(&parsed), Done(&[][..], records));
+
+    // the CRC covers everything after itself
+    assert!(check_entries(&v));
+    let last = v.len() - 1;
+    v[last] ^= 1;
+    assert!(!check_entries(&v));
+  }
 }
@@ -0,0 +1,38 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+
+use parser::primitive::*;
+
+use nom::{HexDisplay,Needed,IResult,FileProducer};
+use nom::{Consumer,ConsumerState};
+use nom::IResult::*;
+
+use responses::primitive::*;
+
+/*
+HeartbeatResponse => ErrorCode
+  ErrorCode => int16
+*/
+
+pub type HeartbeatResponse = i16;
+
+pub fn ser_heartbeat_response(error_code: HeartbeatResponse, output: &mut Vec<u8>) {
+  ser_i16(error_code, output);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nom::*;
+  use nom::IResult::*;
+
+  #[test]
+  fn ser_heartbeat_response_test() {
+    let mut v: Vec<u8> = vec![];
+    ser_heartbeat_response(27, &mut v);
+
+    assert_eq!(&v[..], &[
+      0x00, 0x1b // error_code = 27
+    ][..]);
+  }
+}
@@ -0,0 +1,79 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+
+use parser::primitive::*;
+
+use nom::{HexDisplay,Needed,IResult,FileProducer};
+use nom::{Consumer,ConsumerState};
+use nom::IResult::*;
+
+use responses::primitive::*;
+
+/*
+JoinGroupResponse => ErrorCode GenerationId GroupProtocol LeaderId MemberId [Members]
+  ErrorCode => int16
+  GenerationId => int32
+  GroupProtocol => string
+  LeaderId => string
+  MemberId => string
+  Members => MemberId MemberMetadata
+    MemberId => string
+    MemberMetadata => bytes
+*/
+
+// owned, since the group state it is built from stays locked in the
+// coordinator while the response is serialized
+#[derive(Debug,PartialEq)]
+pub struct JoinGroupResponse {
+  pub error_code: i16,
+  pub generation_id: i32,
+  pub group_protocol: String,
+  pub leader_id: String,
+  pub member_id: String,
+  // only sent to the leader
+  pub members: Vec<(String, Vec<u8>)>
+}
+
+pub fn ser_join_group_response(r: &JoinGroupResponse, output: &mut Vec<u8>) {
+  ser_i16(r.error_code, output);
+  ser_i32(r.generation_id, output);
+  ser_kafka_string(&r.group_protocol, output);
+  ser_kafka_string(&r.leader_id, output);
+  ser_kafka_string(&r.member_id, output);
+  ser_kafka_array(&r.members, |m, o| {
+    let (ref member_id, ref metadata) = *m;
+    ser_kafka_string(member_id, o);
+    ser_kafka_bytes(metadata, o);
+  }, output);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nom::*;
+  use nom::IResult::*;
+
+  #[test]
+  fn ser_join_group_response_test() {
+    let mut v: Vec<u8> = vec![];
+    ser_join_group_response(&JoinGroupResponse {
+      error_code: 0,
+      generation_id: 1,
+      group_protocol: "r".to_string(),
+      leader_id: "m".to_string(),
+      member_id: "m".to_string(),
+      members: vec![("m".to_string(), vec![1])]
+    }, &mut v);
+
+    assert_eq!(&v[..], &[
+      0x00, 0x00,             // error_code = 0
+      0x00, 0x00, 0x00, 0x01, // generation_id = 1
+      0x00, 0x01, 0x72,       // group_protocol = "r"
+      0x00, 0x01, 0x6d,       // leader_id = "m"
+      0x00, 0x01, 0x6d,       // member_id = "m"
+      0x00, 0x00, 0x00, 0x01, // members = 1
+        0x00, 0x01, 0x6d,            // member_id = "m"
+        0x00, 0x00, 0x00, 0x01, 0x01 // member_metadata = [1]
+    ][..]);
+  }
+}
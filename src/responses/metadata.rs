@@ -28,7 +28,7 @@ MetadataResponse => [Broker][TopicMetadata]
 #[derive(Debug,PartialEq)]
 pub struct MetadataResponse<'a> {
   pub brokers: Vec<Broker<'a>>,
-  pub topics: Vec<TopicMetadata<'a>>
+  pub topics: Vec<TopicMetadata>
 }
 
 #[derive(Debug,PartialEq)]
@@ -38,10 +38,12 @@ pub struct Broker<'a> {
   pub port: i32
 }
 
+// the name is owned, since listing every topic copies the names from the
+// topic registry
 #[derive(Debug,PartialEq)]
-pub struct TopicMetadata<'a> {
+pub struct TopicMetadata {
   pub topic_error_code: i16,
-  pub topic_name: KafkaString<'a>,
+  pub topic_name: String,
   pub partitions: Vec<PartitionMetadata>
 }
 
@@ -55,24 +57,24 @@ pub struct PartitionMetadata {
 }
 
 
-pub fn ser_metadata_response<'a>(r: &MetadataResponse<'a>, o: &mut Vec<u8>) -> () {
+pub fn ser_metadata_response<'a>(r: &MetadataResponse<'a>, o: &mut Vec<u8>) {
   ser_kafka_array(&r.brokers, ser_broker, o);
   ser_kafka_array(&r.topics, ser_topic_metadata, o);
 }
 
-pub fn ser_broker<'a>(b: &Broker<'a>, o: &mut Vec<u8>) -> () {
+pub fn ser_broker<'a>(b: &Broker<'a>, o: &mut Vec<u8>) {
   ser_i32(b.node_id, o);
   ser_kafka_string(b.host, o);
   ser_i32(b.port, o);
 }
 
-pub fn ser_topic_metadata<'a>(tm: &TopicMetadata<'a>, o: &mut Vec<u8>) -> () {
+pub fn ser_topic_metadata(tm: &TopicMetadata, o: &mut Vec<u8>) {
   ser_i16(tm.topic_error_code, o);
-  ser_kafka_string(tm.topic_name, o);
+  ser_kafka_string(&tm.topic_name, o);
   ser_kafka_array(&tm.partitions, ser_partition_metadata, o);
 }
 
-pub fn ser_partition_metadata(pm: &PartitionMetadata, o: &mut Vec<u8>) -> () {
+pub fn ser_partition_metadata(pm: &PartitionMetadata, o: &mut Vec<u8>) {
   ser_i16(pm.partition_error_code, o);
   ser_i32(pm.partition_id, o);
   ser_i32(pm.leader, o);
@@ -97,7 +99,7 @@ mod tests {
       }],
       topics: vec![TopicMetadata {
         topic_error_code: 0,
-        topic_name: "",
+        topic_name: "".to_string(),
         partitions: vec![PartitionMetadata {
           partition_error_code: 0,
           partition_id: 0,
@@ -146,7 +148,7 @@ mod tests {
     let mut v: Vec<u8> = vec![];
     ser_topic_metadata(&TopicMetadata {
       topic_error_code: 0,
-      topic_name: "",
+      topic_name: "".to_string(),
       partitions: vec![]
     }, &mut v);
     assert_eq!(&v[..], &[
@@ -1,5 +1,6 @@
 pub mod response;
 pub mod primitive;
+pub mod error_code;
 pub mod consumer_metadata;
 pub mod produce;
 pub mod metadata;
@@ -7,3 +8,8 @@ pub mod fetch;
 pub mod offset;
 pub mod offset_commit;
 pub mod offset_fetch;
+pub mod join_group;
+pub mod heartbeat;
+pub mod leave_group;
+pub mod sync_group;
+pub mod api_versions;
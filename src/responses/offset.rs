@@ -11,23 +11,32 @@ use responses::primitive::*;
 
 /*
 OffsetResponse => [TopicName [PartitionOffsets]]
-  PartitionOffsets => Partition ErrorCode [Offset]
+  PartitionOffsets => Partition ErrorCode [Offset] (v0)
+  PartitionOffsets => Partition ErrorCode Timestamp Offset (v1)
   Partition => int32
   ErrorCode => int16
+  Timestamp => int64
   Offset => int64
   */
 
-pub type OffsetResponse<'a> = Vec<(KafkaString<'a>, Vec<(i32, i16, Vec<i64>)>)>;
+// (partition, error code, timestamp, offsets). Version 1 only sends the
+// first offset
+pub type OffsetResponse<'a> = Vec<(KafkaString<'a>, Vec<(i32, i16, i64, Vec<i64>)>)>;
 
-pub fn ser_offset_response<'a>(r: OffsetResponse<'a>, output: &mut Vec<u8>) -> () {
+pub fn ser_offset_response<'a>(r: OffsetResponse<'a>, api_version: i16, output: &mut Vec<u8>) {
   ser_kafka_array(&r, |topic, oo| {
-    let (ref name, ref ps) = *topic;
+    let (name, ref ps) = *topic;
     ser_kafka_string(name, oo);
     ser_kafka_array(ps, |p, ooo| {
-      let (partition_id, error_code, ref offsets) = *p;
+      let (partition_id, error_code, timestamp, ref offsets) = *p;
       ser_i32(partition_id, ooo);
       ser_i16(error_code, ooo);
-      ser_kafka_array(offsets, ser_i64_ref, ooo);
+      if api_version == 0 {
+        ser_kafka_array(offsets, ser_i64_ref, ooo);
+      } else {
+        ser_i64(timestamp, ooo);
+        ser_i64(offsets.first().cloned().unwrap_or(-1), ooo);
+      }
     }, oo);
   }, output);
 }
@@ -41,7 +50,7 @@ mod tests {
   #[test]
   fn ser_offset_response_tests() {
     let mut v: Vec<u8> = vec![];
-    ser_offset_response(vec![("", vec![(0, 0, vec![0])])], &mut v);
+    ser_offset_response(vec![("", vec![(0, 0, -1, vec![0])])], 0, &mut v);
     assert_eq!(&v[..], &[
       0x00, 0x00, 0x00, 0x01, // topics array length = 1
           0x00, 0x00,             // topic_name = ""
@@ -52,4 +61,19 @@ mod tests {
                   0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00 // partition_id = 0
     ][..]);
   }
+
+  #[test]
+  fn ser_offset_response_v1_tests() {
+    let mut v: Vec<u8> = vec![];
+    ser_offset_response(vec![("", vec![(0, 0, 1000, vec![5])])], 1, &mut v);
+    assert_eq!(&v[..], &[
+      0x00, 0x00, 0x00, 0x01, // topics array length = 1
+          0x00, 0x00,             // topic_name = ""
+          0x00, 0x00, 0x00, 0x01, // partitions array length = 1
+              0x00, 0x00, 0x00, 0x00,                         // partition_id = 0
+              0x00, 0x00,                                     // error_code = 0
+              0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xe8, // timestamp = 1000
+              0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05  // offset = 5
+    ][..]);
+  }
 }
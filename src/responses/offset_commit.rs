@@ -18,9 +18,9 @@ OffsetCommitResponse => [TopicName [Partition ErrorCode]]
 
 pub type OffsetCommitResponse<'a> = Vec<(KafkaString<'a>, Vec<(i32, i16)>)>;
 
-pub fn ser_offset_commit_response<'a>(r: OffsetCommitResponse<'a>, output: &mut Vec<u8>) -> () {
+pub fn ser_offset_commit_response<'a>(r: OffsetCommitResponse<'a>, output: &mut Vec<u8>) {
   ser_kafka_array(&r, |topic, oo| {
-    let (ref name, ref ps) = *topic;
+    let (name, ref ps) = *topic;
     ser_kafka_string(name, oo);
     ser_kafka_array(ps, |p, ooo| {
       let (partition_id, error_code) = *p;
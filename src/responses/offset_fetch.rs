@@ -18,11 +18,12 @@ OffsetFetchResponse => [TopicName [Partition Offset Metadata ErrorCode]]
   ErrorCode => int16
   */
 
-pub type OffsetFetchResponse<'a> = Vec<(KafkaString<'a>, Vec<(i32, i64, KafkaString<'a>, i16)>)>;
+// the metadata is copied from the offset store
+pub type OffsetFetchResponse<'a> = Vec<(KafkaString<'a>, Vec<(i32, i64, String, i16)>)>;
 
-pub fn ser_offset_fetch_response<'a>(r: OffsetFetchResponse<'a>, output: &mut Vec<u8>) -> () {
+pub fn ser_offset_fetch_response<'a>(r: OffsetFetchResponse<'a>, output: &mut Vec<u8>) {
   ser_kafka_array(&r, |topic, oo| {
-    let (ref name, ref ps) = *topic;
+    let (name, ref ps) = *topic;
     ser_kafka_string(name, oo);
     ser_kafka_array(ps, |p, ooo| {
       let (partition_id, offset, ref metadata, error_code) = *p;
@@ -43,7 +44,7 @@ mod tests {
   #[test]
   fn ser_offset_fetch_response_tests() {
     let mut v: Vec<u8> = vec![];
-    ser_offset_fetch_response(vec![("", vec![(0, 0, "", 0)])], &mut v);
+    ser_offset_fetch_response(vec![("", vec![(0, 0, "".to_string(), 0)])], &mut v);
     assert_eq!(&v[..], &[
       0x00, 0x00, 0x00, 0x01, // topics array length = 1
           0x00, 0x00,             // topic_name = ""
@@ -10,17 +10,17 @@ use std::str;
 use parser::request::*;
 use parser::primitive::*;
 
-pub fn ser_i8<'a>(v: i8, output: &mut Vec<u8>) -> () {
+pub fn ser_i8(v: i8, output: &mut Vec<u8>) {
   output.push(v as u8);
 }
 
-pub fn ser_i16<'a>(v: i16, output: &mut Vec<u8>) -> () {
+pub fn ser_i16(v: i16, output: &mut Vec<u8>) {
   let bits: u16 = v as u16;
   output.push((bits >> 8 ) as u8);
   output.push( bits        as u8);
 }
 
-pub fn ser_i32<'a>(v: i32, output: &mut Vec<u8>) -> () {
+pub fn ser_i32(v: i32, output: &mut Vec<u8>) {
   let bits: u32 = v as u32;
   output.push((bits >> 24) as u8);
   output.push((bits >> 16) as u8);
@@ -28,11 +28,11 @@ pub fn ser_i32<'a>(v: i32, output: &mut Vec<u8>) -> () {
   output.push( bits        as u8);
 }
 
-pub fn ser_i32_ref<'a>(v: &i32, output: &mut Vec<u8>) -> () {
+pub fn ser_i32_ref(v: &i32, output: &mut Vec<u8>) {
   ser_i32(*v, output);
 }
 
-pub fn ser_i64<'a>(v: i64, output: &mut Vec<u8>) -> () {
+pub fn ser_i64(v: i64, output: &mut Vec<u8>) {
   let bits: u64 = v as u64;
   output.push((bits >> 56) as u8);
   output.push((bits >> 48) as u8);
@@ -44,17 +44,17 @@ pub fn ser_i64<'a>(v: i64, output: &mut Vec<u8>) -> () {
   output.push( bits        as u8);
 }
 
-pub fn ser_i64_ref<'a>(v: &i64, output: &mut Vec<u8>) -> () {
+pub fn ser_i64_ref(v: &i64, output: &mut Vec<u8>) {
   ser_i64(*v, output);
 }
 
-pub fn ser_kafka_bytes(bs: KafkaBytes, output: &mut Vec<u8>) -> () {
+pub fn ser_kafka_bytes(bs: KafkaBytes, output: &mut Vec<u8>) {
   ser_i32(bs.len() as i32, output);
 
   output.extend(bs.iter().cloned());
 }
 
-pub fn ser_kafka_string(string: KafkaString, output: &mut Vec<u8>) -> () {
+pub fn ser_kafka_string(string: KafkaString, output: &mut Vec<u8>) {
   ser_i16(string.len() as i16, output);
 
   for b in string.bytes() {
@@ -62,22 +62,118 @@ pub fn ser_kafka_string(string: KafkaString, output: &mut Vec<u8>) -> () {
   }
 }
 
-pub fn ser_kafka_array<F,O>(elems: &Vec<O>, closure: F, output: &mut Vec<u8>) -> ()
- where F : Fn(&O, &mut Vec<u8>) -> () {
+pub fn ser_kafka_nullable_bytes(bs: KafkaNullableBytes, output: &mut Vec<u8>) {
+  match bs {
+    Some(bs) => ser_kafka_bytes(bs, output),
+    None     => ser_i32(-1, output)
+  }
+}
+
+pub fn ser_kafka_nullable_string(string: KafkaNullableString, output: &mut Vec<u8>) {
+  match string {
+    Some(s) => ser_kafka_string(s, output),
+    None    => ser_i16(-1, output)
+  }
+}
+
+pub fn ser_kafka_array<F,O>(elems: &[O], closure: F, output: &mut Vec<u8>)
+ where F : FnMut(&O, &mut Vec<u8>) {
   ser_i32(elems.len() as i32, output);
   ser_kafka_array_without_size_prefix(elems, closure, output);
 }
 
-pub fn ser_kafka_array_without_size_prefix<F,O>(elems: &Vec<O>, closure: F, output: &mut Vec<u8>) -> ()
- where F : Fn(&O, &mut Vec<u8>) -> () {
+pub fn ser_kafka_array_without_size_prefix<F,O>(elems: &[O], mut closure: F, output: &mut Vec<u8>)
+ where F : FnMut(&O, &mut Vec<u8>) {
   for e in elems.iter() {
     closure(e, output);
   }
 }
 
+// zigzag encoded variable length integers, used in record batches
+fn ser_unsigned_varint(mut v: u64, output: &mut Vec<u8>) {
+  while v >= 0x80 {
+    output.push((v as u8) | 0x80);
+    v >>= 7;
+  }
+  output.push(v as u8);
+}
 
-#[cfg(test)]
+pub fn ser_varint(v: i32, output: &mut Vec<u8>) {
+  ser_unsigned_varint(((v << 1) ^ (v >> 31)) as u32 as u64, output);
+}
+
+pub fn ser_varlong(v: i64, output: &mut Vec<u8>) {
+  ser_unsigned_varint(((v << 1) ^ (v >> 63)) as u64, output);
+}
+
+pub fn ser_varint_bytes(bs: Option<&[u8]>, output: &mut Vec<u8>) {
+  match bs {
+    Some(bs) => {
+      ser_varint(bs.len() as i32, output);
+      output.extend_from_slice(bs);
+    },
+    None => ser_varint(-1, output)
+  }
+}
+
+pub fn ser_varint_string(s: &str, output: &mut Vec<u8>) {
+  ser_varint_bytes(Some(s.as_bytes()), output);
+}
+
+// compact fields of flexible versions hold their length plus one, 0 for null
+pub fn ser_unsigned_varint32(v: u32, output: &mut Vec<u8>) {
+  ser_unsigned_varint(v as u64, output);
+}
+
+pub fn ser_compact_nullable_bytes(bs: KafkaNullableBytes, output: &mut Vec<u8>) {
+  match bs {
+    Some(bs) => {
+      ser_unsigned_varint32(bs.len() as u32 + 1, output);
+      output.extend_from_slice(bs);
+    },
+    None => ser_unsigned_varint32(0, output)
+  }
+}
+
+pub fn ser_compact_bytes(bs: KafkaBytes, output: &mut Vec<u8>) {
+  ser_compact_nullable_bytes(Some(bs), output);
+}
+
+pub fn ser_compact_nullable_string(string: KafkaNullableString, output: &mut Vec<u8>) {
+  ser_compact_nullable_bytes(string.map(|s| s.as_bytes()), output);
+}
+
+pub fn ser_compact_string(string: KafkaString, output: &mut Vec<u8>) {
+  ser_compact_bytes(string.as_bytes(), output);
+}
 
+pub fn ser_compact_nullable_array<F,O>(elems: Option<&Vec<O>>, closure: F, output: &mut Vec<u8>)
+ where F : Fn(&O, &mut Vec<u8>) {
+  match elems {
+    Some(elems) => {
+      ser_unsigned_varint32(elems.len() as u32 + 1, output);
+      ser_kafka_array_without_size_prefix(elems, closure, output);
+    },
+    None => ser_unsigned_varint32(0, output)
+  }
+}
+
+pub fn ser_compact_array<F,O>(elems: &Vec<O>, closure: F, output: &mut Vec<u8>)
+ where F : Fn(&O, &mut Vec<u8>) {
+  ser_compact_nullable_array(Some(elems), closure, output);
+}
+
+// fields must be sorted by tag
+pub fn ser_tagged_fields(fields: &TaggedFields, output: &mut Vec<u8>) {
+  ser_unsigned_varint32(fields.len() as u32, output);
+  for &(tag, data) in fields.iter() {
+    ser_unsigned_varint32(tag, output);
+    ser_unsigned_varint32(data.len() as u32, output);
+    output.extend_from_slice(data);
+  }
+}
+
+#[cfg(test)]
 mod tests {
   use super::*;
   use nom::*;
@@ -140,6 +236,16 @@ mod tests {
     assert_eq!(&v[..], &[0x00, 0x04, 65, 66, 67, 68][..]);
   }
 
+  #[test]
+  fn ser_kafka_nullable_test() {
+    let mut v: Vec<u8> = vec![];
+    ser_kafka_nullable_bytes(None, &mut v);
+    ser_kafka_nullable_bytes(Some(&b"a"[..]), &mut v);
+    ser_kafka_nullable_string(None, &mut v);
+    ser_kafka_nullable_string(Some("b"), &mut v);
+    assert_eq!(&v[..], &[0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01, 0x61, 0xff, 0xff, 0x00, 0x01, 0x62][..]);
+  }
+
   #[test]
   fn ser_kafka_array_test() {
     let mut v: Vec<u8> = vec![];
@@ -147,6 +253,47 @@ mod tests {
     ser_kafka_array(&i, |ii, o| { ser_i8(*ii,o); }, &mut v);
     assert_eq!(&v[..], &[0x00, 0x00, 0x00, 0x01, 0x7f][..]);
   }
-}
 
+  #[test]
+  fn ser_varint_test() {
+    let mut v: Vec<u8> = vec![];
+    ser_varint(150, &mut v);
+    assert_eq!(&v[..], &[0xac, 0x02][..]);
+    v.clear();
+    ser_varint(-1, &mut v);
+    assert_eq!(&v[..], &[0x01][..]);
+    v.clear();
+    ser_varint(-2147483648, &mut v);
+    assert_eq!(&v[..], &[0xff, 0xff, 0xff, 0xff, 0x0f][..]);
+    v.clear();
+    ser_varlong(-9223372036854775808, &mut v);
+    assert_eq!(&v[..], &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01][..]);
+    v.clear();
+    ser_varint_bytes(None, &mut v);
+    ser_varint_string("AB", &mut v);
+    assert_eq!(&v[..], &[0x01, 0x04, 65, 66][..]);
+  }
 
+  #[test]
+  fn ser_compact_test() {
+    let mut v: Vec<u8> = vec![];
+    ser_unsigned_varint32(4294967295, &mut v);
+    assert_eq!(&v[..], &[0xff, 0xff, 0xff, 0xff, 0x0f][..]);
+    v.clear();
+    ser_compact_nullable_bytes(None, &mut v);
+    ser_compact_bytes(&[65][..], &mut v);
+    ser_compact_nullable_string(None, &mut v);
+    ser_compact_string("AB", &mut v);
+    assert_eq!(&v[..], &[0x00, 0x02, 65, 0x00, 0x03, 65, 66][..]);
+    v.clear();
+    let i: Vec<i8> = vec![1, 2];
+    ser_compact_array(&i, |ii, o| { ser_i8(*ii,o); }, &mut v);
+    ser_compact_nullable_array(None, |ii: &i8, o| { ser_i8(*ii,o); }, &mut v);
+    assert_eq!(&v[..], &[0x03, 0x01, 0x02, 0x00][..]);
+    v.clear();
+    ser_tagged_fields(&vec![], &mut v);
+    ser_tagged_fields(&vec![(0, &b"a"[..]), (200, &[][..])], &mut v);
+    assert_eq!(&v[..], &[0x00, 0x02, 0x00, 0x01, 0x61, 0xc8, 0x01, 0x00][..]);
+    assert_eq!(tagged_fields(&v[1..]), Done(&[][..], vec![(0, &b"a"[..]), (200, &[][..])]));
+  }
+}
@@ -10,30 +10,39 @@ use nom::IResult::*;
 
 use responses::primitive::*;
 
-//ProduceResponse => [TopicName [Partition ErrorCode Offset]]
+//ProduceResponse => [TopicName [Partition ErrorCode Offset [LogAppendTime]]] [ThrottleTime]
 //  TopicName => string
 //  Partition => int32
 //  ErrorCode => int16
 //  Offset => int64
+//  LogAppendTime => int64 (since v2)
+//  ThrottleTime => int32 (since v1)
 
-pub type ProduceResponse<'a> = Vec<(KafkaString<'a>, Vec<(i32, i16, i64)>)>;
+// (partition, error code, base offset, log append time)
+pub type ProduceResponse<'a> = Vec<(KafkaString<'a>, Vec<(i32, i16, i64, i64)>)>;
 
-pub fn ser_produce_response<'a>(r: &ProduceResponse<'a>, o: &mut Vec<u8>) -> () {
+pub fn ser_produce_response<'a>(r: &ProduceResponse<'a>, api_version: i16, o: &mut Vec<u8>) {
   ser_kafka_array(r, |t, oo| {
     let (topic_name, ref partitions) = *t;
     ser_kafka_string(topic_name, oo);
 
     ser_kafka_array(partitions, |p, ooo| {
-        let (pid, error_code, offset) = *p;
+        let (pid, error_code, offset, log_append_time) = *p;
         ser_i32(pid, ooo);
         ser_i16(error_code, ooo);
         ser_i64(offset, ooo);
+        if api_version >= 2 {
+          ser_i64(log_append_time, ooo);
+        }
     }, oo);
   }, o);
+
+  if api_version >= 1 {
+    ser_i32(0, o);
+  }
 }
 
 #[cfg(test)]
-
 mod tests {
   use super::*;
   use nom::*;
@@ -49,9 +58,10 @@ mod tests {
       vec![(
         127,
         0,
-        1337
+        1337,
+        -1
       )]
-    )], &mut v);
+    )], 0, &mut v);
 
     assert_eq!(&v[..], &[
       0x00, 0x00, 0x00, 0x01, // array length = 1
@@ -62,4 +72,21 @@ mod tests {
           0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x39 // offset = 1337
     ][..]);
   }
+
+  #[test]
+  fn ser_produce_response_v2_test() {
+    let mut v: Vec<u8> = vec![];
+    ser_produce_response(&vec![("", vec![(1, 0, 2, -1)])], 2, &mut v);
+
+    assert_eq!(&v[..], &[
+      0x00, 0x00, 0x00, 0x01, // array length = 1
+        0x00, 0x00,             // topic_name = ""
+        0x00, 0x00, 0x00, 0x01, // array length = 1
+          0x00, 0x00, 0x00, 0x01,                         // partition_id = 1
+          0x00, 0x00,                                     // error_code = 0
+          0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // offset = 2
+          0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // log_append_time = -1
+      0x00, 0x00, 0x00, 0x00  // throttle_time_ms = 0
+    ][..]);
+  }
 }
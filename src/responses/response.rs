@@ -17,6 +17,12 @@ use responses::fetch::*;
 use responses::offset::*;
 use responses::offset_commit::*;
 use responses::offset_fetch::*;
+use responses::join_group::*;
+use responses::heartbeat::*;
+use responses::leave_group::*;
+use responses::sync_group::*;
+use responses::api_versions::*;
+use storage::SharedBytes;
 
 
 #[derive(Debug,PartialEq)]
@@ -25,37 +31,60 @@ pub struct ResponseMessage<'a> {
   pub response_payload: ResponsePayload<'a>
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug,PartialEq)]
 pub enum ResponsePayload<'a> {
   ConsumerMetadataResponse(ConsumerMetadataResponse<'a>),
+  // same content, with the layout of FindCoordinator versions 1 and 2
+  FindCoordinatorResponse(ConsumerMetadataResponse<'a>),
   MetadataResponse(MetadataResponse<'a>),
-  ProduceResponse(ProduceResponse<'a>),
-  FetchResponse(FetchResponse<'a>),
-  OffsetResponse(OffsetResponse<'a>),
+  // with the version of the request
+  ProduceResponse(i16, ProduceResponse<'a>),
+  FetchResponse(i16, FetchResponse<'a>),
+  OffsetResponse(i16, OffsetResponse<'a>),
   OffsetCommitResponse(OffsetCommitResponse<'a>),
-  OffsetFetchResponse(OffsetFetchResponse<'a>)
+  OffsetFetchResponse(OffsetFetchResponse<'a>),
+  JoinGroupResponse(JoinGroupResponse),
+  HeartbeatResponse(HeartbeatResponse),
+  LeaveGroupResponse(LeaveGroupResponse),
+  SyncGroupResponse(SyncGroupResponse),
+  ApiVersionsResponse(ApiVersionsResponse<'a>)
 }
 
-pub fn ser_response_message(response: ResponseMessage, output: &mut Vec<u8>) -> () {
-  let mut r_output: Vec<u8> = vec![];
-  ser_i32(response.correlation_id, &mut r_output);
+// the message sets of fetch responses are not copied to `output`, they are
+// returned with the position where they should be written
+pub fn ser_response_message(response: ResponseMessage, output: &mut Vec<u8>) -> Vec<(usize, SharedBytes)> {
+  // the size is written once the payload is serialized, so that large
+  // payloads are not copied from an intermediate buffer
+  let start = output.len();
+  ser_i32(0, output);
+  ser_i32(response.correlation_id, output);
 
+  let mut message_sets = Vec::new();
   match response.response_payload {
-    ResponsePayload::ConsumerMetadataResponse(p) => ser_consumer_metadata_response(p, &mut r_output),
-    ResponsePayload::MetadataResponse(p) => ser_metadata_response(&p, &mut r_output),
-    ResponsePayload::ProduceResponse(p) => ser_produce_response(&p, &mut r_output),
-    ResponsePayload::FetchResponse(p) => ser_fetch_response(p, &mut r_output),
-    ResponsePayload::OffsetResponse(p) => ser_offset_response(p, &mut r_output),
-    ResponsePayload::OffsetCommitResponse(p) => ser_offset_commit_response(p, &mut r_output),
-    ResponsePayload::OffsetFetchResponse(p) => ser_offset_fetch_response(p, &mut r_output)
+    ResponsePayload::ConsumerMetadataResponse(p) => ser_consumer_metadata_response(p, output),
+    ResponsePayload::FindCoordinatorResponse(p) => ser_find_coordinator_response(p, output),
+    ResponsePayload::MetadataResponse(p) => ser_metadata_response(&p, output),
+    ResponsePayload::ProduceResponse(v, p) => ser_produce_response(&p, v, output),
+    ResponsePayload::FetchResponse(v, p) => message_sets = ser_fetch_response(p, v, output),
+    ResponsePayload::OffsetResponse(v, p) => ser_offset_response(p, v, output),
+    ResponsePayload::OffsetCommitResponse(p) => ser_offset_commit_response(p, output),
+    ResponsePayload::OffsetFetchResponse(p) => ser_offset_fetch_response(p, output),
+    ResponsePayload::JoinGroupResponse(p) => ser_join_group_response(&p, output),
+    ResponsePayload::HeartbeatResponse(p) => ser_heartbeat_response(p, output),
+    ResponsePayload::LeaveGroupResponse(p) => ser_leave_group_response(p, output),
+    ResponsePayload::SyncGroupResponse(p) => ser_sync_group_response(&p, output),
+    ResponsePayload::ApiVersionsResponse(p) => ser_api_versions_response(&p, output)
   }
 
-  ser_i32(r_output.len() as i32, output);
-  output.extend(r_output);
+  let message_sets_size: usize = message_sets.iter().map(|(_, ms)| ms.len()).sum();
+  let mut size: Vec<u8> = Vec::with_capacity(4);
+  ser_i32((output.len() - start - 4 + message_sets_size) as i32, &mut size);
+  output[start..start+4].copy_from_slice(&size);
+  message_sets
 }
 
 #[cfg(test)]
-
 mod tests {
   use super::*;
   use nom::*;
@@ -0,0 +1,49 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+
+use parser::primitive::*;
+
+use nom::{HexDisplay,Needed,IResult,FileProducer};
+use nom::{Consumer,ConsumerState};
+use nom::IResult::*;
+
+use responses::primitive::*;
+
+/*
+SyncGroupResponse => ErrorCode MemberAssignment
+  ErrorCode => int16
+  MemberAssignment => bytes
+*/
+
+// owned, like JoinGroupResponse
+#[derive(Debug,PartialEq)]
+pub struct SyncGroupResponse {
+  pub error_code: i16,
+  pub member_assignment: Vec<u8>
+}
+
+pub fn ser_sync_group_response(r: &SyncGroupResponse, output: &mut Vec<u8>) {
+  ser_i16(r.error_code, output);
+  ser_kafka_bytes(&r.member_assignment, output);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nom::*;
+  use nom::IResult::*;
+
+  #[test]
+  fn ser_sync_group_response_test() {
+    let mut v: Vec<u8> = vec![];
+    ser_sync_group_response(&SyncGroupResponse {
+      error_code: 0,
+      member_assignment: vec![1, 2]
+    }, &mut v);
+
+    assert_eq!(&v[..], &[
+      0x00, 0x00,                        // error_code = 0
+      0x00, 0x00, 0x00, 0x02, 0x01, 0x02 // member_assignment = [1, 2]
+    ][..]);
+  }
+}
@@ -0,0 +1,345 @@
+use std::io;
+use std::path::Path;
+
+use nom::{be_i32,be_i64};
+use nom::IResult::*;
+
+use responses::primitive::{ser_i32,ser_i64};
+use storage::Storage;
+
+// an offset index entry is added every time that many bytes were written to the log
+pub const INDEX_INTERVAL_BYTES: usize = 4096;
+
+// RelativeOffset => int32, Position => int32
+pub const OFFSET_INDEX_ENTRY_SIZE: usize = 8;
+// Timestamp => int64, RelativeOffset => int32
+pub const TIME_INDEX_ENTRY_SIZE: usize = 12;
+
+pub fn index_file_name(base_offset: i64) -> String {
+  format!("{:020}.index", base_offset)
+}
+
+pub fn time_index_file_name(base_offset: i64) -> String {
+  format!("{:020}.timeindex", base_offset)
+}
+
+fn read_i32(bytes: &[u8]) -> Option<i32> {
+  match be_i32(bytes) {
+    Done(_, v) => Some(v),
+    _          => None,
+  }
+}
+
+fn read_i64(bytes: &[u8]) -> Option<i64> {
+  match be_i64(bytes) {
+    Done(_, v) => Some(v),
+    _          => None,
+  }
+}
+
+// sparse mapping from offsets to positions in the segment's log file.
+// The file is preallocated with zeros: since the first entry is only added
+// after INDEX_INTERVAL_BYTES, a zeroed entry marks the end of the index
+pub struct OffsetIndex {
+  base_offset: i64,
+  entries:     usize,
+  storage:     Storage,
+}
+
+impl OffsetIndex {
+
+  // opens the index file and loads the valid entries. Returns `false` as
+  // second element if the file was missing or inconsistent and needs a rebuild
+  pub fn open(dir: &Path, base_offset: i64) -> io::Result<(OffsetIndex, bool)> {
+    let path  = dir.join(index_file_name(base_offset));
+    let existed = path.exists();
+    let mut index = OffsetIndex {
+      base_offset,
+      entries: 0,
+      storage: Storage::create(path)?,
+    };
+
+    let mut valid = existed;
+    let max_entries = index.storage.size() / OFFSET_INDEX_ENTRY_SIZE;
+    let mut previous: Option<(i64, usize)> = None;
+    while index.entries < max_entries {
+      let (offset, position) = index.entry(index.entries).expect("entry is in the index file");
+      if offset == base_offset && position == 0 {
+        break;
+      }
+      if let Some((previous_offset, previous_position)) = previous {
+        if offset <= previous_offset || position <= previous_position {
+          valid = false;
+          break;
+        }
+      }
+      previous = Some((offset, position));
+      index.entries += 1;
+    }
+
+    Ok((index, valid))
+  }
+
+  pub fn entry_count(&self) -> usize {
+    self.entries
+  }
+
+  // returns the offset and position stored in the n-th entry
+  pub fn entry(&self, n: usize) -> Option<(i64, usize)> {
+    let bytes = self.storage.read(n * OFFSET_INDEX_ENTRY_SIZE, OFFSET_INDEX_ENTRY_SIZE)?;
+    let relative_offset = read_i32(bytes)?;
+    let position        = read_i32(&bytes[4..])?;
+    Some((self.base_offset + relative_offset as i64, position as usize))
+  }
+
+  pub fn append(&mut self, offset: i64, position: usize) -> Option<()> {
+    let mut entry: Vec<u8> = Vec::with_capacity(OFFSET_INDEX_ENTRY_SIZE);
+    ser_i32((offset - self.base_offset) as i32, &mut entry);
+    ser_i32(position as i32, &mut entry);
+    self.storage.write(self.entries * OFFSET_INDEX_ENTRY_SIZE, &entry)?;
+    self.entries += 1;
+    Some(())
+  }
+
+  // returns the largest indexed offset lower or equal to `offset` and its
+  // position, or the start of the segment if there is none
+  pub fn lookup(&self, offset: i64) -> (i64, usize) {
+    let mut low  = 0;
+    let mut high = self.entries;
+    while low < high {
+      let middle = (low + high) / 2;
+      let (o, _) = self.entry(middle).expect("entry is in the index file");
+      if o <= offset {
+        low = middle + 1;
+      } else {
+        high = middle;
+      }
+    }
+
+    if low == 0 {
+      (self.base_offset, 0)
+    } else {
+      self.entry(low - 1).expect("entry is in the index file")
+    }
+  }
+
+  pub fn clear(&mut self) {
+    let zeros = vec![0; self.entries * OFFSET_INDEX_ENTRY_SIZE];
+    self.storage.write(0, &zeros);
+    self.entries = 0;
+  }
+
+  pub fn flush(&self) -> io::Result<()> {
+    self.storage.flush()
+  }
+}
+
+// sparse mapping from timestamps to offsets: an entry (timestamp, offset)
+// means that the largest timestamp appended up to `offset` is `timestamp`.
+// Each entry records a new largest timestamp, found at a later offset, so
+// the zeroed entries after the last one are recognized by their offset
+pub struct TimeIndex {
+  base_offset: i64,
+  entries:     usize,
+  storage:     Storage,
+}
+
+impl TimeIndex {
+
+  pub fn open(dir: &Path, base_offset: i64) -> io::Result<(TimeIndex, bool)> {
+    let path  = dir.join(time_index_file_name(base_offset));
+    let existed = path.exists();
+    let mut index = TimeIndex {
+      base_offset,
+      entries: 0,
+      storage: Storage::create(path)?,
+    };
+
+    let mut valid = existed;
+    let max_entries = index.storage.size() / TIME_INDEX_ENTRY_SIZE;
+    let mut previous: Option<(i64, i64)> = None;
+    while index.entries < max_entries {
+      let (timestamp, offset) = index.entry(index.entries).expect("entry is in the time index file");
+      let consistent = match previous {
+        Some((_, previous_offset)) if offset <= previous_offset => break,
+        Some((previous_timestamp, _))                           => timestamp > previous_timestamp,
+        // cannot be told apart from a first entry with timestamp 0 at the
+        // base offset, which the segment then rebuilds
+        None if timestamp == 0 && offset == base_offset         => break,
+        None                                                    => offset >= base_offset,
+      };
+      if !consistent {
+        valid = false;
+        break;
+      }
+      previous = Some((timestamp, offset));
+      index.entries += 1;
+    }
+
+    Ok((index, valid))
+  }
+
+  pub fn entry_count(&self) -> usize {
+    self.entries
+  }
+
+  // returns the timestamp and offset stored in the n-th entry
+  pub fn entry(&self, n: usize) -> Option<(i64, i64)> {
+    let bytes = self.storage.read(n * TIME_INDEX_ENTRY_SIZE, TIME_INDEX_ENTRY_SIZE)?;
+    let timestamp       = read_i64(bytes)?;
+    let relative_offset = read_i32(&bytes[8..])?;
+    Some((timestamp, self.base_offset + relative_offset as i64))
+  }
+
+  pub fn last_entry(&self) -> Option<(i64, i64)> {
+    if self.entries == 0 {
+      None
+    } else {
+      self.entry(self.entries - 1)
+    }
+  }
+
+  // only adds the entry if `timestamp` is larger than the last indexed one
+  pub fn maybe_append(&mut self, timestamp: i64, offset: i64) -> Option<()> {
+    if let Some((last_timestamp, _)) = self.last_entry() {
+      if timestamp <= last_timestamp {
+        return Some(());
+      }
+    }
+
+    let mut entry: Vec<u8> = Vec::with_capacity(TIME_INDEX_ENTRY_SIZE);
+    ser_i64(timestamp, &mut entry);
+    ser_i32((offset - self.base_offset) as i32, &mut entry);
+    self.storage.write(self.entries * TIME_INDEX_ENTRY_SIZE, &entry)?;
+    self.entries += 1;
+    Some(())
+  }
+
+  // returns the earliest offset that may hold a message with a timestamp
+  // larger or equal to `timestamp`: every message up to the offset of the
+  // last entry with a smaller timestamp is known to be older
+  pub fn lookup(&self, timestamp: i64) -> i64 {
+    let mut low  = 0;
+    let mut high = self.entries;
+    while low < high {
+      let middle = (low + high) / 2;
+      let (t, _) = self.entry(middle).expect("entry is in the time index file");
+      if t < timestamp {
+        low = middle + 1;
+      } else {
+        high = middle;
+      }
+    }
+
+    if low == 0 {
+      self.base_offset
+    } else {
+      let (_, offset) = self.entry(low - 1).expect("entry is in the time index file");
+      offset + 1
+    }
+  }
+
+  pub fn clear(&mut self) {
+    let zeros = vec![0; self.entries * TIME_INDEX_ENTRY_SIZE];
+    self.storage.write(0, &zeros);
+    self.entries = 0;
+  }
+
+  pub fn flush(&self) -> io::Result<()> {
+    self.storage.flush()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use util::test_dir;
+
+  #[test]
+  fn offset_index_lookup_test() {
+    let dir = test_dir("offset-index");
+    {
+      let (mut index, valid) = OffsetIndex::open(&dir, 100).unwrap();
+      assert!(!valid);
+      index.append(110, 4200).unwrap();
+      index.append(120, 8500).unwrap();
+      index.append(130, 12800).unwrap();
+
+      assert_eq!(index.lookup(100), (100, 0));
+      assert_eq!(index.lookup(109), (100, 0));
+      assert_eq!(index.lookup(110), (110, 4200));
+      assert_eq!(index.lookup(125), (120, 8500));
+      assert_eq!(index.lookup(1000), (130, 12800));
+    }
+
+    let (index, valid) = OffsetIndex::open(&dir, 100).unwrap();
+    assert!(valid);
+    assert_eq!(index.entry_count(), 3);
+    assert_eq!(index.lookup(125), (120, 8500));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn offset_index_corrupt_test() {
+    let dir = test_dir("offset-index-corrupt");
+    {
+      let (mut index, _) = OffsetIndex::open(&dir, 0).unwrap();
+      index.append(10, 4200).unwrap();
+      index.append(5, 8500).unwrap();
+    }
+
+    let (index, valid) = OffsetIndex::open(&dir, 0).unwrap();
+    assert!(!valid);
+    assert_eq!(index.entry_count(), 1);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn time_index_lookup_test() {
+    let dir = test_dir("time-index");
+    {
+      let (mut index, valid) = TimeIndex::open(&dir, 0).unwrap();
+      assert!(!valid);
+      index.maybe_append(1000, 9).unwrap();
+      index.maybe_append(1000, 12).unwrap();
+      index.maybe_append(2000, 19).unwrap();
+      assert_eq!(index.last_entry(), Some((2000, 19)));
+
+      assert_eq!(index.lookup(500), 0);
+      assert_eq!(index.lookup(1000), 0);
+      assert_eq!(index.lookup(1500), 10);
+      assert_eq!(index.lookup(2000), 10);
+      assert_eq!(index.lookup(3000), 20);
+    }
+
+    let (index, valid) = TimeIndex::open(&dir, 0).unwrap();
+    assert!(valid);
+    assert_eq!(index.last_entry(), Some((2000, 19)));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn time_index_zero_timestamp_test() {
+    let dir = test_dir("time-index-zero");
+    {
+      let (mut index, _) = TimeIndex::open(&dir, 10).unwrap();
+      index.maybe_append(-5, 11).unwrap();
+      index.maybe_append(0, 14).unwrap();
+      index.maybe_append(1000, 19).unwrap();
+      assert_eq!(index.lookup(0), 12);
+    }
+
+    // the entries end at the first one whose offset does not increase
+    let (index, valid) = TimeIndex::open(&dir, 10).unwrap();
+    assert!(valid);
+    assert_eq!(index.entry_count(), 3);
+    assert_eq!(index.entry(1), Some((0, 14)));
+    assert_eq!(index.lookup(1), 15);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
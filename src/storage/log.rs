@@ -0,0 +1,419 @@
+use std::io;
+use std::fs;
+use std::path::{Path,PathBuf};
+
+use storage::SharedBytes;
+use storage::segment::{Segment,ENTRY_HEADER_SIZE};
+use util::now_ms;
+
+pub const DEFAULT_SEGMENT_SIZE: usize = 1024 * 1024 * 1024;
+
+// special timestamps used by offset requests
+pub const LATEST_TIMESTAMP:   i64 = -1;
+pub const EARLIEST_TIMESTAMP: i64 = -2;
+
+pub fn partition_dir_name(topic: &str, partition: i32) -> String {
+  format!("{}-{}", topic, partition)
+}
+
+// parses a partition directory name back into a topic and partition.
+// Topic names can contain '-', so the partition is after the last one
+pub fn parse_partition_dir_name(name: &str) -> Option<(&str, i32)> {
+  let separator = name.rfind('-')?;
+  let (topic, partition) = (&name[..separator], &name[separator+1..]);
+  if topic.is_empty() {
+    return None;
+  }
+
+  partition.parse::<i32>().ok().map(|p| (topic, p))
+}
+
+// append only log of a topic partition, stored as a list of segment files
+// named after the offset of their first message
+pub struct PartitionLog {
+  dir:          PathBuf,
+  segment_size: usize,
+  segments:     Vec<Segment>,
+}
+
+impl PartitionLog {
+
+  pub fn open(root: &Path, topic: &str, partition: i32, segment_size: usize) -> io::Result<PartitionLog> {
+    let dir = root.join(partition_dir_name(topic, partition));
+    fs::create_dir_all(&dir)?;
+
+    let mut base_offsets: Vec<i64> = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+      let path = entry?.path();
+      if path.extension().and_then(|e| e.to_str()) != Some("log") {
+        continue;
+      }
+      if let Some(base_offset) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<i64>().ok()) {
+        base_offsets.push(base_offset);
+      }
+    }
+    base_offsets.sort();
+
+    let mut segments = Vec::with_capacity(base_offsets.len());
+    for base_offset in base_offsets {
+      segments.push(Segment::open(&dir, base_offset)?);
+    }
+
+    if segments.is_empty() {
+      segments.push(Segment::open(&dir, 0)?);
+    }
+
+    Ok(PartitionLog {
+      dir,
+      segment_size,
+      segments,
+    })
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  pub fn segments(&self) -> &[Segment] {
+    &self.segments
+  }
+
+  // offset of the first message still stored
+  pub fn log_start_offset(&self) -> i64 {
+    self.segments[0].base_offset()
+  }
+
+  // offset that will be assigned to the next appended message
+  pub fn log_end_offset(&self) -> i64 {
+    self.active_segment().next_offset()
+  }
+
+  fn active_segment(&self) -> &Segment {
+    self.segments.last().expect("a partition log always has an active segment")
+  }
+
+  // index of the segment that holds `offset`
+  fn segment_index(&self, offset: i64) -> Option<usize> {
+    if offset < self.log_start_offset() || offset >= self.log_end_offset() {
+      return None;
+    }
+
+    match self.segments.binary_search_by_key(&offset, |s| s.base_offset()) {
+      Ok(i)  => Some(i),
+      Err(i) => Some(i - 1),
+    }
+  }
+
+  // returns the segment holding `offset` and the position of its entry
+  pub fn find(&self, offset: i64) -> Option<(&Segment, usize)> {
+    let segment = &self.segments[self.segment_index(offset)?];
+    segment.position_for_offset(offset).map(|position| (segment, position))
+  }
+
+  // returns the complete entries starting at `offset`, up to `max_bytes`
+  // from a single segment, or None if `offset` is out of the log's range.
+  // The bytes are not copied from the segment
+  pub fn read(&self, offset: i64, max_bytes: usize) -> Option<SharedBytes> {
+    if offset == self.log_end_offset() {
+      return Some(SharedBytes::from(Vec::new()));
+    }
+
+    self.find(offset).map(|(segment, position)| segment.read_entries(position, max_bytes))
+  }
+
+  // resolves the timestamp of an offset request to (timestamp, offset): the
+  // latest and earliest sentinels map to the log end and start offsets,
+  // other timestamps to the earliest message with a larger or equal
+  // timestamp. The timestamp is -1 when it is not known
+  pub fn offset_for_time(&self, time: i64) -> (i64, i64) {
+    match time {
+      LATEST_TIMESTAMP   => (-1, self.log_end_offset()),
+      EARLIEST_TIMESTAMP => (-1, self.log_start_offset()),
+      _                  => {
+        self.segments.iter()
+          .filter_map(|s| s.offset_for_timestamp(time))
+          .next()
+          .unwrap_or_else(|| (-1, self.log_end_offset()))
+      }
+    }
+  }
+
+  // offsets answered to a version 0 offset request: the base offsets of the
+  // segments, and the log end offset, whose last message is older than `time`,
+  // from the most recent one. The latest and earliest sentinels respectively
+  // start from the log end and only return the log start offset
+  pub fn offsets_before(&self, time: i64, max_number_of_offsets: usize) -> Vec<i64> {
+    let mut offsets: Vec<(i64, i64)> = self.segments.iter()
+      .filter(|s| !s.is_empty())
+      .map(|s| (s.base_offset(), s.max_timestamp()))
+      .collect();
+    offsets.push((self.log_end_offset(), now_ms()));
+
+    let count = match time {
+      LATEST_TIMESTAMP   => offsets.len(),
+      EARLIEST_TIMESTAMP => 1,
+      _                  => offsets.iter().rposition(|&(_, t)| t <= time).map(|i| i + 1).unwrap_or(0)
+    };
+
+    offsets[..count].iter().rev().take(max_number_of_offsets).map(|&(o, _)| o).collect()
+  }
+
+  // appends a message with the current time as timestamp and
+  // returns the offset assigned to it
+  pub fn append(&mut self, message: &[u8]) -> io::Result<i64> {
+    self.append_with_timestamp(message, now_ms())
+  }
+
+  pub fn append_with_timestamp(&mut self, message: &[u8], timestamp: i64) -> io::Result<i64> {
+    let offset = self.log_end_offset();
+    self.append_entry(offset, message, timestamp)?;
+    Ok(offset)
+  }
+
+  // appends an entry whose header holds `offset`, which is past the log end
+  // offset for legacy compressed messages: they are stored under the offset
+  // of their last inner message
+  pub fn append_entry(&mut self, offset: i64, message: &[u8], timestamp: i64) -> io::Result<()> {
+    if offset < self.log_end_offset() {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("offset {} is before the log end offset", offset)));
+    }
+
+    let entry_size = ENTRY_HEADER_SIZE + message.len();
+    {
+      let active = self.active_segment();
+      if !active.is_empty() && active.size() + entry_size > self.segment_size {
+        self.roll()?;
+      }
+    }
+
+    let active = self.segments.last_mut().expect("a partition log always has an active segment");
+    match active.append(offset, message, timestamp) {
+      Some(_) => Ok(()),
+      None    => Err(io::Error::other("could not write to segment")),
+    }
+  }
+
+  // closes the active segment and starts a new one at the log end offset
+  pub fn roll(&mut self) -> io::Result<()> {
+    let base_offset = self.log_end_offset();
+    self.segments.last_mut().expect("a partition log always has an active segment").seal()?;
+    let segment = Segment::open(&self.dir, base_offset)?;
+    self.segments.push(segment);
+    Ok(())
+  }
+
+  // returns once the active segment is written to disk
+  pub fn sync(&self) -> io::Result<()> {
+    self.active_segment().sync()
+  }
+
+  // deletes the segments whose messages are all older than `time`.
+  // Returns the number of deleted segments
+  pub fn delete_segments_before(&mut self, time: i64) -> io::Result<usize> {
+    self.delete_segments_while(|s| s.max_timestamp() < time)
+  }
+
+  // deletes the segments whose messages all have offsets before `offset`.
+  // Returns the number of deleted segments
+  pub fn delete_segments_before_offset(&mut self, offset: i64) -> io::Result<usize> {
+    self.delete_segments_while(|s| s.next_offset() <= offset)
+  }
+
+  // deletes segments from the start of the log while `condition` holds.
+  // The active segment is kept, so that the log end offset is preserved
+  fn delete_segments_while<F: Fn(&Segment) -> bool>(&mut self, condition: F) -> io::Result<usize> {
+    let mut deleted = 0;
+    while self.segments.len() > 1 && condition(&self.segments[0]) {
+      let segment = self.segments.remove(0);
+      info!("deleting segment {} of {:?}", segment.base_offset(), self.dir);
+      segment.delete(&self.dir)?;
+      deleted += 1;
+    }
+
+    Ok(deleted)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use util::test_dir;
+
+  #[test]
+  fn parse_partition_dir_name_test() {
+    assert_eq!(parse_partition_dir_name("topic1-0"), Some(("topic1", 0)));
+    assert_eq!(parse_partition_dir_name("my-topic-12"), Some(("my-topic", 12)));
+    assert_eq!(parse_partition_dir_name("topic1"), None);
+    assert_eq!(parse_partition_dir_name("-1"), None);
+  }
+
+  #[test]
+  fn append_assigns_offsets_test() {
+    let dir = test_dir("append");
+    let mut log = PartitionLog::open(&dir, "topic1", 0, DEFAULT_SEGMENT_SIZE).unwrap();
+
+    assert_eq!(log.log_end_offset(), 0);
+    assert_eq!(log.append(b"hello").unwrap(), 0);
+    assert_eq!(log.append(b"world").unwrap(), 1);
+    assert_eq!(log.log_end_offset(), 2);
+    assert_eq!(log.segments()[0].size(), 2 * (ENTRY_HEADER_SIZE + 5));
+
+    // a legacy wrapper of three messages is stored under its last offset
+    log.append_entry(4, b"wrapper", 0).unwrap();
+    assert_eq!(log.log_end_offset(), 5);
+    assert!(log.append_entry(4, b"again", 0).is_err());
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn segment_rollover_test() {
+    let dir = test_dir("rollover");
+    let mut log = PartitionLog::open(&dir, "topic1", 0, 40).unwrap();
+
+    for _ in 0..5 {
+      log.append(b"0123456789").unwrap();
+    }
+
+    // each entry takes 22 bytes, so a 40 bytes segment only holds one
+    let base_offsets: Vec<i64> = log.segments().iter().map(|s| s.base_offset()).collect();
+    assert_eq!(base_offsets, vec![0, 1, 2, 3, 4]);
+    assert!(dir.join("topic1-0").join("00000000000000000003.log").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn reopen_recovers_log_end_offset_test() {
+    let dir = test_dir("reopen");
+    {
+      let mut log = PartitionLog::open(&dir, "topic1", 3, 64).unwrap();
+      for _ in 0..7 {
+        log.append(b"some message").unwrap();
+      }
+      log.sync().unwrap();
+    }
+
+    let mut log = PartitionLog::open(&dir, "topic1", 3, 64).unwrap();
+    assert_eq!(log.log_start_offset(), 0);
+    assert_eq!(log.log_end_offset(), 7);
+    assert_eq!(log.append(b"another").unwrap(), 7);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn find_test() {
+    let dir = test_dir("find");
+    let mut log = PartitionLog::open(&dir, "topic1", 0, 64).unwrap();
+    for _ in 0..10 {
+      log.append(b"0123456789").unwrap();
+    }
+
+    assert!(log.find(10).is_none());
+    let (segment, position) = log.find(5).unwrap();
+    assert_eq!(segment.base_offset(), 4);
+    assert_eq!(position, 22);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn read_test() {
+    let dir = test_dir("read");
+    let mut log = PartitionLog::open(&dir, "topic1", 0, 64).unwrap();
+    for _ in 0..5 {
+      log.append(b"0123456789").unwrap();
+    }
+
+    assert_eq!(log.read(1, 100).map(|b| b.len()), Some(22));
+    assert_eq!(log.read(2, 100).map(|b| b.len()), Some(44));
+    assert_eq!(log.read(2, 30).map(|b| b.len()), Some(22));
+    assert_eq!(log.read(2, 1).map(|b| b.len()), Some(22));
+    assert_eq!(log.read(5, 100).map(|b| b.len()), Some(0));
+    assert_eq!(log.read(6, 100), None);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn delete_segments_before_test() {
+    let dir = test_dir("retention");
+    {
+      let mut log = PartitionLog::open(&dir, "topic1", 0, 64).unwrap();
+      for i in 0..5 {
+        log.append_with_timestamp(b"0123456789", 1000 * (i + 1)).unwrap();
+      }
+
+      // segments start at 0, 2 and 4
+      assert_eq!(log.delete_segments_before(2500).unwrap(), 1);
+      assert_eq!(log.log_start_offset(), 2);
+      assert!(log.read(0, 1024).is_none());
+      assert_eq!(log.delete_segments_before(10000).unwrap(), 1);
+      assert_eq!(log.log_start_offset(), 4);
+      assert_eq!(log.log_end_offset(), 5);
+    }
+
+    let log = PartitionLog::open(&dir, "topic1", 0, 64).unwrap();
+    assert_eq!(log.log_start_offset(), 4);
+    assert_eq!(log.segments().len(), 1);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn delete_segments_before_offset_test() {
+    let dir = test_dir("delete-offset");
+    let mut log = PartitionLog::open(&dir, "topic1", 0, 64).unwrap();
+    for _ in 0..5 {
+      log.append(b"0123456789").unwrap();
+    }
+
+    // segments start at 0, 2 and 4
+    assert_eq!(log.delete_segments_before_offset(3).unwrap(), 1);
+    assert_eq!(log.log_start_offset(), 2);
+    assert_eq!(log.delete_segments_before_offset(4).unwrap(), 1);
+    assert_eq!(log.delete_segments_before_offset(10).unwrap(), 0);
+    assert_eq!(log.log_start_offset(), 4);
+    assert_eq!(log.log_end_offset(), 5);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn offsets_before_test() {
+    let dir = test_dir("offsets-before");
+    let mut log = PartitionLog::open(&dir, "topic1", 0, 64).unwrap();
+    for i in 0..5 {
+      log.append_with_timestamp(b"0123456789", 1000 * (i + 1)).unwrap();
+    }
+
+    // segments start at 0, 2 and 4
+    assert_eq!(log.offsets_before(LATEST_TIMESTAMP, 10), vec![5, 4, 2, 0]);
+    assert_eq!(log.offsets_before(LATEST_TIMESTAMP, 1), vec![5]);
+    assert_eq!(log.offsets_before(EARLIEST_TIMESTAMP, 10), vec![0]);
+    assert_eq!(log.offsets_before(4500, 10), vec![2, 0]);
+    assert_eq!(log.offsets_before(500, 10), Vec::<i64>::new());
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn offset_for_time_test() {
+    let dir = test_dir("offset-for-time");
+    let mut log = PartitionLog::open(&dir, "topic1", 0, 64).unwrap();
+    for i in 0..10 {
+      log.append_with_timestamp(b"0123456789", 1000 * (i + 1)).unwrap();
+    }
+
+    assert_eq!(log.offset_for_time(LATEST_TIMESTAMP), (-1, 10));
+    assert_eq!(log.offset_for_time(EARLIEST_TIMESTAMP), (-1, 0));
+    assert_eq!(log.offset_for_time(500), (-1, 0));
+    // segments hold two messages each, the one with 6000 starts at 4
+    assert_eq!(log.offset_for_time(5500), (-1, 4));
+    assert_eq!(log.offset_for_time(20000), (-1, 10));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,99 @@
+use std::io;
+use std::fs;
+use std::collections::HashMap;
+use std::path::{Path,PathBuf};
+use std::sync::{Arc,Mutex,RwLock};
+
+use storage::log::{PartitionLog,parse_partition_dir_name};
+
+// each partition log has its own lock, so that requests for different
+// partitions do not wait for each other
+pub type SharedLog = Arc<Mutex<PartitionLog>>;
+
+// owns the logs of every topic partition stored in the data directory.
+// The map is only locked to find a log or add one
+pub struct LogManager {
+  root:         PathBuf,
+  segment_size: usize,
+  logs:         RwLock<HashMap<(String, i32), SharedLog>>,
+}
+
+impl LogManager {
+
+  // opens every partition log found in `root`
+  pub fn open(root: &Path, segment_size: usize) -> io::Result<LogManager> {
+    fs::create_dir_all(root)?;
+
+    let mut logs = HashMap::new();
+    for entry in fs::read_dir(root)? {
+      let entry = entry?;
+      if !entry.file_type()?.is_dir() {
+        continue;
+      }
+
+      let name = entry.file_name();
+      if let Some((topic, partition)) = name.to_str().and_then(parse_partition_dir_name) {
+        info!("loading log for partition {}-{}", topic, partition);
+        let log = PartitionLog::open(root, topic, partition, segment_size)?;
+        logs.insert((topic.to_string(), partition), Arc::new(Mutex::new(log)));
+      }
+    }
+
+    Ok(LogManager {
+      root: root.to_path_buf(),
+      segment_size,
+      logs: RwLock::new(logs),
+    })
+  }
+
+  pub fn get(&self, topic: &str, partition: i32) -> Option<SharedLog> {
+    self.logs.read().unwrap().get(&(topic.to_string(), partition)).cloned()
+  }
+
+  // returns the log of the partition, creating it if it does not exist
+  pub fn create(&self, topic: &str, partition: i32) -> io::Result<SharedLog> {
+    let key = (topic.to_string(), partition);
+    let mut logs = self.logs.write().unwrap();
+    if !logs.contains_key(&key) {
+      let log = PartitionLog::open(&self.root, topic, partition, self.segment_size)?;
+      logs.insert(key.clone(), Arc::new(Mutex::new(log)));
+    }
+
+    Ok(logs[&key].clone())
+  }
+
+  // every log, to be locked one at a time by the caller
+  pub fn logs(&self) -> Vec<(String, i32, SharedLog)> {
+    self.logs.read().unwrap().iter().map(|(&(ref t, p), log)| (t.clone(), p, log.clone())).collect()
+  }
+
+  pub fn partitions(&self) -> Vec<(String, i32)> {
+    let mut partitions: Vec<(String, i32)> = self.logs.read().unwrap().keys().cloned().collect();
+    partitions.sort();
+    partitions
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use util::test_dir;
+
+  #[test]
+  fn open_existing_partitions_test() {
+    let dir = test_dir("log-manager");
+    {
+      let logs = LogManager::open(&dir, 1024).unwrap();
+      assert!(logs.get("topic1", 0).is_none());
+      logs.create("topic1", 0).unwrap().lock().unwrap().append(b"message").unwrap();
+      logs.create("my-topic", 2).unwrap();
+    }
+
+    let logs = LogManager::open(&dir, 1024).unwrap();
+    assert_eq!(logs.partitions(), vec![("my-topic".to_string(), 2), ("topic1".to_string(), 0)]);
+    assert_eq!(logs.get("topic1", 0).unwrap().lock().unwrap().log_end_offset(), 1);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,189 @@
+//#![feature(core)]
+
+//extern crate core;
+use std::io;
+use std::fmt;
+use std::ptr;
+use std::slice;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::ops::Deref;
+use std::path::{Path,PathBuf};
+use std::sync::Arc;
+use memmap::MmapMut;
+
+pub mod index;
+pub mod segment;
+pub mod log;
+pub mod log_manager;
+
+const PAGE_SIZE: usize = 4096;
+
+pub struct Storage {
+  filename: PathBuf,
+  file:     File,
+  size:     usize,
+  // shared with the bytes read from it, so that responses are sent from
+  // the mapping. It is only written through `data`, and only past the
+  // bytes that were read
+  map:      Arc<MmapMut>,
+  data:     *mut u8,
+}
+
+// the mapping is only written through `&mut self`
+unsafe impl Send for Storage {}
+
+impl Storage {
+
+  pub fn create<P: AsRef<Path>>(filename: P) -> io::Result<Storage> {
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(filename.as_ref())?;
+
+    // fills the file with 0
+    let mut size = file.metadata()?.len() as usize;
+    if size == 0 {
+      file.set_len(PAGE_SIZE as u64)?;
+      size = PAGE_SIZE;
+    }
+
+    let mut map = unsafe { MmapMut::map_mut(&file)? };
+    let data = map.as_mut_ptr();
+
+    Ok(Storage { filename: filename.as_ref().to_path_buf(), file, size, map: Arc::new(map), data })
+  }
+
+  pub fn filename(&self) -> &Path {
+    &self.filename
+  }
+
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  pub fn read(&self, position: usize, length: usize) -> Option<&[u8]> {
+    if position > self.size || length > self.size || self.size - length < position {
+      None
+    } else {
+      Some(unsafe { slice::from_raw_parts(self.data.add(position), length) })
+    }
+  }
+
+  // like `read`, but the bytes stay valid after the storage is written,
+  // grown or deleted
+  pub fn share(&self, position: usize, length: usize) -> Option<SharedBytes> {
+    self.read(position, length).map(|bytes| SharedBytes { owner: self.map.clone(), data: bytes.as_ptr(), len: length })
+  }
+
+  pub fn write(&mut self, position: usize, src: &[u8]) -> Option<()> {
+    let length = src.len();
+    if position + length > self.size && self.grow(position + length).is_err() {
+      return None;
+    }
+
+    unsafe { ptr::copy_nonoverlapping(src.as_ptr(), self.data.add(position), length) };
+    Some(())
+  }
+
+  // overwrites `length` bytes with zeros, without growing the file
+  pub fn zero(&mut self, position: usize, length: usize) -> Option<()> {
+    self.read(position, length)?;
+    unsafe { ptr::write_bytes(self.data.add(position), 0, length) };
+    Some(())
+  }
+
+  // grows the file to at least `min_size`, doubling the current size to
+  // avoid remapping on every append
+  pub fn grow(&mut self, min_size: usize) -> io::Result<()> {
+    let mut new_size = self.size * 2;
+    while new_size < min_size {
+      new_size *= 2;
+    }
+
+    // shared bytes keep the previous mapping, the file only grows
+    self.file.set_len(new_size as u64)?;
+    let mut map = unsafe { MmapMut::map_mut(&self.file)? };
+    self.data = map.as_mut_ptr();
+    self.map  = Arc::new(map);
+    self.size = new_size;
+    Ok(())
+  }
+
+  pub fn flush(&self) -> io::Result<()> {
+    self.map.flush_async()
+  }
+
+  // returns once the data is written to disk
+  pub fn sync(&self) -> io::Result<()> {
+    self.map.flush()
+  }
+}
+
+// bytes sent to another thread without being copied: a range of a mapped
+// file, kept mapped while they are referenced, or an owned buffer
+#[derive(Clone)]
+pub struct SharedBytes {
+  owner: Arc<dyn Send + Sync>,
+  data:  *const u8,
+  len:   usize,
+}
+
+// the bytes are never written while they are shared
+unsafe impl Send for SharedBytes {}
+unsafe impl Sync for SharedBytes {}
+
+impl SharedBytes {
+  // the bytes from `start` to `end`, sharing the same owner
+  pub fn slice(&self, start: usize, end: usize) -> SharedBytes {
+    assert!(start <= end && end <= self.len, "slice out of range");
+    SharedBytes { owner: self.owner.clone(), data: unsafe { self.data.add(start) }, len: end - start }
+  }
+}
+
+impl From<Vec<u8>> for SharedBytes {
+  fn from(bytes: Vec<u8>) -> SharedBytes {
+    let owner = Arc::new(bytes);
+    SharedBytes { data: owner.as_ptr(), len: owner.len(), owner }
+  }
+}
+
+impl Deref for SharedBytes {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    unsafe { slice::from_raw_parts(self.data, self.len) }
+  }
+}
+
+impl fmt::Debug for SharedBytes {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    (**self).fmt(f)
+  }
+}
+
+impl PartialEq for SharedBytes {
+  fn eq(&self, other: &SharedBytes) -> bool {
+    **self == **other
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use util::test_dir;
+
+  #[test]
+  fn share_test() {
+    let dir = test_dir("storage-share");
+    let mut storage = Storage::create(dir.join("shared")).unwrap();
+    storage.write(0, b"hello").unwrap();
+    let shared = storage.share(1, 3).unwrap();
+    assert!(storage.share(PAGE_SIZE, 1).is_none());
+
+    // the bytes stay valid after the file is remapped and deleted
+    storage.write(PAGE_SIZE, b"world").unwrap();
+    drop(storage);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(&shared[..], b"ell");
+    assert_eq!(&shared.slice(1, 3)[..], b"ll");
+  }
+}
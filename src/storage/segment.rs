@@ -0,0 +1,546 @@
+use std::io;
+use std::fs;
+use std::path::Path;
+
+use nom::{be_i16,be_i32,be_i64};
+use nom::IResult::*;
+
+use parser::message::{MAGIC_POSITION,COMPRESSION_CODEC_MASK,TIMESTAMP_TYPE_MASK,RECORD_BATCH_OVERHEAD,record};
+use responses::primitive::{ser_i32,ser_i64};
+use storage::{Storage,SharedBytes};
+use storage::index::{OffsetIndex,TimeIndex,INDEX_INTERVAL_BYTES,index_file_name,time_index_file_name};
+use util::{now_ms,timestamp_ms};
+
+// every entry in a segment is stored in the wire format of a message set:
+// Offset => int64, MessageSize => int32, Message => bytes
+// An entry is either a legacy message, or a record batch holding several
+// offsets from the base offset in its header
+pub const ENTRY_HEADER_SIZE: usize = 12;
+// positions of record batch fields, after the entry header
+const ATTRIBUTES_POSITION: usize        = 9;
+const LAST_OFFSET_DELTA_POSITION: usize = 11;
+const FIRST_TIMESTAMP_POSITION: usize   = 15;
+const MAX_TIMESTAMP_POSITION: usize     = 23;
+// position of the timestamp of a magic 1 message, after the entry header
+const MESSAGE_TIMESTAMP_POSITION: usize = 6;
+
+// number of offsets after the first one used by an entry
+fn last_offset_delta(message: &[u8]) -> i64 {
+  if message.len() < LAST_OFFSET_DELTA_POSITION + 4 || message[MAGIC_POSITION - ENTRY_HEADER_SIZE] != 2 {
+    return 0;
+  }
+
+  match be_i32(&message[LAST_OFFSET_DELTA_POSITION..]) {
+    Done(_, delta) if delta > 0 => delta as i64,
+    _                           => 0,
+  }
+}
+
+fn magic(message: &[u8]) -> Option<u8> {
+  message.get(MAGIC_POSITION - ENTRY_HEADER_SIZE).cloned()
+}
+
+fn read_i64_at(message: &[u8], position: usize) -> Option<i64> {
+  match be_i64(message.get(position..)?) {
+    Done(_, v) => Some(v),
+    _          => None,
+  }
+}
+
+// largest timestamp of the messages of an entry, None for legacy messages
+// which have no timestamp
+fn entry_max_timestamp(message: &[u8]) -> Option<i64> {
+  match magic(message)? {
+    1 => read_i64_at(message, MESSAGE_TIMESTAMP_POSITION),
+    2 => read_i64_at(message, MAX_TIMESTAMP_POSITION),
+    _ => None,
+  }
+}
+
+// (timestamp, offset) of the messages of an entry starting at `offset`,
+// with -1 as timestamp for legacy messages. Records of compressed batches
+// are not read, and batches with the log append time use it for every record
+fn entry_timestamps(offset: i64, message: &[u8]) -> Vec<(i64, i64)> {
+  if magic(message) != Some(2) || message.len() < RECORD_BATCH_OVERHEAD {
+    return vec![(entry_max_timestamp(message).unwrap_or(-1), offset)];
+  }
+
+  let attributes = match be_i16(&message[ATTRIBUTES_POSITION..]) {
+    Done(_, a) => a,
+    _          => 0,
+  };
+  let max_timestamp = read_i64_at(message, MAX_TIMESTAMP_POSITION).unwrap_or(-1);
+  if attributes & (COMPRESSION_CODEC_MASK | TIMESTAMP_TYPE_MASK) != 0 {
+    return vec![(max_timestamp, offset)];
+  }
+
+  let first_timestamp = read_i64_at(message, FIRST_TIMESTAMP_POSITION).unwrap_or(-1);
+  let mut timestamps = Vec::new();
+  let mut records = &message[RECORD_BATCH_OVERHEAD..];
+  while let Done(rest, r) = record(records) {
+    timestamps.push((first_timestamp + r.timestamp_delta, offset + r.offset_delta as i64));
+    records = rest;
+  }
+  timestamps
+}
+
+pub fn log_file_name(base_offset: i64) -> String {
+  format!("{:020}.log", base_offset)
+}
+
+pub struct Segment {
+  base_offset:             i64,
+  next_offset:             i64,
+  size:                    usize,
+  bytes_since_index_entry: usize,
+  max_timestamp:           i64,
+  offset_of_max_timestamp: i64,
+  log:                     Storage,
+  index:                   OffsetIndex,
+  time_index:              TimeIndex,
+}
+
+impl Segment {
+
+  // opens the segment starting at `base_offset` in `dir`, creating it if needed.
+  // Existing data is scanned to find the end of the last complete entry, and
+  // the indexes are rebuilt if they are missing or do not match the log
+  pub fn open(dir: &Path, base_offset: i64) -> io::Result<Segment> {
+    let log                        = Storage::create(dir.join(log_file_name(base_offset)))?;
+    let (index, index_valid)       = OffsetIndex::open(dir, base_offset)?;
+    let (time_index, time_valid)   = TimeIndex::open(dir, base_offset)?;
+
+    let mut segment = Segment {
+      base_offset,
+      next_offset:             base_offset,
+      size:                    0,
+      bytes_since_index_entry: 0,
+      // no timestamp yet, as in the messages that have none
+      max_timestamp:           -1,
+      offset_of_max_timestamp: base_offset,
+      log,
+      index,
+      time_index,
+    };
+    segment.recover(index_valid, time_valid)?;
+
+    Ok(segment)
+  }
+
+  fn recover(&mut self, index_valid: bool, time_valid: bool) -> io::Result<()> {
+    // index entries as they would have been written by `append`
+    let mut expected_index: Vec<(i64, usize)> = Vec::new();
+    let mut expected_time_index: Vec<(i64, i64)> = Vec::new();
+    // largest timestamp found in the messages, and its offset
+    let mut max_timestamp: Option<(i64, i64)> = None;
+    let mut position = 0;
+    while let Some((offset, last_offset, length)) = self.entry_at(position) {
+      if offset < self.next_offset {
+        break;
+      }
+      let timestamp = self.log.read(position + ENTRY_HEADER_SIZE, length - ENTRY_HEADER_SIZE).and_then(entry_max_timestamp);
+      if let Some(t) = timestamp {
+        if max_timestamp.map(|(m, _)| t > m) != Some(false) {
+          max_timestamp = Some((t, last_offset));
+        }
+      }
+      if self.bytes_since_index_entry > INDEX_INTERVAL_BYTES {
+        expected_index.push((offset, position));
+        expected_time_index.extend(max_timestamp);
+        self.bytes_since_index_entry = 0;
+      }
+      self.bytes_since_index_entry += length;
+      self.next_offset = last_offset + 1;
+      position += length;
+    }
+    self.size = position;
+
+    // erase any partially written entry, so that it cannot be mistaken
+    // for valid data once new entries are appended before it
+    let tail = self.log.size() - self.size;
+    if tail > 0 && self.log.read(self.size, tail).map(|t| t.iter().any(|b| *b != 0)) == Some(true) {
+      self.log.zero(self.size, tail);
+    }
+
+    let index_matches = index_valid && self.index.entry_count() == expected_index.len() &&
+      expected_index.iter().enumerate().all(|(i, e)| self.index.entry(i) == Some(*e));
+    if !index_matches {
+      if !self.is_empty() {
+        info!("rebuilding offset index of segment {:?}", self.log.filename());
+      }
+      self.index.clear();
+      for &(offset, position) in expected_index.iter() {
+        if self.index.append(offset, position).is_none() {
+          return Err(io::Error::other("could not write to offset index"));
+        }
+      }
+    }
+
+    // timestamps are not stored in legacy messages, so without newer ones
+    // the time index can only be rebuilt from the last modification time
+    // of the log file
+    let modified = fs::metadata(self.log.filename())?.modified().map(timestamp_ms).unwrap_or_else(|_| now_ms());
+    let max_timestamp = max_timestamp.unwrap_or((modified, self.next_offset - 1));
+    let time_matches = time_valid && self.time_index.last_entry().map(|(_, o)| o < self.next_offset) != Some(false) &&
+      (self.time_index.entry_count() > 0 || expected_time_index.is_empty());
+    if !time_matches {
+      self.time_index.clear();
+      if !self.is_empty() {
+        info!("rebuilding time index of segment {:?}", self.log.filename());
+        for &(timestamp, offset) in expected_time_index.iter().chain(Some(&max_timestamp)) {
+          self.time_index.maybe_append(timestamp, offset);
+        }
+      }
+    }
+
+    match self.time_index.last_entry() {
+      Some((timestamp, offset)) => {
+        self.max_timestamp           = timestamp;
+        self.offset_of_max_timestamp = offset;
+      },
+      None if !self.is_empty() => {
+        self.max_timestamp           = max_timestamp.0;
+        self.offset_of_max_timestamp = max_timestamp.1;
+      },
+      None => {}
+    }
+
+    Ok(())
+  }
+
+  // returns the first and last offsets, and the total length, of the
+  // entry written at `position`
+  fn entry_at(&self, position: usize) -> Option<(i64, i64, usize)> {
+    let header = self.log.read(position, ENTRY_HEADER_SIZE)?;
+    let offset = match be_i64(header) {
+      Done(_, o) => o,
+      _          => return None,
+    };
+    let size = match be_i32(&header[8..]) {
+      Done(_, s) => s,
+      _          => return None,
+    };
+
+    if size <= 0 {
+      return None;
+    }
+
+    let length = ENTRY_HEADER_SIZE + size as usize;
+    self.log.read(position, length).map(|entry| (offset, offset + last_offset_delta(&entry[ENTRY_HEADER_SIZE..]), length))
+  }
+
+  pub fn base_offset(&self) -> i64 {
+    self.base_offset
+  }
+
+  pub fn next_offset(&self) -> i64 {
+    self.next_offset
+  }
+
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.size == 0
+  }
+
+  // largest timestamp of the messages in this segment
+  pub fn max_timestamp(&self) -> i64 {
+    self.max_timestamp
+  }
+
+  // writes `message` at the end of the segment with the given offset,
+  // which is the base offset of record batches, and returns the position
+  // of the new entry
+  pub fn append(&mut self, offset: i64, message: &[u8], timestamp: i64) -> Option<usize> {
+    let position = self.size;
+    let mut entry: Vec<u8> = Vec::with_capacity(ENTRY_HEADER_SIZE + message.len());
+    ser_i64(offset, &mut entry);
+    ser_i32(message.len() as i32, &mut entry);
+    entry.extend_from_slice(message);
+
+    self.log.write(position, &entry)?;
+    let last_offset  = offset + last_offset_delta(message);
+    self.size       += entry.len();
+    self.next_offset = last_offset + 1;
+
+    if timestamp > self.max_timestamp {
+      self.max_timestamp           = timestamp;
+      self.offset_of_max_timestamp = last_offset;
+    }
+
+    if self.bytes_since_index_entry > INDEX_INTERVAL_BYTES {
+      self.index.append(offset, position)?;
+      self.time_index.maybe_append(self.max_timestamp, self.offset_of_max_timestamp)?;
+      self.bytes_since_index_entry = 0;
+    }
+    self.bytes_since_index_entry += entry.len();
+
+    Some(position)
+  }
+
+  // returns the position of the entry holding `offset`, if it is in this segment
+  pub fn position_for_offset(&self, offset: i64) -> Option<usize> {
+    if offset < self.base_offset || offset >= self.next_offset {
+      return None;
+    }
+
+    let (_, mut position) = self.index.lookup(offset);
+    while let Some((_, last_offset, length)) = self.entry_at(position) {
+      if last_offset >= offset {
+        return Some(position);
+      }
+      position += length;
+    }
+    None
+  }
+
+  // returns the earliest message of this segment with a timestamp larger
+  // or equal to `timestamp`, as (timestamp, offset). Legacy messages have
+  // no timestamp, so the first one the time index points to is returned
+  // with -1 as timestamp
+  pub fn offset_for_timestamp(&self, timestamp: i64) -> Option<(i64, i64)> {
+    if self.is_empty() || timestamp > self.max_timestamp {
+      return None;
+    }
+
+    let mut position = self.position_for_offset(self.time_index.lookup(timestamp))?;
+    while let Some((offset, _, length)) = self.entry_at(position) {
+      if position + length > self.size {
+        break;
+      }
+
+      let message = self.log.read(position + ENTRY_HEADER_SIZE, length - ENTRY_HEADER_SIZE)?;
+      let found = entry_timestamps(offset, message).into_iter().find(|&(t, _)| t < 0 || t >= timestamp);
+      if found.is_some() {
+        return found;
+      }
+      position += length;
+    }
+    None
+  }
+
+  // returns the complete entries starting at `position` that fit in
+  // `max_bytes`. The first entry is always returned whole even if it is
+  // larger, otherwise a consumer could never get past it
+  pub fn read_entries(&self, position: usize, max_bytes: usize) -> SharedBytes {
+    let mut end = position;
+    while let Some((_, _, length)) = self.entry_at(end) {
+      if end + length > self.size || (end > position && end + length - position > max_bytes) {
+        break;
+      }
+      end += length;
+    }
+
+    self.log.share(position, end - position).unwrap_or_else(|| SharedBytes::from(Vec::new()))
+  }
+
+  // called when a new segment is rolled, to make sure the time index
+  // records the largest timestamp of this one
+  pub fn seal(&mut self) -> io::Result<()> {
+    if !self.is_empty() && self.time_index.maybe_append(self.max_timestamp, self.offset_of_max_timestamp).is_none() {
+      return Err(io::Error::other("could not write to time index"));
+    }
+    self.flush()
+  }
+
+  pub fn flush(&self) -> io::Result<()> {
+    self.log.flush()?;
+    self.index.flush()?;
+    self.time_index.flush()
+  }
+
+  // the indexes are rebuilt from the log if they do not match it
+  pub fn sync(&self) -> io::Result<()> {
+    self.log.sync()
+  }
+
+  // unmaps the segment and removes its files from `dir`
+  pub fn delete(self, dir: &Path) -> io::Result<()> {
+    let base_offset = self.base_offset;
+    drop(self);
+
+    fs::remove_file(dir.join(log_file_name(base_offset)))?;
+    fs::remove_file(dir.join(index_file_name(base_offset)))?;
+    fs::remove_file(dir.join(time_index_file_name(base_offset)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use util::test_dir;
+
+  use parser::message::Message;
+  use responses::fetch::ser_message;
+  use storage::index::index_file_name;
+
+
+  #[test]
+  fn position_for_offset_test() {
+    let dir = test_dir("segment-position");
+    let message = [0u8; 1000];
+    let mut segment = Segment::open(&dir, 50).unwrap();
+    for i in 0..20 {
+      segment.append(50 + i, &message, 1000 + i).unwrap();
+    }
+
+    assert_eq!(segment.position_for_offset(49), None);
+    assert_eq!(segment.position_for_offset(50), Some(0));
+    assert_eq!(segment.position_for_offset(57), Some(7 * 1012));
+    assert_eq!(segment.position_for_offset(69), Some(19 * 1012));
+    assert_eq!(segment.position_for_offset(70), None);
+    assert!(segment.index.entry_count() > 0);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn offset_for_timestamp_test() {
+    let dir = test_dir("segment-timestamp");
+    let message = [0u8; 1000];
+    let mut segment = Segment::open(&dir, 0).unwrap();
+    for i in 0..20 {
+      segment.append(i, &message, 1000 + 100 * i).unwrap();
+    }
+
+    assert_eq!(segment.max_timestamp(), 2900);
+    assert_eq!(segment.offset_for_timestamp(0), Some((-1, 0)));
+    assert_eq!(segment.offset_for_timestamp(3000), None);
+    let (_, offset) = segment.offset_for_timestamp(2000).unwrap();
+    assert!(offset <= 10);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn message_timestamps_test() {
+    let dir = test_dir("segment-message-timestamps");
+    let value = [0u8; 1000];
+    {
+      let mut segment = Segment::open(&dir, 0).unwrap();
+      for i in 0..20 {
+        let mut message: Vec<u8> = vec![];
+        ser_message(&Message { magic_byte: 1, attributes: 0, timestamp: Some(1000 + 100 * i), key: None, value: Some(&value) }, &mut message);
+        segment.append(i, &message, 1000 + 100 * i).unwrap();
+      }
+
+      assert_eq!(segment.offset_for_timestamp(2050), Some((2100, 11)));
+      assert_eq!(segment.offset_for_timestamp(0), Some((1000, 0)));
+      segment.flush().unwrap();
+    }
+
+    // the time index is rebuilt from the timestamps of the messages
+    fs::remove_file(dir.join(time_index_file_name(0))).unwrap();
+    let segment = Segment::open(&dir, 0).unwrap();
+    assert_eq!(segment.max_timestamp(), 2900);
+    assert_ne!(segment.time_index.last_entry(), segment.time_index.entry(0));
+    assert_eq!(segment.offset_for_timestamp(2050), Some((2100, 11)));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn zero_timestamp_test() {
+    let dir = test_dir("segment-zero-timestamp");
+    let value = [0u8; 1000];
+    {
+      let mut segment = Segment::open(&dir, 0).unwrap();
+      for i in 0..20 {
+        let timestamp = if i < 10 { 0 } else { 100 * i };
+        let mut message: Vec<u8> = vec![];
+        ser_message(&Message { magic_byte: 1, attributes: 0, timestamp: Some(timestamp), key: None, value: Some(&value) }, &mut message);
+        segment.append(i, &message, timestamp).unwrap();
+      }
+
+      assert_eq!(segment.time_index.entry(0), Some((0, 0)));
+      assert_eq!(segment.offset_for_timestamp(0), Some((0, 0)));
+      assert_eq!(segment.offset_for_timestamp(1), Some((1000, 10)));
+      segment.flush().unwrap();
+    }
+
+    // the first entry of the time index looks like its end, so it is rebuilt
+    let segment = Segment::open(&dir, 0).unwrap();
+    assert_eq!(segment.time_index.entry(0), Some((0, 0)));
+    assert!(segment.time_index.entry_count() > 1);
+    assert_eq!(segment.max_timestamp(), 1900);
+    assert_eq!(segment.offset_for_timestamp(1), Some((1000, 10)));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn rebuild_missing_index_test() {
+    let dir = test_dir("segment-rebuild");
+    let message = [0u8; 1000];
+    {
+      let mut segment = Segment::open(&dir, 0).unwrap();
+      for i in 0..20 {
+        segment.append(i, &message, 1000 + i).unwrap();
+      }
+      segment.flush().unwrap();
+    }
+
+    let expected = {
+      let segment = Segment::open(&dir, 0).unwrap();
+      (0..segment.index.entry_count()).map(|i| segment.index.entry(i)).collect::<Vec<_>>()
+    };
+    assert!(!expected.is_empty());
+
+    fs::remove_file(dir.join(index_file_name(0))).unwrap();
+    let segment = Segment::open(&dir, 0).unwrap();
+    let rebuilt = (0..segment.index.entry_count()).map(|i| segment.index.entry(i)).collect::<Vec<_>>();
+    assert_eq!(rebuilt, expected);
+    assert_eq!(segment.position_for_offset(15), Some(15 * 1012));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn recover_partial_entry_test() {
+    let dir = test_dir("segment-partial-entry");
+    {
+      let mut segment = Segment::open(&dir, 0).unwrap();
+      for i in 0..3 {
+        segment.append(i, &[1u8; 100], 1000).unwrap();
+      }
+      // a header whose size was not completely written
+      let size = segment.size();
+      segment.log.write(size, &[0, 0, 0, 0, 0, 0, 0, 3, 0x7f]).unwrap();
+      segment.flush().unwrap();
+    }
+
+    let segment = Segment::open(&dir, 0).unwrap();
+    assert_eq!(segment.next_offset(), 3);
+    assert_eq!(segment.size(), 3 * 112);
+    assert!(segment.log.read(segment.size(), segment.log.size() - segment.size()).unwrap().iter().all(|b| *b == 0));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn record_batch_offsets_test() {
+    let dir = test_dir("segment-batches");
+    // partition leader epoch, magic 2, crc, attributes, last offset delta = 4
+    let mut batch = vec![0u8; 60];
+    batch[4]  = 2;
+    batch[14] = 4;
+    {
+      let mut segment = Segment::open(&dir, 0).unwrap();
+      segment.append(0, &batch, 1000).unwrap();
+      segment.append(5, &[0u8; 20], 1000).unwrap();
+      assert_eq!(segment.next_offset(), 6);
+      assert_eq!(segment.position_for_offset(3), Some(0));
+      assert_eq!(segment.position_for_offset(5), Some(72));
+      segment.flush().unwrap();
+    }
+
+    let segment = Segment::open(&dir, 0).unwrap();
+    assert_eq!(segment.next_offset(), 6);
+    assert_eq!(segment.position_for_offset(4), Some(0));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
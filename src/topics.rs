@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+// longest topic name accepted by Kafka brokers
+pub const MAX_TOPIC_NAME_LENGTH: usize = 249;
+
+// topic names end up in partition directory names, so they are restricted
+// to the characters Kafka allows
+pub fn valid_topic_name(name: &str) -> bool {
+  !name.is_empty() && name.len() <= MAX_TOPIC_NAME_LENGTH && name != "." && name != ".." &&
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+// the broker as announced to clients in metadata responses
+#[derive(Debug,Clone,PartialEq)]
+pub struct Node {
+  pub id:   i32,
+  pub host: String,
+  pub port: i32,
+}
+
+// topics known to the broker, with the leader of each partition
+pub struct TopicRegistry {
+  // id of this broker, which leads every partition
+  leader: i32,
+  topics: HashMap<String, Vec<i32>>,
+}
+
+impl TopicRegistry {
+  pub fn new(leader: i32) -> TopicRegistry {
+    TopicRegistry {
+      leader,
+      topics: HashMap::new(),
+    }
+  }
+
+  // registers a partition led by this broker. Partitions are numbered from 0,
+  // so adding partition 2 also declares partitions 0 and 1
+  pub fn add_partition(&mut self, topic: &str, partition: i32) {
+    let leader = self.leader;
+    let partitions = self.topics.entry(topic.to_string()).or_default();
+    while partitions.len() <= partition as usize {
+      partitions.push(leader);
+    }
+  }
+
+  // leader of each partition of the topic, indexed by partition id
+  pub fn partitions(&self, topic: &str) -> Option<&[i32]> {
+    self.topics.get(topic).map(|p| &p[..])
+  }
+
+  pub fn topics(&self) -> Vec<String> {
+    let mut topics: Vec<String> = self.topics.keys().cloned().collect();
+    topics.sort();
+    topics
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn registry_test() {
+    let mut registry = TopicRegistry::new(1);
+    assert_eq!(registry.partitions("topic1"), None);
+
+    registry.add_partition("topic2", 0);
+    registry.add_partition("topic1", 1);
+    registry.add_partition("topic1", 0);
+    assert_eq!(registry.partitions("topic1"), Some(&[1, 1][..]));
+    assert_eq!(registry.partitions("topic2"), Some(&[1][..]));
+    assert_eq!(registry.topics(), vec!["topic1", "topic2"]);
+  }
+
+  #[test]
+  fn valid_topic_name_test() {
+    assert!(valid_topic_name("topic1"));
+    assert!(valid_topic_name("my.topic_name-2"));
+    assert!(!valid_topic_name(""));
+    assert!(!valid_topic_name(".."));
+    assert!(!valid_topic_name("../topic"));
+    assert!(!valid_topic_name("topic 1"));
+    assert!(!valid_topic_name(&"a".repeat(MAX_TOPIC_NAME_LENGTH + 1)));
+  }
+}
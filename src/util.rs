@@ -1,35 +1,24 @@
-use std::thread;
+use std::time::{SystemTime,UNIX_EPOCH};
 
-#[cfg(not(feature = "nightly"))]
-pub fn monitor<F, R>(f: F, restart_count: u8) -> Result<R, u8>
-where F: Fn() -> R + Send + 'static {
-
-  thread::spawn( move || {
-    f();
-  });
-  Err(0)
+// milliseconds since the UNIX epoch, the unit of Kafka timestamps
+pub fn timestamp_ms(time: SystemTime) -> i64 {
+  match time.duration_since(UNIX_EPOCH) {
+    Ok(d)  => (d.as_secs() * 1000) as i64 + d.subsec_nanos() as i64 / 1_000_000,
+    Err(_) => 0,
+  }
 }
 
-#[cfg(feature = "nightly")]
-pub fn monitor<F, R>(f: F, restart_count: u8) -> Result<R, u8>
-where F: Fn() -> R + Send + 'static {
-  use std::rt::unwind::try;
-
-  thread::spawn( move || {
-    let mut count = 0;
+pub fn now_ms() -> i64 {
+  timestamp_ms(SystemTime::now())
+}
 
-    while count <= restart_count {
-      unsafe {
-        let mut res: Option<R> = None;
+// creates an empty directory for tests that need to write files
+#[cfg(test)]
+pub fn test_dir(name: &str) -> ::std::path::PathBuf {
+  use std::{env,fs,process};
 
-        if let Err(e) = try(|| res = Some(f())) {
-          count = count + 1;
-          println!("restarting");
-        }
-      }
-    }
-    println!("failed too much");
-  });
-  Err(0)
+  let dir = env::temp_dir().join(format!("proust-test-{}-{}", name, process::id()));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).expect("create test directory");
+  dir
 }
-